@@ -0,0 +1,41 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use std::env;
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// sets videocore_software when calls are served by the in-memory compositor rather than the
+// firmware, which only exists on arm and aarch64 and is skipped with the software feature
+fn main() {
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rustc-check-cfg=cfg(videocore_software)");
+
+    if env::var_os("CARGO_FEATURE_SOFTWARE").is_some() || (arch != "arm" && arch != "aarch64") {
+        println!("cargo:rustc-cfg=videocore_software");
+    }
+}
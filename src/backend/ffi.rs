@@ -0,0 +1,300 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use libc::{ c_void,
+            int32_t,
+            uint8_t,
+            uint16_t,
+            uint32_t };
+
+// local
+use backend::Backend;
+
+use bcm_host::ffi as bcm_host_ffi;
+
+use dispmanx::{ CallbackFunc,
+                Clamp,
+                DisplayHandle,
+                ElementHandle,
+                Modeinfo,
+                Protection,
+                ResourceHandle,
+                Transform,
+                UpdateHandle,
+                VCAlpha };
+
+use dispmanx::ffi as dispmanx_ffi;
+
+use image::{ ImageType,
+             Rect };
+
+use vchi::MemHandle;
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// calls straight through to libbcm_host
+pub struct FfiBackend;
+
+// -------------------------------------------------------------------------------------------------
+// STATICS
+// -------------------------------------------------------------------------------------------------
+
+static BACKEND: FfiBackend = FfiBackend;
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl FfiBackend {
+    pub fn instance() -> &'static FfiBackend {
+        &BACKEND
+    }
+}
+
+impl Backend for FfiBackend {
+    fn display_close(&self, display: DisplayHandle) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_display_close(display)
+        }
+    }
+
+    fn display_get_info(&self, display: DisplayHandle, pinfo: *mut Modeinfo) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_display_get_info(display, pinfo)
+        }
+    }
+
+    fn display_open(&self, device: uint32_t) -> DisplayHandle {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_display_open(device)
+        }
+    }
+
+    fn display_open_mode(&self, device: uint32_t, mode: uint32_t) -> DisplayHandle {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_display_open_mode(device, mode)
+        }
+    }
+
+    fn display_open_offscreen(&self, dest: ResourceHandle,
+                              orientation: Transform) -> DisplayHandle {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_display_open_offscreen(dest, orientation)
+        }
+    }
+
+    fn display_reconfigure(&self, display: DisplayHandle, mode: uint32_t) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_display_reconfigure(display, mode)
+        }
+    }
+
+    fn display_set_background(&self, update: UpdateHandle, display: DisplayHandle, red: uint8_t,
+                              green: uint8_t, blue: uint8_t) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_display_set_background(update, display, red, green, blue)
+        }
+    }
+
+    fn display_set_destination(&self, display: DisplayHandle, dest: ResourceHandle) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_display_set_destination(display, dest)
+        }
+    }
+
+    fn element_add(&self, update: UpdateHandle, display: DisplayHandle, layer: int32_t,
+                   dest_rect: *mut Rect, src: ResourceHandle, src_rect: *mut Rect,
+                   protection: Protection, alpha: *mut VCAlpha, clamp: *mut Clamp,
+                   transform: Transform) -> ElementHandle {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_element_add(update, display, layer, dest_rect, src,
+                                                  src_rect, protection, alpha, clamp, transform)
+        }
+    }
+
+    fn element_change_attributes(&self, update: UpdateHandle, element: ElementHandle,
+                                 change_flags: uint32_t, layer: int32_t, opacity: uint8_t,
+                                 dest_rect: *const Rect, src_rect: *const Rect,
+                                 mask: ResourceHandle, transform: Transform) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_element_change_attributes(update, element, change_flags,
+                                                                layer, opacity, dest_rect,
+                                                                src_rect, mask, transform)
+        }
+    }
+
+    fn element_change_layer(&self, update: UpdateHandle, element: ElementHandle,
+                            layer: int32_t) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_element_change_layer(update, element, layer)
+        }
+    }
+
+    fn element_change_source(&self, update: UpdateHandle, element: ElementHandle,
+                             src: ResourceHandle) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_element_change_source(update, element, src)
+        }
+    }
+
+    fn element_modified(&self, update: UpdateHandle, element: ElementHandle,
+                        rect: *mut Rect) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_element_modified(update, element, rect)
+        }
+    }
+
+    fn element_remove(&self, update: UpdateHandle, element: ElementHandle) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_element_remove(update, element)
+        }
+    }
+
+    fn graphics_get_display_size(&self, display_number: uint16_t, width: &mut uint32_t,
+                                 height: &mut uint32_t) -> int32_t {
+        unsafe {
+            bcm_host_ffi::graphics_get_display_size(display_number, width, height)
+        }
+    }
+
+    fn host_deinit(&self) {
+        unsafe {
+            bcm_host_ffi::bcm_host_deinit()
+        }
+    }
+
+    fn host_get_peripheral_address(&self) -> uint32_t {
+        unsafe {
+            bcm_host_ffi::bcm_host_get_peripheral_address()
+        }
+    }
+
+    fn host_get_peripheral_size(&self) -> uint32_t {
+        unsafe {
+            bcm_host_ffi::bcm_host_get_peripheral_size()
+        }
+    }
+
+    fn host_get_sdram_address(&self) -> uint32_t {
+        unsafe {
+            bcm_host_ffi::bcm_host_get_sdram_address()
+        }
+    }
+
+    fn host_init(&self) {
+        unsafe {
+            bcm_host_ffi::bcm_host_init()
+        }
+    }
+
+    fn query_image_formats(&self, supported_formats: *mut uint32_t) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_query_image_formats(supported_formats)
+        }
+    }
+
+    fn resource_create(&self, type_: ImageType, width: uint32_t, height: uint32_t,
+                       native_image_handle: *mut uint32_t) -> ResourceHandle {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_resource_create(type_, width, height, native_image_handle)
+        }
+    }
+
+    fn resource_delete(&self, res: ResourceHandle) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_resource_delete(res)
+        }
+    }
+
+    fn resource_read_data(&self, handle: ResourceHandle, p_rect: *const Rect,
+                          dst_address: *mut c_void, dst_pitch: uint32_t) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_resource_read_data(handle, p_rect, dst_address, dst_pitch)
+        }
+    }
+
+    fn resource_set_palette(&self, handle: ResourceHandle, src_address: *mut c_void,
+                            offset: int32_t, size: int32_t) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_resource_set_palette(handle, src_address, offset, size)
+        }
+    }
+
+    fn resource_write_data(&self, res: ResourceHandle, src_type: ImageType, src_pitch: int32_t,
+                           src_address: *mut c_void, rect: *const Rect) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_resource_write_data(res, src_type, src_pitch, src_address,
+                                                          rect)
+        }
+    }
+
+    fn resource_write_data_handle(&self, res: ResourceHandle, src_type: ImageType,
+                                  src_pitch: int32_t, handle: MemHandle, offset: uint32_t,
+                                  rect: *const Rect) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_resource_write_data_handle(res, src_type, src_pitch, handle,
+                                                                 offset, rect)
+        }
+    }
+
+    fn snapshot(&self, display: DisplayHandle, snapshot_resource: ResourceHandle,
+                transform: Transform) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_snapshot(display, snapshot_resource, transform)
+        }
+    }
+
+    fn stop(&self) {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_stop()
+        }
+    }
+
+    fn update_start(&self, priority: int32_t) -> UpdateHandle {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_update_start(priority)
+        }
+    }
+
+    fn update_submit(&self, update: UpdateHandle, cb_func: CallbackFunc,
+                     cb_arg: *mut c_void) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_update_submit(update, cb_func, cb_arg)
+        }
+    }
+
+    fn update_submit_sync(&self, update: UpdateHandle) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_update_submit_sync(update)
+        }
+    }
+
+    fn vsync_callback(&self, display: DisplayHandle, cb_func: Option<CallbackFunc>,
+                      cb_arg: *mut c_void) -> int32_t {
+        unsafe {
+            dispmanx_ffi::vc_dispmanx_vsync_callback(display, cb_func, cb_arg)
+        }
+    }
+}
@@ -0,0 +1,171 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use libc::{ c_void,
+            int32_t,
+            uint8_t,
+            uint16_t,
+            uint32_t };
+
+// local
+use dispmanx::{ CallbackFunc,
+                Clamp,
+                DisplayHandle,
+                ElementHandle,
+                Modeinfo,
+                Protection,
+                ResourceHandle,
+                Transform,
+                UpdateHandle,
+                VCAlpha };
+
+use image::{ ImageType,
+             Rect };
+
+use vchi::MemHandle;
+
+// -------------------------------------------------------------------------------------------------
+// MODULES
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(not(videocore_software))]
+pub mod ffi;
+
+#[cfg(videocore_software)]
+pub mod software;
+
+// -------------------------------------------------------------------------------------------------
+// TYPES
+// -------------------------------------------------------------------------------------------------
+
+// the firmware is only reachable on the pi itself, everywhere else and with the software feature
+// enabled the calls are served by the in-memory compositor
+#[cfg(not(videocore_software))]
+pub type Active = ffi::FfiBackend;
+
+#[cfg(videocore_software)]
+pub type Active = software::SoftwareBackend;
+
+// -------------------------------------------------------------------------------------------------
+// TRAITS
+// -------------------------------------------------------------------------------------------------
+
+// the bcm_host and vc_dispmanx calls the safe wrappers are built on, mirroring the C signatures
+// and return codes so either implementation can sit behind them
+pub trait Backend: Sync {
+    fn display_close(&self, display: DisplayHandle) -> int32_t;
+
+    fn display_get_info(&self, display: DisplayHandle, pinfo: *mut Modeinfo) -> int32_t;
+
+    fn display_open(&self, device: uint32_t) -> DisplayHandle;
+
+    fn display_open_mode(&self, device: uint32_t, mode: uint32_t) -> DisplayHandle;
+
+    fn display_open_offscreen(&self, dest: ResourceHandle, orientation: Transform) -> DisplayHandle;
+
+    fn display_reconfigure(&self, display: DisplayHandle, mode: uint32_t) -> int32_t;
+
+    fn display_set_background(&self, update: UpdateHandle, display: DisplayHandle, red: uint8_t,
+                              green: uint8_t, blue: uint8_t) -> int32_t;
+
+    fn display_set_destination(&self, display: DisplayHandle, dest: ResourceHandle) -> int32_t;
+
+    #[allow(clippy::too_many_arguments)]
+    fn element_add(&self, update: UpdateHandle, display: DisplayHandle, layer: int32_t,
+                   dest_rect: *mut Rect, src: ResourceHandle, src_rect: *mut Rect,
+                   protection: Protection, alpha: *mut VCAlpha, clamp: *mut Clamp,
+                   transform: Transform) -> ElementHandle;
+
+    #[allow(clippy::too_many_arguments)]
+    fn element_change_attributes(&self, update: UpdateHandle, element: ElementHandle,
+                                 change_flags: uint32_t, layer: int32_t, opacity: uint8_t,
+                                 dest_rect: *const Rect, src_rect: *const Rect,
+                                 mask: ResourceHandle, transform: Transform) -> int32_t;
+
+    fn element_change_layer(&self, update: UpdateHandle, element: ElementHandle,
+                            layer: int32_t) -> int32_t;
+
+    fn element_change_source(&self, update: UpdateHandle, element: ElementHandle,
+                             src: ResourceHandle) -> int32_t;
+
+    fn element_modified(&self, update: UpdateHandle, element: ElementHandle,
+                        rect: *mut Rect) -> int32_t;
+
+    fn element_remove(&self, update: UpdateHandle, element: ElementHandle) -> int32_t;
+
+    fn graphics_get_display_size(&self, display_number: uint16_t, width: &mut uint32_t,
+                                 height: &mut uint32_t) -> int32_t;
+
+    fn host_deinit(&self);
+
+    fn host_get_peripheral_address(&self) -> uint32_t;
+
+    fn host_get_peripheral_size(&self) -> uint32_t;
+
+    fn host_get_sdram_address(&self) -> uint32_t;
+
+    fn host_init(&self);
+
+    fn query_image_formats(&self, supported_formats: *mut uint32_t) -> int32_t;
+
+    fn resource_create(&self, type_: ImageType, width: uint32_t, height: uint32_t,
+                       native_image_handle: *mut uint32_t) -> ResourceHandle;
+
+    fn resource_delete(&self, res: ResourceHandle) -> int32_t;
+
+    fn resource_read_data(&self, handle: ResourceHandle, p_rect: *const Rect,
+                          dst_address: *mut c_void, dst_pitch: uint32_t) -> int32_t;
+
+    fn resource_set_palette(&self, handle: ResourceHandle, src_address: *mut c_void,
+                            offset: int32_t, size: int32_t) -> int32_t;
+
+    fn resource_write_data(&self, res: ResourceHandle, src_type: ImageType, src_pitch: int32_t,
+                           src_address: *mut c_void, rect: *const Rect) -> int32_t;
+
+    fn resource_write_data_handle(&self, res: ResourceHandle, src_type: ImageType,
+                                  src_pitch: int32_t, handle: MemHandle, offset: uint32_t,
+                                  rect: *const Rect) -> int32_t;
+
+    fn snapshot(&self, display: DisplayHandle, snapshot_resource: ResourceHandle,
+                transform: Transform) -> int32_t;
+
+    fn stop(&self);
+
+    fn update_start(&self, priority: int32_t) -> UpdateHandle;
+
+    fn update_submit(&self, update: UpdateHandle, cb_func: CallbackFunc,
+                     cb_arg: *mut c_void) -> int32_t;
+
+    fn update_submit_sync(&self, update: UpdateHandle) -> int32_t;
+
+    fn vsync_callback(&self, display: DisplayHandle, cb_func: Option<CallbackFunc>,
+                      cb_arg: *mut c_void) -> int32_t;
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+pub fn get() -> &'static Active {
+    Active::instance()
+}
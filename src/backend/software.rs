@@ -0,0 +1,1318 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use std::collections::HashMap;
+use std::ptr;
+use std::slice;
+use std::sync::{ Mutex,
+                 MutexGuard,
+                 OnceLock };
+use std::thread;
+use std::time::Duration;
+
+use libc::{ c_void,
+            int32_t,
+            uint8_t,
+            uint16_t,
+            uint32_t };
+
+// local
+use backend::Backend;
+
+use convert::{ self,
+               Layout };
+
+use display::InputFormat;
+
+use dispmanx::{ AlphaSource,
+                CallbackFunc,
+                Clamp,
+                DisplayHandle,
+                ElementHandle,
+                FlagsAlpha,
+                Modeinfo,
+                Protection,
+                ResourceHandle,
+                Rotation,
+                Transform,
+                UpdateHandle,
+                VCAlpha,
+                DISPMANX_NO_HANDLE,
+                ELEMENT_CHANGE_DEST_RECT,
+                ELEMENT_CHANGE_LAYER,
+                ELEMENT_CHANGE_MASK_RESOURCE,
+                ELEMENT_CHANGE_OPACITY,
+                ELEMENT_CHANGE_SRC_RECT,
+                ELEMENT_CHANGE_TRANSFORM };
+
+use image::{ ImageType,
+             Rect };
+
+use tformat;
+
+use vchi::MemHandle;
+
+use yuv::{ self,
+           Colorspace };
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+// element changes queued by an update until it is submitted
+enum Op {
+    Add(ElementHandle, ElementState),
+    Attributes(ElementHandle, ElementChanges),
+    Background(DisplayHandle, [u8; 3]),
+    Layer(ElementHandle, int32_t),
+    Remove(ElementHandle),
+    Source(ElementHandle, ResourceHandle)
+}
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+struct DisplayState {
+    device:      Option<uint32_t>,
+    width:       uint32_t,
+    height:      uint32_t,
+    background:  [u8; 3],
+    destination: ResourceHandle
+}
+
+#[derive(Clone, Copy)]
+struct ElementChanges {
+    flags:     uint32_t,
+    layer:     int32_t,
+    opacity:   uint8_t,
+    dest:      Option<Rect>,
+    src:       Option<Rect>,
+    mask:      ResourceHandle,
+    transform: Transform
+}
+
+#[derive(Clone, Copy)]
+struct ElementState {
+    display:   DisplayHandle,
+    layer:     int32_t,
+    dest:      Option<Rect>,
+    src:       Option<Rect>,
+    source:    ResourceHandle,
+    flags:     FlagsAlpha,
+    opacity:   uint32_t,
+    mask:      ResourceHandle,
+    transform: Transform
+}
+
+struct ResourceState {
+    type_:   ImageType,
+    width:   uint32_t,
+    height:  uint32_t,
+    pitch:   usize,
+    data:    Vec<u8>,
+    palette: Vec<u16>
+}
+
+// in-memory stand-in for the HVS, displays are composited on demand from the elements added to
+// them, scaling with nearest neighbour sampling and blending according to each element's alpha
+// flags, clamping and protection are accepted but ignored
+pub struct SoftwareBackend {
+    state: Mutex<State>,
+    vsync: Mutex<Option<(CallbackFunc, usize)>>
+}
+
+struct State {
+    next_handle:    uint32_t,
+    display_sizes:  HashMap<uint32_t, (uint32_t, uint32_t)>,
+    displays:       HashMap<DisplayHandle, DisplayState>,
+    elements:       HashMap<ElementHandle, ElementState>,
+    resources:      HashMap<ResourceHandle, ResourceState>,
+    updates:        HashMap<UpdateHandle, Vec<Op>>,
+    vsync_interval: Option<Duration>,
+    vsync_thread:   bool
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+const DEFAULT_DISPLAY_SIZE: (uint32_t, uint32_t) = (1920, 1080);
+
+const DEFAULT_VSYNC_INTERVAL: Duration = Duration::from_micros(16_667);
+
+// -------------------------------------------------------------------------------------------------
+// STATICS
+// -------------------------------------------------------------------------------------------------
+
+static BACKEND: OnceLock<SoftwareBackend> = OnceLock::new();
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl ResourceState {
+    fn get(&self, x: uint32_t, y: uint32_t) -> [u8; 4] {
+        let bits = bits_per_pixel(self.type_).unwrap() as usize;
+        let row  = &self.data[y as usize * self.pitch..];
+
+        decode(self.type_, row, x as usize, bits, &self.palette)
+    }
+
+    fn set(&mut self, x: uint32_t, y: uint32_t, pixel: [u8; 4]) {
+        let row = &mut self.data[y as usize * self.pitch..];
+
+        encode(self.type_, row, x as usize, pixel);
+    }
+}
+
+impl SoftwareBackend {
+    pub fn instance() -> &'static SoftwareBackend {
+        BACKEND.get_or_init(|| {
+            SoftwareBackend {
+                state: Mutex::new(State {
+                                      next_handle:    1,
+                                      display_sizes:  HashMap::new(),
+                                      displays:       HashMap::new(),
+                                      elements:       HashMap::new(),
+                                      resources:      HashMap::new(),
+                                      updates:        HashMap::new(),
+                                      vsync_interval: Some(DEFAULT_VSYNC_INTERVAL),
+                                      vsync_thread:   false
+                                  }),
+                vsync: Mutex::new(None)
+            }
+        })
+    }
+
+    // RGBA8888 rows of the display as it currently looks
+    pub fn composite(&self, display: DisplayHandle) -> Option<Vec<u8>> {
+        let state = self.lock();
+
+        state.displays.get(&display).map(|_| composite(&state, display, Transform::NO_ROTATE))
+    }
+
+    // size reported for a device by graphics_get_display_size and used by displays opened on it
+    // afterwards, 1920x1080 unless set
+    pub fn set_display_size(&self, device: uint32_t, width: uint32_t, height: uint32_t) {
+        self.lock().display_sizes.insert(device, (width, height));
+    }
+
+    // None stops the automatic 60Hz vsyncs so they only happen when vsync() is called
+    pub fn set_vsync_interval(&self, interval: Option<Duration>) {
+        self.lock().vsync_interval = interval;
+    }
+
+    // calls the registered vsync callback, which is copied out first so the callback is free to
+    // unregister itself or register another
+    pub fn vsync(&self) {
+        let vsync = *self.vsync.lock().unwrap();
+
+        if let Some((callback, arg)) = vsync {
+            callback(DISPMANX_NO_HANDLE, arg as *mut c_void);
+        }
+    }
+
+    fn apply(&self, update: UpdateHandle) -> bool {
+        let mut state = self.lock();
+
+        let ops = match state.updates.remove(&update) {
+            Some(ops) => ops,
+            None      => return false
+        };
+
+        for op in ops {
+            match op {
+                Op::Add(handle, element) => {
+                    state.elements.insert(handle, element);
+                },
+
+                Op::Attributes(handle, changes) => {
+                    if let Some(element) = state.elements.get_mut(&handle) {
+                        apply_changes(element, &changes);
+                    }
+                },
+
+                Op::Background(display, background) => {
+                    if let Some(display) = state.displays.get_mut(&display) {
+                        display.background = background;
+                    }
+                },
+
+                Op::Layer(handle, layer) => {
+                    if let Some(element) = state.elements.get_mut(&handle) {
+                        element.layer = layer;
+                    }
+                },
+
+                Op::Remove(handle) => {
+                    state.elements.remove(&handle);
+                },
+
+                Op::Source(handle, source) => {
+                    if let Some(element) = state.elements.get_mut(&handle) {
+                        element.source = source;
+                    }
+                }
+            }
+        }
+
+        // offscreen displays render into their destination resource on every update
+        let offscreen: Vec<(DisplayHandle, ResourceHandle)> =
+            state.displays.iter()
+                          .filter(|&(_, display)| display.destination != DISPMANX_NO_HANDLE)
+                          .map(|(&handle, display)| (handle, display.destination))
+                          .collect();
+
+        for (display, destination) in offscreen {
+            snapshot(&mut state, display, destination, Transform::NO_ROTATE);
+        }
+
+        true
+    }
+
+    fn handle(state: &mut State) -> uint32_t {
+        let handle = state.next_handle;
+
+        state.next_handle += 1;
+
+        handle
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn queue(&self, update: UpdateHandle, op: Op) -> int32_t {
+        match self.lock().updates.get_mut(&update) {
+            Some(ops) => {
+                ops.push(op);
+                0
+            },
+
+            None => -1
+        }
+    }
+
+    fn start_vsync_thread(&self, state: &mut State) {
+        if state.vsync_thread {
+            return;
+        }
+
+        state.vsync_thread = true;
+
+        thread::spawn(move || {
+            let backend = SoftwareBackend::instance();
+
+            loop {
+                let interval = backend.lock().vsync_interval;
+
+                thread::sleep(interval.unwrap_or(DEFAULT_VSYNC_INTERVAL));
+
+                if interval.is_some() {
+                    backend.vsync();
+                }
+            }
+        });
+    }
+}
+
+// pointers are trusted the same way the raw dispmanx functions trust them
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+impl Backend for SoftwareBackend {
+    fn display_close(&self, display: DisplayHandle) -> int32_t {
+        match self.lock().displays.remove(&display) {
+            Some(_) => 0,
+            None    => -1
+        }
+    }
+
+    fn display_get_info(&self, display: DisplayHandle, pinfo: *mut Modeinfo) -> int32_t {
+        let state = self.lock();
+
+        match (state.displays.get(&display), pinfo.is_null()) {
+            (Some(display), false) => {
+                unsafe {
+                    ptr::write(pinfo, Modeinfo {
+                                          width:        display.width as int32_t,
+                                          height:       display.height as int32_t,
+                                          transform:    Transform::NO_ROTATE,
+                                          input_format: InputFormat::RGB888
+                                      });
+                }
+
+                0
+            },
+
+            _ => -1
+        }
+    }
+
+    fn display_open(&self, device: uint32_t) -> DisplayHandle {
+        let mut state = self.lock();
+
+        let (width, height) = *state.display_sizes.get(&device).unwrap_or(&DEFAULT_DISPLAY_SIZE);
+        let handle          = SoftwareBackend::handle(&mut state);
+
+        state.displays.insert(handle, DisplayState {
+                                          device: Some(device),
+                                          width,
+                                          height,
+                                          background: [0, 0, 0],
+                                          destination: DISPMANX_NO_HANDLE
+                                      });
+
+        handle
+    }
+
+    fn display_open_mode(&self, device: uint32_t, _: uint32_t) -> DisplayHandle {
+        self.display_open(device)
+    }
+
+    fn display_open_offscreen(&self, dest: ResourceHandle, _: Transform) -> DisplayHandle {
+        let mut state = self.lock();
+
+        let (width, height) = match state.resources.get(&dest) {
+            Some(resource) => (resource.width, resource.height),
+            None           => return DISPMANX_NO_HANDLE
+        };
+
+        let handle = SoftwareBackend::handle(&mut state);
+
+        state.displays.insert(handle, DisplayState {
+                                          device: None,
+                                          width,
+                                          height,
+                                          background: [0, 0, 0],
+                                          destination: dest
+                                      });
+
+        handle
+    }
+
+    fn display_reconfigure(&self, display: DisplayHandle, _: uint32_t) -> int32_t {
+        if self.lock().displays.contains_key(&display) {
+            0
+        } else {
+            -1
+        }
+    }
+
+    fn display_set_background(&self, update: UpdateHandle, display: DisplayHandle, red: uint8_t,
+                              green: uint8_t, blue: uint8_t) -> int32_t {
+        self.queue(update, Op::Background(display, [red, green, blue]))
+    }
+
+    fn display_set_destination(&self, display: DisplayHandle, dest: ResourceHandle) -> int32_t {
+        let mut state = self.lock();
+
+        if !state.resources.contains_key(&dest) {
+            return -1;
+        }
+
+        match state.displays.get_mut(&display) {
+            Some(display) => {
+                display.destination = dest;
+                0
+            },
+
+            None => -1
+        }
+    }
+
+    fn element_add(&self, update: UpdateHandle, display: DisplayHandle, layer: int32_t,
+                   dest_rect: *mut Rect, src: ResourceHandle, src_rect: *mut Rect,
+                   _: Protection, alpha: *mut VCAlpha, _: *mut Clamp,
+                   transform: Transform) -> ElementHandle {
+        let mut state = self.lock();
+
+        if !state.displays.contains_key(&display) || !state.updates.contains_key(&update) {
+            return DISPMANX_NO_HANDLE;
+        }
+
+        let (flags, opacity, mask) = if alpha.is_null() {
+            (FlagsAlpha::FROM_SOURCE, 255, DISPMANX_NO_HANDLE)
+        } else {
+            let alpha = unsafe {
+                &*alpha
+            };
+
+            (alpha.flags, alpha.opacity, alpha.mask)
+        };
+
+        let element = ElementState {
+            display,
+            layer,
+            dest: read_rect(dest_rect),
+            src: read_rect(src_rect),
+            source: src,
+            flags,
+            opacity,
+            mask,
+            transform
+        };
+
+        let handle = SoftwareBackend::handle(&mut state);
+
+        state.updates.get_mut(&update).unwrap().push(Op::Add(handle, element));
+
+        handle
+    }
+
+    fn element_change_attributes(&self, update: UpdateHandle, element: ElementHandle,
+                                 change_flags: uint32_t, layer: int32_t, opacity: uint8_t,
+                                 dest_rect: *const Rect, src_rect: *const Rect,
+                                 mask: ResourceHandle, transform: Transform) -> int32_t {
+        let changes = ElementChanges {
+            flags: change_flags,
+            layer,
+            opacity,
+            dest: read_rect(dest_rect),
+            src: read_rect(src_rect),
+            mask,
+            transform
+        };
+
+        self.queue(update, Op::Attributes(element, changes))
+    }
+
+    fn element_change_layer(&self, update: UpdateHandle, element: ElementHandle,
+                            layer: int32_t) -> int32_t {
+        self.queue(update, Op::Layer(element, layer))
+    }
+
+    fn element_change_source(&self, update: UpdateHandle, element: ElementHandle,
+                             src: ResourceHandle) -> int32_t {
+        self.queue(update, Op::Source(element, src))
+    }
+
+    // displays are composited on demand so there is nothing to invalidate
+    fn element_modified(&self, update: UpdateHandle, _: ElementHandle, _: *mut Rect) -> int32_t {
+        if self.lock().updates.contains_key(&update) {
+            0
+        } else {
+            -1
+        }
+    }
+
+    fn element_remove(&self, update: UpdateHandle, element: ElementHandle) -> int32_t {
+        self.queue(update, Op::Remove(element))
+    }
+
+    fn graphics_get_display_size(&self, display_number: uint16_t, width: &mut uint32_t,
+                                 height: &mut uint32_t) -> int32_t {
+        let state = self.lock();
+
+        let size = state.display_sizes.get(&(display_number as uint32_t))
+                                      .unwrap_or(&DEFAULT_DISPLAY_SIZE);
+
+        *width  = size.0;
+        *height = size.1;
+
+        0
+    }
+
+    fn host_deinit(&self) {
+    }
+
+    // the addresses of a pi 2 or 3
+    fn host_get_peripheral_address(&self) -> uint32_t {
+        0x3f00_0000
+    }
+
+    fn host_get_peripheral_size(&self) -> uint32_t {
+        0x0100_0000
+    }
+
+    fn host_get_sdram_address(&self) -> uint32_t {
+        0xc000_0000
+    }
+
+    fn host_init(&self) {
+    }
+
+    fn query_image_formats(&self, _: *mut uint32_t) -> int32_t {
+        -1
+    }
+
+    fn resource_create(&self, type_: ImageType, width: uint32_t, height: uint32_t,
+                       native_image_handle: *mut uint32_t) -> ResourceHandle {
+        // YUV420 is only kept for snapshots and reading back
+        if (bits_per_pixel(type_).is_none() && type_ != ImageType::YUV420) || width == 0
+           || height == 0 {
+            return DISPMANX_NO_HANDLE;
+        }
+
+        let size = type_.resource_rows(height).and_then(|rows| type_.buffer_size(width, rows));
+
+        let (pitch, size) = match (type_.required_pitch(width), size) {
+            (Some(pitch), Some(size)) => (pitch as usize, size),
+            _                         => return DISPMANX_NO_HANDLE
+        };
+
+        let mut state = self.lock();
+
+        let handle = SoftwareBackend::handle(&mut state);
+
+        state.resources.insert(handle, ResourceState {
+                                           type_,
+                                           width,
+                                           height,
+                                           pitch,
+                                           data: vec![0; size],
+                                           palette: vec![0; 256]
+                                       });
+
+        if !native_image_handle.is_null() {
+            unsafe {
+                ptr::write(native_image_handle, 0);
+            }
+        }
+
+        handle
+    }
+
+    fn resource_delete(&self, res: ResourceHandle) -> int32_t {
+        match self.lock().resources.remove(&res) {
+            Some(_) => 0,
+            None    => -1
+        }
+    }
+
+    // rows are addressed from dst_address + rect.y * dst_pitch, like the host library does
+    fn resource_read_data(&self, handle: ResourceHandle, p_rect: *const Rect,
+                          dst_address: *mut c_void, dst_pitch: uint32_t) -> int32_t {
+        let state = self.lock();
+
+        let (resource, rect) = match (state.resources.get(&handle), read_rect(p_rect)) {
+            (Some(resource), Some(rect)) => (resource, rect),
+            _                            => return -1
+        };
+
+        if dst_address.is_null() {
+            return -1;
+        }
+
+        let bits = match bits_per_pixel(resource.type_) {
+            Some(bits) => bits as usize,
+            None       => return read_planes(resource, &rect, dst_address as *mut u8, dst_pitch)
+        };
+
+        if !within(&rect, resource.width, resource.height) {
+            return -1;
+        }
+
+        for y in rect.y..rect.y + rect.height {
+            let row = unsafe {
+                host_row(dst_address as *mut u8, dst_pitch as isize, y, &rect, bits)
+            };
+
+            copy_row(&resource.data[y as usize * resource.pitch..], row, &rect, bits);
+        }
+
+        0
+    }
+
+    // offset and size are in bytes of RGB565 entries
+    fn resource_set_palette(&self, handle: ResourceHandle, src_address: *mut c_void,
+                            offset: int32_t, size: int32_t) -> int32_t {
+        let mut state = self.lock();
+
+        let resource = match state.resources.get_mut(&handle) {
+            Some(resource) => resource,
+            None           => return -1
+        };
+
+        if src_address.is_null() || offset < 0 || size < 0 || offset % 2 != 0 || size % 2 != 0
+           || (offset + size) / 2 > resource.palette.len() as int32_t {
+            return -1;
+        }
+
+        for i in 0..(size / 2) as usize {
+            resource.palette[offset as usize / 2 + i] = unsafe {
+                ptr::read_unaligned((src_address as *const u16).add(i))
+            };
+        }
+
+        0
+    }
+
+    // rows are addressed from src_address + rect.y * src_pitch, like the host library does
+    fn resource_write_data(&self, res: ResourceHandle, src_type: ImageType, src_pitch: int32_t,
+                           src_address: *mut c_void, rect: *const Rect) -> int32_t {
+        let mut state = self.lock();
+
+        let (resource, rect) = match (state.resources.get_mut(&res), read_rect(rect)) {
+            (Some(resource), Some(rect)) => (resource, rect),
+            _                            => return -1
+        };
+
+        let src_bits = match bits_per_pixel(src_type) {
+            Some(bits) => bits as usize,
+            None       => return -1
+        };
+
+        if src_address.is_null() || !within(&rect, resource.width, resource.height) {
+            return -1;
+        }
+
+        if resource.type_.is_tformat() {
+            return write_tiled(resource, src_type, src_pitch, src_address as *const u8, &rect);
+        }
+
+        let dst_bits = match bits_per_pixel(resource.type_) {
+            Some(bits) => bits as usize,
+            None       => return -1
+        };
+
+        for y in rect.y..rect.y + rect.height {
+            let row = unsafe {
+                host_row(src_address as *mut u8, src_pitch as isize, y, &rect, src_bits)
+            };
+
+            if src_type == resource.type_ {
+                copy_row(row, &mut resource.data[y as usize * resource.pitch..], &rect, dst_bits);
+                continue;
+            }
+
+            for x in rect.x..rect.x + rect.width {
+                let pixel = decode(src_type, row, x as usize, src_bits, &resource.palette);
+
+                resource.set(x as uint32_t, y as uint32_t, pixel);
+            }
+        }
+
+        0
+    }
+
+    // there is no videocore memory to take the data from
+    fn resource_write_data_handle(&self, _: ResourceHandle, _: ImageType, _: int32_t,
+                                  _: MemHandle, _: uint32_t, _: *const Rect) -> int32_t {
+        -1
+    }
+
+    fn snapshot(&self, display: DisplayHandle, snapshot_resource: ResourceHandle,
+                transform: Transform) -> int32_t {
+        let mut state = self.lock();
+
+        if !state.displays.contains_key(&display) || !transform.is_valid() {
+            return -1;
+        }
+
+        if snapshot(&mut state, display, snapshot_resource, transform) {
+            0
+        } else {
+            -1
+        }
+    }
+
+    fn stop(&self) {
+    }
+
+    fn update_start(&self, _: int32_t) -> UpdateHandle {
+        let mut state = self.lock();
+
+        let handle = SoftwareBackend::handle(&mut state);
+
+        state.updates.insert(handle, Vec::new());
+
+        handle
+    }
+
+    // the callback is made before returning rather than from a separate thread
+    fn update_submit(&self, update: UpdateHandle, cb_func: CallbackFunc,
+                     cb_arg: *mut c_void) -> int32_t {
+        if !self.apply(update) {
+            return -1;
+        }
+
+        cb_func(update, cb_arg);
+
+        0
+    }
+
+    fn update_submit_sync(&self, update: UpdateHandle) -> int32_t {
+        if self.apply(update) {
+            0
+        } else {
+            -1
+        }
+    }
+
+    fn vsync_callback(&self, display: DisplayHandle, cb_func: Option<CallbackFunc>,
+                      cb_arg: *mut c_void) -> int32_t {
+        let mut state = self.lock();
+
+        if !state.displays.contains_key(&display) {
+            return -1;
+        }
+
+        *self.vsync.lock().unwrap() = cb_func.map(|cb_func| (cb_func, cb_arg as usize));
+
+        if cb_func.is_some() {
+            self.start_vsync_thread(&mut state);
+        }
+
+        0
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+fn apply_changes(element: &mut ElementState, changes: &ElementChanges) {
+    if changes.flags & ELEMENT_CHANGE_LAYER != 0 {
+        element.layer = changes.layer;
+    }
+
+    if changes.flags & ELEMENT_CHANGE_OPACITY != 0 {
+        element.opacity = changes.opacity as uint32_t;
+    }
+
+    if changes.flags & ELEMENT_CHANGE_DEST_RECT != 0 && changes.dest.is_some() {
+        element.dest = changes.dest;
+    }
+
+    if changes.flags & ELEMENT_CHANGE_SRC_RECT != 0 && changes.src.is_some() {
+        element.src = changes.src;
+    }
+
+    if changes.flags & ELEMENT_CHANGE_MASK_RESOURCE != 0 {
+        element.mask = changes.mask;
+    }
+
+    if changes.flags & ELEMENT_CHANGE_TRANSFORM != 0 {
+        element.transform = changes.transform;
+    }
+}
+
+// formats the compositor can sample and render to
+fn bits_per_pixel(type_: ImageType) -> Option<uint32_t> {
+    match type_ {
+        ImageType::_4BPP | ImageType::_8BPP | ImageType::TF_PAL4 | ImageType::TF_PAL8 |
+        ImageType::RGB565 | ImageType::RGBA565 | ImageType::RGBA16 | ImageType::RGB888 |
+        ImageType::BGR888 | ImageType::RGBA32 | ImageType::RGBX32 | ImageType::RGBX8888 |
+        ImageType::BGRX8888 | ImageType::ARGB8888 | ImageType::XRGB8888 => type_.bits_per_pixel(),
+        _                                                                => None
+    }
+}
+
+fn blend(dst: &mut [u8], pixel: [u8; 4], alpha: u32, premultiplied: bool) {
+    for i in 0..3 {
+        let src = if premultiplied {
+            pixel[i] as u32 * 255
+        } else {
+            pixel[i] as u32 * alpha
+        };
+
+        dst[i] = ((src + dst[i] as u32 * (255 - alpha) + 127) / 255).min(255) as u8;
+    }
+}
+
+fn composite(state: &State, handle: DisplayHandle, transform: Transform) -> Vec<u8> {
+    let display = &state.displays[&handle];
+
+    let width  = display.width as usize;
+    let height = display.height as usize;
+
+    let mut out = Vec::with_capacity(width * height * 4);
+
+    for _ in 0..width * height {
+        out.extend_from_slice(&[display.background[0], display.background[1],
+                                display.background[2], 255]);
+    }
+
+    // every display opened on the same device shows the same elements
+    let mut elements: Vec<(&ElementHandle, &ElementState)> =
+        state.elements.iter()
+                      .filter(|&(_, element)| {
+                          element.display == handle
+                          || (display.device.is_some()
+                              && state.displays.get(&element.display)
+                                               .is_some_and(|other| other.device == display.device))
+                      })
+                      .collect();
+
+    elements.sort_by_key(|&(&handle, element)| (element.layer, handle));
+
+    for (_, element) in elements {
+        let resource = match state.resources.get(&element.source) {
+            Some(resource) if bits_per_pixel(resource.type_).is_some() => resource,
+            _                                                          => continue
+        };
+
+        // element images are all rgb, so a snapshot leaving rgb out shows only the background
+        if transform.contains(Transform::SNAPSHOT_NO_RGB) {
+            continue;
+        }
+
+        let dest = element.dest.unwrap_or(Rect {
+                                              x:      0,
+                                              y:      0,
+                                              width:  width as int32_t,
+                                              height: height as int32_t
+                                          });
+
+        let (src_x, src_y, src_width, src_height) = match element.src {
+            Some(src) => (src.x as f64 / 65536.0, src.y as f64 / 65536.0,
+                          src.width as f64 / 65536.0, src.height as f64 / 65536.0),
+            None      => (0.0, 0.0, resource.width as f64, resource.height as f64)
+        };
+
+        if dest.width <= 0 || dest.height <= 0 || src_width <= 0.0 || src_height <= 0.0 {
+            continue;
+        }
+
+        let mask = state.resources
+                        .get(&element.mask)
+                        .filter(|mask| bits_per_pixel(mask.type_).is_some());
+
+        let x_range = dest.x.max(0)..(dest.x + dest.width).min(width as int32_t);
+
+        for y in dest.y.max(0)..(dest.y + dest.height).min(height as int32_t) {
+            for x in x_range.clone() {
+                let u = ((x - dest.x) as f64 + 0.5) / dest.width as f64;
+                let v = ((y - dest.y) as f64 + 0.5) / dest.height as f64;
+
+                let (s, t) = orient(element.transform, u, v);
+
+                let sx = sample(src_x + s * src_width, resource.width);
+                let sy = sample(src_y + t * src_height, resource.height);
+
+                let pixel = resource.get(sx, sy);
+
+                let mut alpha = match element.flags.alpha_source() {
+                    AlphaSource::FromSource if element.flags.mix() => {
+                        pixel[3] as u32 * element.opacity.min(255) / 255
+                    },
+                    AlphaSource::FromSource      => pixel[3] as u32,
+                    AlphaSource::FixedAllPixels  => element.opacity.min(255),
+                    AlphaSource::FixedNonZero    => {
+                        if pixel[3] != 0 { element.opacity.min(255) } else { 0 }
+                    },
+                    AlphaSource::FixedExceed0x07 => {
+                        if pixel[3] > 7 { element.opacity.min(255) } else { 0 }
+                    }
+                };
+
+                if let Some(mask) = mask {
+                    let m = mask.get(sample(u * mask.width as f64, mask.width),
+                                     sample(v * mask.height as f64, mask.height));
+
+                    alpha = alpha * coverage(mask.type_, m) / 255;
+                }
+
+                let offset = (y as usize * width + x as usize) * 4;
+
+                blend(&mut out[offset..offset + 3], pixel, alpha, element.flags.premultiplied());
+            }
+        }
+    }
+
+    out
+}
+
+// raw pixels of a row, nibbles of 4 bit formats are copied one at a time
+fn copy_row(src: &[u8], dst: &mut [u8], rect: &Rect, bits: usize) {
+    let left  = rect.x as usize;
+    let right = (rect.x + rect.width) as usize;
+
+    if bits.is_multiple_of(8) {
+        let (start, end) = (left * bits / 8, right * bits / 8);
+
+        dst[start..end].copy_from_slice(&src[start..end]);
+        return;
+    }
+
+    for x in left..right {
+        let shift = if x.is_multiple_of(2) { 0 } else { 4 };
+
+        dst[x / 2] = dst[x / 2] & !(0x0f << shift) | src[x / 2] & (0x0f << shift);
+    }
+}
+
+// masks use their alpha channel, or their luma when they have none
+fn coverage(type_: ImageType, pixel: [u8; 4]) -> u32 {
+    if type_.has_alpha() {
+        pixel[3] as u32
+    } else {
+        (pixel[0] as u32 * 77 + pixel[1] as u32 * 150 + pixel[2] as u32 * 29) >> 8
+    }
+}
+
+// rgb formats are left to the convert module, palettised ones look up RGB565 entries
+fn decode(type_: ImageType, row: &[u8], x: usize, bits: usize, palette: &[u16]) -> [u8; 4] {
+    let index = match type_ {
+        ImageType::_4BPP | ImageType::TF_PAL4 => {
+            let byte = row[x * bits / 8];
+
+            if x.is_multiple_of(2) { byte & 0x0f } else { byte >> 4 }
+        },
+        ImageType::_8BPP | ImageType::TF_PAL8 => row[x],
+        _                                     => return convert::read_pixel(type_, row, x)
+    };
+
+    convert::read_pixel(ImageType::RGB565, &palette[index as usize].to_le_bytes(), 0)
+}
+
+// palettised resources are never rendered to so only rgb formats are written
+fn encode(type_: ImageType, row: &mut [u8], x: usize, pixel: [u8; 4]) {
+    if !type_.is_palettised() {
+        convert::write_pixel(type_, row, x, pixel);
+    }
+}
+
+// the bytes of a host row up to the right of the rect
+unsafe fn host_row<'a>(base: *mut u8, pitch: isize, y: int32_t, rect: &Rect,
+                       bits: usize) -> &'a mut [u8] {
+    let len = ((rect.x + rect.width) as usize * bits).div_ceil(8);
+
+    unsafe {
+        slice::from_raw_parts_mut(base.offset(pitch * y as isize), len)
+    }
+}
+
+// maps a position in the destination rect to one in the source, flips apply to the rotated image
+fn orient(transform: Transform, u: f64, v: f64) -> (f64, f64) {
+    let u = if transform.flip_horizontal() { 1.0 - u } else { u };
+    let v = if transform.flip_vertical() { 1.0 - v } else { v };
+
+    match transform.rotation() {
+        Rotation::Rotate0   => (u, v),
+        Rotation::Rotate90  => (v, 1.0 - u),
+        Rotation::Rotate180 => (1.0 - u, 1.0 - v),
+        Rotation::Rotate270 => (1.0 - v, u)
+    }
+}
+
+// planar resources are read as rows of the luma pitch running on through the padded luma rows and
+// the chroma planes
+fn read_planes(resource: &ResourceState, rect: &Rect, dst: *mut u8,
+               dst_pitch: uint32_t) -> int32_t {
+    let pitch = resource.pitch;
+    let rows  = (resource.data.len() / pitch) as uint32_t;
+
+    if rect.x != 0 || rect.width as uint32_t != resource.width
+       || !within(rect, resource.width, rows) || (dst_pitch as usize) < pitch {
+        return -1;
+    }
+
+    for y in rect.y as usize..(rect.y + rect.height) as usize {
+        unsafe {
+            ptr::copy_nonoverlapping(resource.data[y * pitch..].as_ptr(),
+                                     dst.add(y * dst_pitch as usize), pitch);
+        }
+    }
+
+    0
+}
+
+fn read_rect(rect: *const Rect) -> Option<Rect> {
+    if rect.is_null() {
+        None
+    } else {
+        Some(unsafe {
+                 ptr::read(rect)
+             })
+    }
+}
+
+fn sample(position: f64, size: uint32_t) -> uint32_t {
+    (position.floor().max(0.0) as uint32_t).min(size - 1)
+}
+
+// renders the display into the resource, scaling to the resource size
+fn snapshot(state: &mut State, display: DisplayHandle, resource: ResourceHandle,
+            transform: Transform) -> bool {
+    let (width, height) = {
+        let display = &state.displays[&display];
+
+        (display.width, display.height)
+    };
+
+    let image = composite(state, display, transform);
+
+    let resource = match state.resources.get_mut(&resource) {
+        Some(resource) => resource,
+        None           => return false
+    };
+
+    if resource.type_ == ImageType::_4BPP || resource.type_ == ImageType::_8BPP {
+        return false;
+    }
+
+    let planar = resource.type_ == ImageType::YUV420;
+
+    let mut rgba = Vec::new();
+
+    for y in 0..resource.height {
+        for x in 0..resource.width {
+            let u = (x as f64 + 0.5) / resource.width as f64;
+            let v = (y as f64 + 0.5) / resource.height as f64;
+
+            let (s, t) = orient(transform, u, v);
+
+            let offset = (sample(t * height as f64, height) * width
+                          + sample(s * width as f64, width)) as usize * 4;
+
+            let mut pixel = [image[offset], image[offset + 1], image[offset + 2], 255];
+
+            if transform.contains(Transform::SNAPSHOT_SWAP_RED_BLUE) {
+                pixel.swap(0, 2);
+            }
+
+            if planar {
+                rgba.extend_from_slice(&pixel);
+            } else {
+                resource.set(x, y, pixel);
+            }
+        }
+    }
+
+    if planar {
+        let row  = resource.width as usize * 4;
+        let rows = resource.type_.resource_rows(resource.height).unwrap();
+
+        // the padding rows repeat the last one so the chroma below it is not dragged to black
+        let last = rgba[rgba.len() - row..].to_vec();
+
+        for _ in resource.height..rows {
+            rgba.extend_from_slice(&last);
+        }
+
+        let src = Layout::new(ImageType::RGBA32, row);
+        let dst = Layout::new(resource.type_, resource.pitch);
+
+        return yuv::from_rgb(&rgba, src, &mut resource.data, dst, resource.width, rows,
+                             Colorspace::default()).is_ok();
+    }
+
+    true
+}
+
+fn within(rect: &Rect, width: uint32_t, height: uint32_t) -> bool {
+    rect.x >= 0 && rect.y >= 0 && rect.width > 0 && rect.height > 0
+    && rect.x as i64 + rect.width as i64 <= width as i64
+    && rect.y as i64 + rect.height as i64 <= height as i64
+}
+
+// t-format resources are kept detiled, their data arrives as one tiled block of the whole image
+// in the `pitch * height` bytes the firmware transfers
+fn write_tiled(resource: &mut ResourceState, src_type: ImageType, src_pitch: int32_t,
+               src_address: *const u8, rect: &Rect) -> int32_t {
+    let (width, height) = (resource.width, resource.height);
+
+    if src_type != resource.type_ || src_pitch <= 0 || rect.x != 0 || rect.y != 0
+       || rect.width as uint32_t != width || rect.height as uint32_t != height {
+        return -1;
+    }
+
+    let src = unsafe {
+        slice::from_raw_parts(src_address, src_pitch as usize * height as usize)
+    };
+
+    let decoded = tformat::tiling(resource.type_, width, height).map(|tiling| {
+        tformat::decode(src, &mut resource.data, resource.pitch, resource.type_, width, height,
+                        tiling)
+    });
+
+    match decoded {
+        Some(Ok(())) => 0,
+        _            => -1
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use dispmanx::{ self,
+                    Display,
+                    ElementBuilder,
+                    FlagsAlpha,
+                    Resource,
+                    Transform };
+
+    use image::{ ImageType,
+                 Rect };
+
+    use super::SoftwareBackend;
+
+    fn rect(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height
+        }
+    }
+
+    fn pixel(image: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * width + x) * 4;
+
+        [image[offset], image[offset + 1], image[offset + 2], image[offset + 3]]
+    }
+
+    #[test]
+    pub fn composite_alpha_pass() {
+        let backend = SoftwareBackend::instance();
+
+        backend.set_display_size(101, 2, 1);
+
+        let display  = Display::open(101).unwrap();
+        let resource = Resource::create(ImageType::RGB888, 1, 1).unwrap();
+
+        resource.write(&[255, 0, 0], 3, &rect(0, 0, 1, 1)).unwrap();
+
+        let mut update = dispmanx::update_start(0).unwrap();
+
+        display.set_background(&mut update, 0, 0, 200).unwrap();
+
+        update.element_add(&display,
+                           ElementBuilder::new().dest(rect(0, 0, 1, 1))
+                                                .source(&resource)
+                                                .alpha_mode(FlagsAlpha::FIXED_ALL_PIXELS)
+                                                .opacity(128))
+              .unwrap();
+
+        update.submit_sync().unwrap();
+
+        let image = backend.composite(display.as_raw()).unwrap();
+
+        assert_eq!(pixel(&image, 2, 0, 0), [128, 0, 100, 255]);
+        assert_eq!(pixel(&image, 2, 1, 0), [0, 0, 200, 255]);
+    }
+
+    #[test]
+    pub fn composite_layers_pass() {
+        let backend = SoftwareBackend::instance();
+
+        backend.set_display_size(102, 2, 2);
+
+        let display = Display::open(102).unwrap();
+        let red     = Resource::create(ImageType::RGBA32, 1, 1).unwrap();
+        let green   = Resource::create(ImageType::RGBA32, 1, 1).unwrap();
+
+        red.write(&[255, 0, 0, 255], 4, &rect(0, 0, 1, 1)).unwrap();
+        green.write(&[0, 255, 0, 255], 4, &rect(0, 0, 1, 1)).unwrap();
+
+        let mut update = dispmanx::update_start(0).unwrap();
+
+        update.element_add(&display,
+                           ElementBuilder::new().dest(rect(0, 0, 2, 2)).source(&green).layer(2))
+              .unwrap();
+
+        update.element_add(&display,
+                           ElementBuilder::new().dest(rect(0, 0, 1, 2)).source(&red).layer(1))
+              .unwrap();
+
+        update.submit_sync().unwrap();
+
+        let image = backend.composite(display.as_raw()).unwrap();
+
+        assert_eq!(pixel(&image, 2, 0, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 2, 0, 1), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    pub fn composite_scale_pass() {
+        let backend = SoftwareBackend::instance();
+
+        backend.set_display_size(100, 4, 4);
+
+        let display  = Display::open(100).unwrap();
+        let resource = Resource::create(ImageType::RGBA32, 2, 2).unwrap();
+
+        resource.write(&[255, 0, 0, 255, 0, 255, 0, 255,
+                         0, 0, 255, 255, 255, 255, 255, 255], 8, &rect(0, 0, 2, 2))
+                .unwrap();
+
+        let mut update = dispmanx::update_start(0).unwrap();
+
+        update.element_add(&display,
+                           ElementBuilder::new().dest(rect(0, 0, 4, 4)).source(&resource))
+              .unwrap();
+
+        update.submit_sync().unwrap();
+
+        let image = backend.composite(display.as_raw()).unwrap();
+
+        assert_eq!(pixel(&image, 4, 0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 4, 1, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(&image, 4, 2, 0), [0, 255, 0, 255]);
+        assert_eq!(pixel(&image, 4, 0, 3), [0, 0, 255, 255]);
+        assert_eq!(pixel(&image, 4, 3, 3), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    pub fn offscreen_pass() {
+        let target   = Resource::create(ImageType::RGB888, 2, 1).unwrap();
+        let resource = Resource::create(ImageType::RGB565, 1, 1).unwrap();
+
+        resource.write(&0xf800u16.to_le_bytes(), 2, &rect(0, 0, 1, 1)).unwrap();
+
+        let display = Display::open_offscreen(target.as_raw(), Transform::NO_ROTATE).unwrap();
+
+        let mut update = dispmanx::update_start(0).unwrap();
+
+        update.element_add(&display,
+                           ElementBuilder::new().dest(rect(1, 0, 1, 1)).source(&resource))
+              .unwrap();
+
+        update.submit_sync().unwrap();
+
+        let mut data = [1; 6];
+
+        target.read(&mut data, 6, &rect(0, 0, 2, 1)).unwrap();
+
+        assert_eq!(data, [0, 0, 0, 255, 0, 0]);
+    }
+
+    #[test]
+    pub fn snapshot_pass() {
+        let backend = SoftwareBackend::instance();
+
+        backend.set_display_size(103, 2, 2);
+
+        let display  = Display::open(103).unwrap();
+        let snapshot = Resource::create(ImageType::RGB888, 2, 2).unwrap();
+
+        let mut update = dispmanx::update_start(0).unwrap();
+
+        display.set_background(&mut update, 10, 20, 30).unwrap();
+
+        update.submit_sync().unwrap();
+
+        dispmanx::snapshot(display.as_raw(), snapshot.as_raw(),
+                           Transform::SNAPSHOT_SWAP_RED_BLUE).unwrap();
+
+        let mut data = [0; 12];
+
+        snapshot.read(&mut data, 6, &rect(0, 0, 2, 2)).unwrap();
+
+        assert_eq!(data, [30, 20, 10, 30, 20, 10, 30, 20, 10, 30, 20, 10]);
+    }
+}
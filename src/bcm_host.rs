@@ -25,6 +25,10 @@
 // system
 use libc::uint32_t;
 
+// local
+use backend::{ self,
+               Backend };
+
 // -------------------------------------------------------------------------------------------------
 // STRUCTS
 // -------------------------------------------------------------------------------------------------
@@ -39,49 +43,37 @@ pub struct GraphicsDisplaySize {
 // -------------------------------------------------------------------------------------------------
 
 pub fn deinit() {
-    unsafe {
-        ffi::bcm_host_deinit();
-    }
+    backend::get().host_deinit();
 }
 
 pub fn get_peripheral_address() -> uint32_t {
-    unsafe {
-        return ffi::bcm_host_get_peripheral_address();
-    }
+    backend::get().host_get_peripheral_address()
 }
 
 pub fn get_peripheral_size() -> uint32_t {
-    unsafe {
-        return ffi::bcm_host_get_peripheral_size();
-    }
+    backend::get().host_get_peripheral_size()
 }
 
 pub fn get_sdram_address() -> uint32_t {
-    unsafe {
-        return ffi::bcm_host_get_sdram_address();
-    }
+    backend::get().host_get_sdram_address()
 }
 
 pub fn graphics_get_display_size(display_number: u16) -> Option<GraphicsDisplaySize> {
-    unsafe {
-        let mut width:  uint32_t = 0;
-        let mut height: uint32_t = 0;
-
-        if ffi::graphics_get_display_size(display_number, &mut width, &mut height) == 0 {
-            Some(GraphicsDisplaySize {
-                     height: height,
-                     width:  width
-                 })
-        } else {
-            None
-        }
+    let mut width:  uint32_t = 0;
+    let mut height: uint32_t = 0;
+
+    if backend::get().graphics_get_display_size(display_number, &mut width, &mut height) == 0 {
+        Some(GraphicsDisplaySize {
+                 height,
+                 width
+             })
+    } else {
+        None
     }
 }
 
 pub fn init() {
-    unsafe {
-        ffi::bcm_host_init();
-    }
+    backend::get().host_init();
 }
 
 // -------------------------------------------------------------------------------------------------
@@ -140,7 +132,7 @@ mod test {
     #[test]
     pub fn graphics_test_pass() {
         super::init();
-        let x = super::graphics_get_display_size(0);
+        let x = super::graphics_get_display_size(0).unwrap();
         println!("Display size = {}x{}", x.width, x.height);
         super::deinit();
     }
@@ -0,0 +1,547 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use std::fs::File;
+use std::io::{ self,
+               BufWriter,
+               Write };
+use std::path::Path;
+
+use libc::uint32_t;
+
+// local
+use convert::{ self,
+               AlphaMode,
+               Layout };
+
+use dispmanx::{ self,
+                Display,
+                Error,
+                ErrorKind,
+                Resource,
+                Transform };
+
+use image::{ ImageType,
+             Rect };
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// tightly packed RGB888, or RGBA32 when captured through a format with alpha
+pub struct CapturedImage {
+    pub width:  uint32_t,
+    pub height: uint32_t,
+    pub type_:  ImageType,
+    pub data:   Vec<u8>
+}
+
+// the SNAPSHOT_* transform flags
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Options {
+    pub no_yuv:        bool,
+    pub no_rgb:        bool,
+    pub fill:          bool,
+    pub swap_red_blue: bool,
+    pub pack:          bool
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// largest stored deflate block
+const STORED_BLOCK: usize = 65535;
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl CapturedImage {
+    pub fn bytes_per_pixel(&self) -> usize {
+        if self.type_ == ImageType::RGBA32 { 4 } else { 3 }
+    }
+
+    pub fn pitch(&self) -> usize {
+        self.width as usize * self.bytes_per_pixel()
+    }
+
+    fn rows(&self) -> impl DoubleEndedIterator<Item = &[u8]> {
+        self.data.chunks(self.pitch().max(1)).take(self.height as usize)
+    }
+
+    // picks the writer from the extension, png, ppm or bmp
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path      = path.as_ref();
+        let extension = path.extension()
+                            .and_then(|extension| extension.to_str())
+                            .map(|extension| extension.to_ascii_lowercase())
+                            .unwrap_or_default();
+
+        if !matches!(extension.as_str(), "bmp" | "png" | "ppm") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown image extension"));
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        match extension.as_str() {
+            "bmp" => self.write_bmp(&mut writer)?,
+            "png" => self.write_png(&mut writer)?,
+            _     => self.write_ppm(&mut writer)?
+        }
+
+        writer.flush()
+    }
+
+    // 24 bit bottom-up rows, alpha is dropped
+    pub fn write_bmp<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let row    = (self.width as usize * 3).next_multiple_of(4);
+        let pixels = row * self.height as usize;
+
+        let mut header = Vec::with_capacity(54);
+
+        header.extend_from_slice(b"BM");
+        header.extend_from_slice(&(54 + pixels as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&54u32.to_le_bytes());
+        header.extend_from_slice(&40u32.to_le_bytes());
+        header.extend_from_slice(&(self.width as i32).to_le_bytes());
+        header.extend_from_slice(&(self.height as i32).to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&24u16.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&(pixels as u32).to_le_bytes());
+
+        // 72 dpi
+        header.extend_from_slice(&2835i32.to_le_bytes());
+        header.extend_from_slice(&2835i32.to_le_bytes());
+        header.extend_from_slice(&[0; 8]);
+
+        writer.write_all(&header)?;
+
+        let mut out = vec![0; row];
+
+        for src in self.rows().rev() {
+            for (dst, pixel) in out.chunks_mut(3).zip(src.chunks(self.bytes_per_pixel())) {
+                dst.copy_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+
+            writer.write_all(&out)?;
+        }
+
+        Ok(())
+    }
+
+    // 8 bit truecolour with alpha when the image has it, compressed with stored deflate blocks
+    // so no compressor is needed
+    pub fn write_png<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let colour_type = if self.type_ == ImageType::RGBA32 { 6 } else { 2 };
+
+        let mut header = Vec::with_capacity(13);
+
+        header.extend_from_slice(&self.width.to_be_bytes());
+        header.extend_from_slice(&self.height.to_be_bytes());
+        header.extend_from_slice(&[8, colour_type, 0, 0, 0]);
+
+        // each row starts with filter type 0
+        let mut raw = Vec::with_capacity((self.pitch() + 1) * self.height as usize);
+
+        for row in self.rows() {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+
+        let mut zlib = vec![0x78, 0x01];
+
+        for (i, block) in raw.chunks(STORED_BLOCK).enumerate() {
+            let last = (i + 1) * STORED_BLOCK >= raw.len();
+
+            zlib.push(last as u8);
+            zlib.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            zlib.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+
+        if raw.is_empty() {
+            zlib.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+        }
+
+        zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+        writer.write_all(&PNG_SIGNATURE)?;
+
+        write_chunk(writer, b"IHDR", &header)?;
+        write_chunk(writer, b"IDAT", &zlib)?;
+        write_chunk(writer, b"IEND", &[])
+    }
+
+    // binary P6, alpha is dropped
+    pub fn write_ppm<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+
+        for row in self.rows() {
+            if self.type_ == ImageType::RGBA32 {
+                let rgb: Vec<u8> = row.chunks(4).flat_map(|pixel| pixel[..3].to_vec()).collect();
+
+                writer.write_all(&rgb)?;
+            } else {
+                writer.write_all(row)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    pub fn transform(&self) -> Transform {
+        let flags = [(self.no_yuv, Transform::SNAPSHOT_NO_YUV),
+                     (self.no_rgb, Transform::SNAPSHOT_NO_RGB),
+                     (self.fill, Transform::SNAPSHOT_FILL),
+                     (self.swap_red_blue, Transform::SNAPSHOT_SWAP_RED_BLUE),
+                     (self.pack, Transform::SNAPSHOT_PACK)];
+
+        flags.iter()
+             .filter(|&&(set, _)| set)
+             .fold(Transform::NO_ROTATE, |transform, &(_, flag)| transform | flag)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+
+    // 5552 bytes is the most that can be summed before b has to be reduced
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+
+        a %= 65521;
+        b %= 65521;
+    }
+
+    b << 16 | a
+}
+
+pub fn capture_display(device: uint32_t, format: ImageType,
+                       region: Option<Rect>) -> Result<CapturedImage, Error> {
+    capture_display_with(device, format, region, Options::new())
+}
+
+// snapshots a display into a resource of `format`, then reads back the region, which defaults to
+// the whole display and is clipped to it
+pub fn capture_display_with(device: uint32_t, format: ImageType, region: Option<Rect>,
+                            options: Options) -> Result<CapturedImage, Error> {
+    let call = "vc_dispmanx_snapshot";
+
+    let bits = match format.bits_per_pixel() {
+        Some(bits) if convert::is_supported(format) => bits,
+        _                                           => {
+            return Err(Error::new(call, ErrorKind::UnsupportedFormat))
+        }
+    };
+
+    let display = Display::open(device)?;
+    let info    = display.get_info()?;
+
+    let (width, height) = (info.width.max(0) as uint32_t, info.height.max(0) as uint32_t);
+
+    let region = region.unwrap_or(Rect::from_size(width, height))
+                       .clip_to(width, height)
+                       .ok_or_else(|| Error::new(call, ErrorKind::InvalidRect))?;
+
+    let resource = Resource::create(format, width, height)?;
+
+    dispmanx::snapshot(display.as_raw(), resource.as_raw(), options.transform())?;
+
+    // rows are read at the resource's own pitch, then the region is cut out while converting
+    let pitch = format.required_pitch(width)
+                      .ok_or_else(|| Error::new(call, ErrorKind::UnsupportedFormat))?;
+
+    let mut raw = vec![0; pitch as usize * (region.y + region.height) as usize];
+
+    resource.read(&mut raw, pitch, &region)?;
+
+    let type_ = if format.has_alpha() { ImageType::RGBA32 } else { ImageType::RGB888 };
+    let bytes = if type_ == ImageType::RGBA32 { 4 } else { 3 };
+
+    let (region_width, region_height) = (region.width as uint32_t, region.height as uint32_t);
+
+    let mut data = vec![0; region_width as usize * region_height as usize * bytes];
+
+    let offset = region.y as usize * pitch as usize + region.x as usize * bits as usize / 8;
+
+    convert::convert(&raw[offset..], Layout::new(format, pitch as usize), &mut data,
+                     Layout::new(type_, region_width as usize * bytes), region_width,
+                     region_height, AlphaMode::Keep)
+            .map_err(|error| convert_error(call, error))?;
+
+    Ok(CapturedImage {
+           width: region_width,
+           height: region_height,
+           type_,
+           data
+       })
+}
+
+fn convert_error(call: &'static str, error: convert::Error) -> Error {
+    let kind = match error {
+        convert::Error::BufferTooSmall    => ErrorKind::BufferTooSmall,
+        convert::Error::InvalidPitch      => ErrorKind::InvalidPitch,
+        convert::Error::Unsupported(_, _) => ErrorKind::UnsupportedFormat
+    };
+
+    Error::new(call, kind)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc = kind.to_vec();
+
+    crc.extend_from_slice(data);
+
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32(&crc).to_be_bytes())
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use convert;
+
+    use dispmanx::{ ErrorKind,
+                    Transform };
+
+    use image::ImageType;
+
+    use super::{ adler32,
+                 convert_error,
+                 crc32,
+                 CapturedImage,
+                 Options };
+
+    fn image(type_: ImageType, width: u32, height: u32) -> CapturedImage {
+        let bytes = if type_ == ImageType::RGBA32 { 4 } else { 3 };
+
+        CapturedImage {
+            width,
+            height,
+            type_,
+            data: (0..width * height * bytes).map(|i| i as u8).collect()
+        }
+    }
+
+    #[test]
+    pub fn checksum_pass() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    pub fn error_kind_pass() {
+        let kind = |error| convert_error("vc_dispmanx_snapshot", error).kind();
+
+        assert_eq!(kind(convert::Error::BufferTooSmall), ErrorKind::BufferTooSmall);
+        assert_eq!(kind(convert::Error::InvalidPitch), ErrorKind::InvalidPitch);
+        assert_eq!(kind(convert::Error::Unsupported(ImageType::YUV420, ImageType::RGB888)),
+                   ErrorKind::UnsupportedFormat);
+    }
+
+    #[test]
+    pub fn options_pass() {
+        assert_eq!(Options::new().transform(), Transform::NO_ROTATE);
+
+        let options = Options {
+            no_yuv: true,
+            swap_red_blue: true,
+            ..Options::default()
+        };
+
+        assert_eq!(options.transform(),
+                   Transform::SNAPSHOT_NO_YUV | Transform::SNAPSHOT_SWAP_RED_BLUE);
+        assert!(options.transform().is_valid());
+    }
+
+    #[test]
+    pub fn write_bmp_pass() {
+        let mut out = Vec::new();
+
+        image(ImageType::RGB888, 2, 2).write_bmp(&mut out).unwrap();
+
+        // rows of 6 bytes are padded to 8
+        assert_eq!(out.len(), 54 + 16);
+        assert_eq!(&out[..2], b"BM");
+        assert_eq!(&out[2..6], &70u32.to_le_bytes());
+        assert_eq!(&out[10..14], &54u32.to_le_bytes());
+        assert_eq!(&out[18..26], &[2, 0, 0, 0, 2, 0, 0, 0]);
+        assert_eq!(&out[28..30], &24u16.to_le_bytes());
+
+        // bottom-up and BGR
+        assert_eq!(&out[54..], &[8, 7, 6, 11, 10, 9, 0, 0, 2, 1, 0, 5, 4, 3, 0, 0]);
+
+        out.clear();
+
+        image(ImageType::RGBA32, 1, 1).write_bmp(&mut out).unwrap();
+
+        assert_eq!(&out[54..], &[2, 1, 0, 0]);
+    }
+
+    #[test]
+    pub fn write_png_pass() {
+        let mut out = Vec::new();
+
+        image(ImageType::RGBA32, 2, 1).write_png(&mut out).unwrap();
+
+        assert_eq!(&out[..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+
+        // IHDR
+        assert_eq!(&out[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(&out[16..29], &[0, 0, 0, 2, 0, 0, 0, 1, 8, 6, 0, 0, 0]);
+        assert_eq!(&out[29..33], &crc32(&out[12..29]).to_be_bytes());
+
+        // IDAT holds the zlib header, one final stored block and the adler32 of the rows
+        let raw = [0, 0, 1, 2, 3, 4, 5, 6, 7];
+
+        assert_eq!(&out[33..41], &[0, 0, 0, 2 + 5 + 9 + 4, b'I', b'D', b'A', b'T']);
+        assert_eq!(&out[41..48], &[0x78, 0x01, 1, 9, 0, 0xf6, 0xff]);
+        assert_eq!(&out[48..57], &raw);
+        assert_eq!(&out[57..61], &adler32(&raw).to_be_bytes());
+        assert_eq!(&out[61..65], &crc32(&out[37..61]).to_be_bytes());
+
+        assert_eq!(&out[65..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+
+        // large images are split across several stored blocks
+        out.clear();
+
+        image(ImageType::RGB888, 200, 200).write_png(&mut out).unwrap();
+
+        let raw_len: usize = 200 * (200 * 3 + 1);
+        let blocks  = raw_len.div_ceil(65535);
+
+        assert_eq!(out[25], 2);
+        assert_eq!(out.len(), 8 + 25 + 12 + 2 + blocks * 5 + raw_len + 4 + 12);
+        assert_eq!(out[43], 0);
+    }
+
+    #[test]
+    pub fn write_ppm_pass() {
+        let mut out = Vec::new();
+
+        image(ImageType::RGB888, 2, 1).write_ppm(&mut out).unwrap();
+
+        assert_eq!(out, b"P6\n2 1\n255\n\x00\x01\x02\x03\x04\x05");
+
+        out.clear();
+
+        image(ImageType::RGBA32, 2, 1).write_ppm(&mut out).unwrap();
+
+        assert_eq!(out, b"P6\n2 1\n255\n\x00\x01\x02\x04\x05\x06");
+    }
+
+    #[cfg(videocore_software)]
+    mod software {
+        use backend::software::SoftwareBackend;
+
+        use capture::capture_display;
+
+        use dispmanx::{ update_start,
+                        Display,
+                        ElementBuilder,
+                        ErrorKind,
+                        Resource };
+
+        use image::{ ImageType,
+                     Rect };
+
+        #[test]
+        pub fn capture_pass() {
+            let backend = SoftwareBackend::instance();
+
+            backend.set_display_size(106, 3, 2);
+
+            let display  = Display::open(106).unwrap();
+            let resource = Resource::create(ImageType::RGB888, 2, 1).unwrap();
+
+            resource.write(&[0, 0, 255, 255, 0, 0], 6, &Rect::new(0, 0, 2, 1)).unwrap();
+
+            let mut update = update_start(0).unwrap();
+
+            update.element_add(&display,
+                               ElementBuilder::new().dest(Rect::new(1, 1, 2, 1)).source(&resource))
+                  .unwrap();
+
+            update.submit_sync().unwrap();
+
+            let image = capture_display(106, ImageType::RGBA32, None).unwrap();
+
+            assert_eq!((image.width, image.height, image.type_), (3, 2, ImageType::RGBA32));
+            assert_eq!(&image.data[..4], &[0, 0, 0, 255]);
+            assert_eq!(&image.data[16..], &[0, 0, 255, 255, 255, 0, 0, 255]);
+
+            let image = capture_display(106, ImageType::RGB565, Some(Rect::new(1, 1, 4, 4)))
+                            .unwrap();
+
+            assert_eq!((image.width, image.height, image.type_), (2, 1, ImageType::RGB888));
+            assert_eq!(image.data, [0, 0, 255, 255, 0, 0]);
+
+            assert_eq!(capture_display(106, ImageType::RGB888, Some(Rect::new(3, 0, 1, 1)))
+                           .err()
+                           .map(|error| error.kind()),
+                       Some(ErrorKind::InvalidRect));
+            assert_eq!(capture_display(106, ImageType::YUV420, None)
+                           .err()
+                           .map(|error| error.kind()),
+                       Some(ErrorKind::UnsupportedFormat));
+        }
+    }
+}
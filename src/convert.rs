@@ -0,0 +1,347 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use std::{ error,
+           fmt };
+
+use libc::uint32_t;
+
+// local
+use image::ImageType;
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+// what to do with the colour channels of pixels that carry alpha
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Keep,
+    Premultiply,
+    Unpremultiply
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    BufferTooSmall,
+    InvalidPitch,
+    Unsupported(ImageType, ImageType)
+}
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// format of a buffer along with the bytes between the starts of its rows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    pub type_: ImageType,
+    pub pitch: usize
+}
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl error::Error for Error {
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BufferTooSmall        => write!(f, "buffer too small"),
+            Error::InvalidPitch          => write!(f, "invalid pitch"),
+            Error::Unsupported(src, dst) => write!(f, "cannot convert {:?} to {:?}", src, dst)
+        }
+    }
+}
+
+impl Layout {
+    pub fn new(type_: ImageType, pitch: usize) -> Layout {
+        Layout {
+            type_,
+            pitch
+        }
+    }
+
+    // validates the pitch and size of a buffer holding width x height pixels, the last row
+    // does not need to be padded out to the pitch
+    pub(crate) fn check(&self, len: usize, width: uint32_t,
+                        height: uint32_t) -> Result<usize, Error> {
+        let row = row_bytes(self.type_, width);
+
+        if self.pitch < row {
+            return Err(Error::InvalidPitch);
+        }
+
+        if len < self.pitch * (height as usize - 1) + row {
+            return Err(Error::BufferTooSmall);
+        }
+
+        Ok(row)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// converts width x height pixels from src to dst, channels are scaled to and from 8 bits and
+// alpha is 255 when the source has none
+pub fn convert(src: &[u8], src_layout: Layout, dst: &mut [u8], dst_layout: Layout,
+               width: uint32_t, height: uint32_t, alpha: AlphaMode) -> Result<(), Error> {
+    if !is_supported(src_layout.type_) || !is_supported(dst_layout.type_) {
+        return Err(Error::Unsupported(src_layout.type_, dst_layout.type_));
+    }
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let row = src_layout.check(src.len(), width, height)?;
+
+    dst_layout.check(dst.len(), width, height)?;
+
+    for y in 0..height as usize {
+        let src_row = &src[y * src_layout.pitch..];
+        let dst_row = &mut dst[y * dst_layout.pitch..];
+
+        if src_layout.type_ == dst_layout.type_ && alpha == AlphaMode::Keep {
+            dst_row[..row].copy_from_slice(&src_row[..row]);
+            continue;
+        }
+
+        for x in 0..width as usize {
+            let mut pixel = read_pixel(src_layout.type_, src_row, x);
+
+            match alpha {
+                AlphaMode::Keep          => (),
+                AlphaMode::Premultiply   => premultiply(&mut pixel),
+                AlphaMode::Unpremultiply => unpremultiply(&mut pixel)
+            }
+
+            write_pixel(dst_layout.type_, dst_row, x, pixel);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn is_supported(type_: ImageType) -> bool {
+    matches!(type_, ImageType::RGB565 | ImageType::RGBA565 | ImageType::RGBA16
+                    | ImageType::RGB888 | ImageType::BGR888 | ImageType::BGR888_NP
+                    | ImageType::RGBA32 | ImageType::RGBX32 | ImageType::RGBX8888
+                    | ImageType::BGRX8888 | ImageType::ARGB8888 | ImageType::XRGB8888)
+}
+
+pub fn premultiply(pixel: &mut [u8; 4]) {
+    let alpha = pixel[3] as u32;
+
+    for channel in &mut pixel[..3] {
+        *channel = ((*channel as u32 * alpha + 127) / 255) as u8;
+    }
+}
+
+// RGBA8888 value of pixel x in a row of a supported format, RGBA565 is read as RGB565
+pub(crate) fn read_pixel(type_: ImageType, row: &[u8], x: usize) -> [u8; 4] {
+    match type_ {
+        ImageType::RGB565 | ImageType::RGBA565 => {
+            let value = u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]);
+
+            let r = (value >> 11) as u8;
+            let g = (value >> 5 & 0x3f) as u8;
+            let b = (value & 0x1f) as u8;
+
+            [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 255]
+        },
+        ImageType::RGBA16 => {
+            let value = u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]);
+
+            [(value >> 12) as u8 * 17, (value >> 8 & 0xf) as u8 * 17,
+             (value >> 4 & 0xf) as u8 * 17, (value & 0xf) as u8 * 17]
+        },
+        ImageType::RGB888 => {
+            let p = &row[x * 3..];
+
+            [p[0], p[1], p[2], 255]
+        },
+        ImageType::BGR888 | ImageType::BGR888_NP => {
+            let p = &row[x * 3..];
+
+            [p[2], p[1], p[0], 255]
+        },
+        _ => {
+            let p = &row[x * 4..];
+
+            match type_ {
+                ImageType::RGBA32                         => [p[0], p[1], p[2], p[3]],
+                ImageType::ARGB8888                       => [p[2], p[1], p[0], p[3]],
+                ImageType::BGRX8888 | ImageType::XRGB8888 => [p[2], p[1], p[0], 255],
+                _                                         => [p[0], p[1], p[2], 255]
+            }
+        }
+    }
+}
+
+// bytes taken by width pixels of a supported format
+fn row_bytes(type_: ImageType, width: uint32_t) -> usize {
+    type_.bits_per_pixel().map_or(0, |bits| (width as usize * bits as usize).div_ceil(8))
+}
+
+pub fn unpremultiply(pixel: &mut [u8; 4]) {
+    let alpha = pixel[3] as u32;
+
+    for channel in &mut pixel[..3] {
+        *channel = (*channel as u32 * 255 + alpha / 2).checked_div(alpha)
+                                                      .map_or(0, |value| value.min(255) as u8);
+    }
+}
+
+// stores an RGBA8888 value as pixel x in a row of a supported format, formats without alpha drop
+// it and RGBA565 is written as RGB565
+pub(crate) fn write_pixel(type_: ImageType, row: &mut [u8], x: usize, pixel: [u8; 4]) {
+    let [r, g, b, a] = pixel;
+
+    match type_ {
+        ImageType::RGB565 | ImageType::RGBA565 => {
+            let value = (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3;
+
+            row[x * 2..x * 2 + 2].copy_from_slice(&value.to_le_bytes());
+        },
+        ImageType::RGBA16 => {
+            let value = (r as u16 >> 4) << 12 | (g as u16 >> 4) << 8 | (b as u16 >> 4) << 4
+                        | a as u16 >> 4;
+
+            row[x * 2..x * 2 + 2].copy_from_slice(&value.to_le_bytes());
+        },
+        ImageType::RGB888 => {
+            row[x * 3..x * 3 + 3].copy_from_slice(&[r, g, b]);
+        },
+        ImageType::BGR888 | ImageType::BGR888_NP => {
+            row[x * 3..x * 3 + 3].copy_from_slice(&[b, g, r]);
+        },
+        _ => {
+            let p = match type_ {
+                ImageType::RGBA32                         => [r, g, b, a],
+                ImageType::ARGB8888                       => [b, g, r, a],
+                ImageType::BGRX8888 | ImageType::XRGB8888 => [b, g, r, 255],
+                _                                         => [r, g, b, 255]
+            };
+
+            row[x * 4..x * 4 + 4].copy_from_slice(&p);
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use image::ImageType;
+
+    use super::{ convert,
+                 AlphaMode,
+                 Error,
+                 Layout };
+
+    #[test]
+    pub fn convert_alpha_pass() {
+        let src     = [200, 100, 50, 128];
+        let mut dst = [0; 4];
+
+        convert(&src, Layout::new(ImageType::RGBA32, 4), &mut dst,
+                Layout::new(ImageType::RGBA32, 4), 1, 1, AlphaMode::Premultiply).unwrap();
+
+        assert_eq!(dst, [100, 50, 25, 128]);
+
+        let mut back = [0; 4];
+
+        convert(&dst, Layout::new(ImageType::RGBA32, 4), &mut back,
+                Layout::new(ImageType::RGBA32, 4), 1, 1, AlphaMode::Unpremultiply).unwrap();
+
+        assert_eq!(back, [199, 100, 50, 128]);
+    }
+
+    #[test]
+    pub fn convert_error_pass() {
+        let mut dst = [0; 64];
+
+        assert_eq!(convert(&[0; 64], Layout::new(ImageType::YUV420, 8), &mut dst,
+                           Layout::new(ImageType::RGB565, 16), 4, 4, AlphaMode::Keep),
+                   Err(Error::Unsupported(ImageType::YUV420, ImageType::RGB565)));
+
+        assert_eq!(convert(&[0; 64], Layout::new(ImageType::RGB888, 8), &mut dst,
+                           Layout::new(ImageType::RGB565, 8), 4, 4, AlphaMode::Keep),
+                   Err(Error::InvalidPitch));
+
+        assert_eq!(convert(&[0; 44], Layout::new(ImageType::RGB888, 12), &mut dst,
+                           Layout::new(ImageType::RGB565, 8), 4, 4, AlphaMode::Keep),
+                   Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    pub fn convert_pitch_pass() {
+        // two rows of two RGB888 pixels padded to 8 bytes, the last row unpadded
+        let src     = [255, 0, 0, 0, 255, 0, 9, 9, 0, 0, 255, 255, 255, 255];
+        let mut dst = [0; 8];
+
+        convert(&src, Layout::new(ImageType::RGB888, 8), &mut dst,
+                Layout::new(ImageType::RGB565, 4), 2, 2, AlphaMode::Keep).unwrap();
+
+        assert_eq!(dst, [0x00, 0xf8, 0xe0, 0x07, 0x1f, 0x00, 0xff, 0xff]);
+    }
+
+    #[test]
+    pub fn convert_round_trip_pass() {
+        let src = [1, 2, 3, 4, 5, 6, 7, 8];
+
+        for &type_ in &[ImageType::ARGB8888, ImageType::RGBA32] {
+            let mut mid = [0; 8];
+            let mut dst = [0; 8];
+
+            convert(&src, Layout::new(ImageType::RGBA32, 8), &mut mid, Layout::new(type_, 8),
+                    2, 1, AlphaMode::Keep).unwrap();
+
+            convert(&mid, Layout::new(type_, 8), &mut dst, Layout::new(ImageType::RGBA32, 8),
+                    2, 1, AlphaMode::Keep).unwrap();
+
+            assert_eq!(dst, src);
+        }
+
+        let mut bgr = [0; 6];
+
+        convert(&src, Layout::new(ImageType::RGBA32, 8), &mut bgr,
+                Layout::new(ImageType::BGR888, 6), 2, 1, AlphaMode::Keep).unwrap();
+
+        assert_eq!(bgr, [3, 2, 1, 7, 6, 5]);
+
+        let mut rgba16 = [0; 4];
+
+        convert(&[0xff, 0x88, 0x00, 0x44], Layout::new(ImageType::RGBA32, 4), &mut rgba16,
+                Layout::new(ImageType::RGBA16, 2), 1, 1, AlphaMode::Keep).unwrap();
+
+        assert_eq!(rgba16[..2], [0x04, 0xf8]);
+    }
+}
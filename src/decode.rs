@@ -0,0 +1,1495 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use std::{ error,
+           fmt,
+           io };
+
+use std::io::Read;
+
+use libc::uint32_t;
+
+// local
+use convert::{ self,
+               AlphaMode,
+               Layout };
+
+use dispmanx;
+
+use image::ImageType;
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum Error {
+    Convert(convert::Error),
+    Dispmanx(dispmanx::Error),
+    InvalidData,
+    Io(io::Error),
+    Unsupported
+}
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// least significant bit first reader over a deflate stream
+struct BitReader<'a> {
+    data:     &'a [u8],
+    position: usize,
+    buffer:   u32,
+    count:    u32
+}
+
+// pixels laid out for resource_write_data, rows are padded to the required pitch
+#[derive(Clone, Debug, PartialEq)]
+pub struct Decoded {
+    pub width:  uint32_t,
+    pub height: uint32_t,
+    pub type_:  ImageType,
+    pub pitch:  uint32_t,
+    pub data:   Vec<u8>
+}
+
+// canonical huffman code stored as the number of codes of each length and the symbols in code
+// order
+struct Huffman {
+    counts:  [u16; 16],
+    symbols: Vec<u16>
+}
+
+// images larger than the maximum size are scaled down to fit, keeping their aspect ratio, and
+// opaque images use RGB565 rather than RGB888 when rgb565 is set
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Options {
+    pub max_width:  Option<uint32_t>,
+    pub max_height: Option<uint32_t>,
+    pub rgb565:     bool
+}
+
+// decoded RGBA pixels
+struct Pixels {
+    width:  usize,
+    height: usize,
+    data:   Vec<u8>
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+// x, y, x step and y step of each interlaced pass
+const ADAM7: [(usize, usize, usize, usize); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8),
+                                                  (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2),
+                                                  (0, 1, 1, 2)];
+
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2,
+                                        14, 1, 15];
+
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257,
+                                  385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289,
+                                  16385, 24577];
+
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9,
+                                  9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43,
+                                51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4,
+                                4, 4, 5, 5, 5, 5, 0];
+
+// 64 megapixels, keeps a corrupt header from asking for more than 256MB of rgba
+const MAX_PIXELS: usize = 1 << 26;
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            data,
+            position: 0,
+            buffer:   0,
+            count:    0
+        }
+    }
+
+    // drops the rest of the current byte
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count  = 0;
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, Error> {
+        while self.count < count {
+            let byte = *self.data.get(self.position).ok_or(Error::InvalidData)?;
+
+            self.position += 1;
+            self.buffer   |= (byte as u32) << self.count;
+            self.count    += 8;
+        }
+
+        let value = self.buffer & ((1 << count) - 1);
+
+        self.buffer >>= count;
+        self.count   -= count;
+
+        Ok(value)
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.data
+                        .get(self.position..self.position + count)
+                        .ok_or(Error::InvalidData)?;
+
+        self.position += count;
+
+        Ok(bytes)
+    }
+}
+
+impl error::Error for Error {
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Convert(ref error)  => write!(f, "{}", error),
+            Error::Dispmanx(ref error) => write!(f, "{}", error),
+            Error::InvalidData         => write!(f, "invalid image data"),
+            Error::Io(ref error)       => write!(f, "{}", error),
+            Error::Unsupported         => write!(f, "unsupported image format")
+        }
+    }
+}
+
+impl From<convert::Error> for Error {
+    fn from(error: convert::Error) -> Error {
+        Error::Convert(error)
+    }
+}
+
+impl From<dispmanx::Error> for Error {
+    fn from(error: dispmanx::Error) -> Error {
+        Error::Dispmanx(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl Huffman {
+    // incomplete codes are allowed, deflate uses them for single distance codes
+    fn new(lengths: &[u8]) -> Result<Huffman, Error> {
+        let mut counts = [0u16; 16];
+
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+
+        let mut left = 1i32;
+
+        for &count in &counts[1..] {
+            left = (left << 1) - count as i32;
+
+            if left < 0 {
+                return Err(Error::InvalidData);
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0; lengths.len()];
+
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+
+        counts[0] = 0;
+
+        Ok(Huffman {
+               counts,
+               symbols
+           })
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+
+            if code - (count as i32) < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+
+            index += count as i32;
+            first  = (first + count as i32) << 1;
+            code <<= 1;
+        }
+
+        Err(Error::InvalidData)
+    }
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+}
+
+impl Pixels {
+    fn new(width: usize, height: usize) -> Result<Pixels, Error> {
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidData);
+        }
+
+        if width.checked_mul(height).is_none_or(|pixels| pixels > MAX_PIXELS) {
+            return Err(Error::Unsupported);
+        }
+
+        Ok(Pixels {
+               width,
+               height,
+               data: vec![0; width * height * 4]
+           })
+    }
+
+    fn has_alpha(&self) -> bool {
+        self.data.chunks(4).any(|pixel| pixel[3] != 255)
+    }
+
+    fn set(&mut self, x: usize, y: usize, pixel: [u8; 4]) {
+        let offset = (y * self.width + x) * 4;
+
+        self.data[offset..offset + 4].copy_from_slice(&pixel);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// scales a masked field of a bitfield pixel to 8 bits, missing alpha is opaque
+fn bitfield(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 255;
+    }
+
+    let shift = mask.trailing_zeros();
+    let max   = (mask >> shift) as u64;
+
+    (((value & mask) >> shift) as u64 * 255 / max) as u8
+}
+
+// reads a whole PNG, BMP, TGA or PPM image and lays it out for a dispmanx resource, the format
+// is found from the leading bytes and anything unrecognised is taken to be a TGA
+pub fn decode<R: Read>(mut reader: R, options: &Options) -> Result<Decoded, Error> {
+    let mut data = Vec::new();
+
+    reader.read_to_end(&mut data)?;
+
+    let pixels = match data[..] {
+        [0x89, b'P', b'N', b'G', ..] => read_png(&data)?,
+        [b'B', b'M', ..]             => read_bmp(&data)?,
+        [b'P', b'1'..=b'6', ..]      => read_pnm(&data)?,
+        _                            => read_tga(&data)?
+    };
+
+    let pixels = downscale(pixels, options.max_width, options.max_height);
+
+    layout(&pixels, options)
+}
+
+// box filter weighted by alpha so transparent pixels do not darken their neighbours
+fn downscale(pixels: Pixels, max_width: Option<uint32_t>,
+             max_height: Option<uint32_t>) -> Pixels {
+    let (width, height) = (pixels.width, pixels.height);
+
+    let scale_x = max_width.map_or(1.0, |max| max.max(1) as f64 / width as f64);
+    let scale_y = max_height.map_or(1.0, |max| max.max(1) as f64 / height as f64);
+    let scale   = scale_x.min(scale_y);
+
+    if scale >= 1.0 {
+        return pixels;
+    }
+
+    let new_width  = ((width as f64 * scale).round() as usize).max(1);
+    let new_height = ((height as f64 * scale).round() as usize).max(1);
+
+    let mut out = Pixels {
+        width:  new_width,
+        height: new_height,
+        data:   vec![0; new_width * new_height * 4]
+    };
+
+    for y in 0..new_height {
+        let top    = y * height / new_height;
+        let bottom = ((y + 1) * height / new_height).max(top + 1);
+
+        for x in 0..new_width {
+            let left  = x * width / new_width;
+            let right = ((x + 1) * width / new_width).max(left + 1);
+
+            let mut sums = [0u64; 4];
+
+            for row in top..bottom {
+                for column in left..right {
+                    let offset = (row * width + column) * 4;
+                    let alpha  = pixels.data[offset + 3] as u64;
+
+                    for (sum, &value) in sums.iter_mut().zip(&pixels.data[offset..offset + 3]) {
+                        *sum += value as u64 * alpha;
+                    }
+
+                    sums[3] += alpha;
+                }
+            }
+
+            let count = ((bottom - top) * (right - left)) as u64;
+
+            let mut pixel = [0; 4];
+
+            for (channel, &sum) in pixel.iter_mut().zip(&sums[..3]) {
+                *channel = (sum + sums[3] / 2).checked_div(sums[3]).unwrap_or(0) as u8;
+            }
+
+            pixel[3] = ((sums[3] + count / 2) / count) as u8;
+
+            out.set(x, y, pixel);
+        }
+    }
+
+    out
+}
+
+// raw zlib or deflate data is inflated up to limit bytes, anything longer is invalid
+fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    let mut bits = BitReader::new(data);
+    let mut out  = Vec::new();
+
+    loop {
+        let last = bits.bits(1)? == 1;
+
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+
+                let header = bits.bytes(4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+
+                if length != !u16::from_le_bytes([header[2], header[3]]) {
+                    return Err(Error::InvalidData);
+                }
+
+                out.extend_from_slice(bits.bytes(length as usize)?);
+            },
+
+            1 => {
+                let mut lengths = [0u8; 288];
+
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+
+                inflate_block(&mut bits, &mut out, &Huffman::new(&lengths)?,
+                              &Huffman::new(&[5; 30])?, limit)?;
+            },
+
+            2 => {
+                let (literals, distances) = inflate_tables(&mut bits)?;
+
+                inflate_block(&mut bits, &mut out, &literals, &distances, limit)?;
+            },
+
+            _ => return Err(Error::InvalidData)
+        }
+
+        if out.len() > limit {
+            return Err(Error::InvalidData);
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn inflate_block(bits: &mut BitReader, out: &mut Vec<u8>, literals: &Huffman,
+                 distances: &Huffman, limit: usize) -> Result<(), Error> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+
+            if symbol >= LENGTH_BASE.len() {
+                return Err(Error::InvalidData);
+            }
+
+            let length = LENGTH_BASE[symbol] as usize
+                         + bits.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+            let symbol = distances.decode(bits)? as usize;
+
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(Error::InvalidData);
+            }
+
+            let distance = DISTANCE_BASE[symbol] as usize
+                           + bits.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+
+            if distance > out.len() {
+                return Err(Error::InvalidData);
+            }
+
+            // copies byte by byte as the source may overlap what is being written
+            for _ in 0..length {
+                out.push(out[out.len() - distance]);
+            }
+        }
+
+        if out.len() > limit {
+            return Err(Error::InvalidData);
+        }
+    }
+}
+
+// literal/length and distance codes of a dynamic block
+fn inflate_tables(bits: &mut BitReader) -> Result<(Huffman, Huffman), Error> {
+    let literals  = bits.bits(5)? as usize + 257;
+    let distances = bits.bits(5)? as usize + 1;
+    let codes     = bits.bits(4)? as usize + 4;
+
+    if literals > 286 || distances > 30 {
+        return Err(Error::InvalidData);
+    }
+
+    let mut lengths = [0u8; 19];
+
+    for &index in &CODE_LENGTH_ORDER[..codes] {
+        lengths[index] = bits.bits(3)? as u8;
+    }
+
+    let code = Huffman::new(&lengths)?;
+
+    let mut lengths = vec![0u8; literals + distances];
+    let mut index   = 0;
+
+    while index < lengths.len() {
+        let symbol = code.decode(bits)?;
+
+        if symbol < 16 {
+            lengths[index] = symbol as u8;
+            index += 1;
+
+            continue;
+        }
+
+        let (value, repeat) = match symbol {
+            16 if index > 0 => (lengths[index - 1], 3 + bits.bits(2)? as usize),
+            17              => (0, 3 + bits.bits(3)? as usize),
+            18              => (0, 11 + bits.bits(7)? as usize),
+            _               => return Err(Error::InvalidData)
+        };
+
+        if index + repeat > lengths.len() {
+            return Err(Error::InvalidData);
+        }
+
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+
+    if lengths[256] == 0 {
+        return Err(Error::InvalidData);
+    }
+
+    Ok((Huffman::new(&lengths[..literals])?, Huffman::new(&lengths[literals..])?))
+}
+
+// RGBA32 when any pixel is not opaque, otherwise RGB888 or RGB565
+fn layout(pixels: &Pixels, options: &Options) -> Result<Decoded, Error> {
+    let type_ = if pixels.has_alpha() {
+        ImageType::RGBA32
+    } else if options.rgb565 {
+        ImageType::RGB565
+    } else {
+        ImageType::RGB888
+    };
+
+    let (width, height) = (pixels.width as uint32_t, pixels.height as uint32_t);
+
+    let pitch = type_.required_pitch(width).ok_or(Error::Unsupported)?;
+
+    let mut data = vec![0; pitch as usize * pixels.height];
+
+    convert::convert(&pixels.data, Layout::new(ImageType::RGBA32, pixels.width * 4), &mut data,
+                     Layout::new(type_, pitch as usize), width, height, AlphaMode::Keep)?;
+
+    Ok(Decoded {
+           width,
+           height,
+           type_,
+           pitch,
+           data
+       })
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let (a16, b16, c16) = (a as i16, b as i16, c as i16);
+
+    let p = a16 + b16 - c16;
+
+    let (pa, pb, pc) = ((p - a16).abs(), (p - b16).abs(), (p - c16).abs());
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// uncompressed BI_RGB and BI_BITFIELDS images at any depth, rows are bottom-up unless the
+// height is negative
+fn read_bmp(data: &[u8]) -> Result<Pixels, Error> {
+    let header = u32_le(data, 14)? as usize;
+
+    let (width, height, depth, compression) = match header {
+        12       => (u16_le(data, 18)? as i32, u16_le(data, 20)? as i32, u16_le(data, 24)?, 0),
+        40..=124 => (u32_le(data, 18)? as i32, u32_le(data, 22)? as i32, u16_le(data, 28)?,
+                     u32_le(data, 30)?),
+        _        => return Err(Error::Unsupported)
+    };
+
+    if width <= 0 || height == 0 {
+        return Err(Error::InvalidData);
+    }
+
+    let mut pixels = Pixels::new(width as usize, height.unsigned_abs() as usize)?;
+
+    let masks = match (compression, depth) {
+        (0, 1) | (0, 4) | (0, 8) => [0; 4],
+        (0, 16)                  => [0x7c00, 0x03e0, 0x001f, 0],
+        (0, 24) | (0, 32)        => [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0],
+
+        // the alpha mask is only there for version 3 headers and up or BI_ALPHABITFIELDS
+        (3, 16) | (3, 32) | (6, 16) | (6, 32) => {
+            let alpha = if header >= 56 || compression == 6 { u32_le(data, 66)? } else { 0 };
+
+            [u32_le(data, 54)?, u32_le(data, 58)?, u32_le(data, 62)?, alpha]
+        },
+
+        _ => return Err(Error::Unsupported)
+    };
+
+    let mut palette = Vec::new();
+
+    if depth <= 8 {
+        let entry = if header == 12 { 3 } else { 4 };
+        let used  = if header == 12 { 0 } else { u32_le(data, 46)? as usize };
+        let count = if used == 0 || used > 1 << depth { 1 << depth } else { used };
+        let start = 14 + header + if compression == 3 && header == 40 { 12 } else { 0 };
+
+        for i in 0..count {
+            let colour = data.get(start + i * entry..start + i * entry + 3)
+                             .ok_or(Error::InvalidData)?;
+
+            palette.push([colour[2], colour[1], colour[0], 255]);
+        }
+    }
+
+    let offset = u32_le(data, 10)? as usize;
+    let stride = (pixels.width * depth as usize).div_ceil(32) * 4;
+
+    let end = stride.checked_mul(pixels.height)
+                    .and_then(|size| size.checked_add(offset))
+                    .ok_or(Error::InvalidData)?;
+
+    let rows = data.get(offset..end).ok_or(Error::InvalidData)?;
+
+    for (i, row) in rows.chunks(stride).enumerate() {
+        let y = if height < 0 { i } else { pixels.height - 1 - i };
+
+        for x in 0..pixels.width {
+            let pixel = match depth {
+                1 | 4 | 8 => {
+                    *palette.get(sample(row, x, depth as usize) as usize)
+                            .ok_or(Error::InvalidData)?
+                },
+
+                24 => [row[x * 3 + 2], row[x * 3 + 1], row[x * 3], 255],
+
+                _ => {
+                    let value = if depth == 16 {
+                        u16_le(row, x * 2)?
+                    } else {
+                        u32_le(row, x * 4)?
+                    };
+
+                    [bitfield(value, masks[0]), bitfield(value, masks[1]),
+                     bitfield(value, masks[2]), bitfield(value, masks[3])]
+                }
+            };
+
+            pixels.set(x, y, pixel);
+        }
+    }
+
+    Ok(pixels)
+}
+
+// every colour type, depth and interlacing, tRNS is applied
+fn read_png(data: &[u8]) -> Result<Pixels, Error> {
+    let mut header      = None;
+    let mut palette     = Vec::new();
+    let mut transparent = None;
+    let mut compressed  = Vec::new();
+    let mut position    = 8;
+
+    while position < data.len() {
+        let length = u32_be(data, position)? as usize;
+        let kind   = data.get(position + 4..position + 8).ok_or(Error::InvalidData)?;
+        let end    = (position + 8).checked_add(length).ok_or(Error::InvalidData)?;
+        let chunk  = data.get(position + 8..end).ok_or(Error::InvalidData)?;
+
+        // the crc follows the chunk
+        position = end + 4;
+
+        match kind {
+            b"IHDR" if length == 13 => header = Some(chunk),
+            b"PLTE"                 => {
+                palette = chunk.chunks_exact(3)
+                               .map(|colour| [colour[0], colour[1], colour[2], 255])
+                               .collect();
+            },
+
+            b"tRNS" => transparent = Some(chunk),
+            b"IDAT" => compressed.extend_from_slice(chunk),
+            b"IEND" => break,
+            _       => ()
+        }
+    }
+
+    let header = header.ok_or(Error::InvalidData)?;
+
+    let (width, height) = (u32_be(header, 0)? as usize, u32_be(header, 4)? as usize);
+    let (depth, colour) = (header[8] as usize, header[9]);
+
+    let channels = match (colour, depth) {
+        (0, 1) | (0, 2) | (0, 4) | (0, 8) | (0, 16) => 1,
+        (3, 1) | (3, 2) | (3, 4) | (3, 8)           => 1,
+        (2, 8) | (2, 16)                            => 3,
+        (4, 8) | (4, 16)                            => 2,
+        (6, 8) | (6, 16)                            => 4,
+        _                                           => return Err(Error::InvalidData)
+    };
+
+    if header[10] != 0 || header[11] != 0 || header[12] > 1 {
+        return Err(Error::Unsupported);
+    }
+
+    let mut pixels = Pixels::new(width, height)?;
+
+    // grey and truecolour images have a single 16 bit transparent colour, palettes an alpha for
+    // each entry
+    let mut key = None;
+
+    if let Some(transparent) = transparent {
+        match colour {
+            0 => key = Some([u16_be(transparent, 0)?; 3]),
+            2 => {
+                key = Some([u16_be(transparent, 0)?, u16_be(transparent, 2)?,
+                            u16_be(transparent, 4)?])
+            },
+
+            3 => {
+                for (entry, &alpha) in palette.iter_mut().zip(transparent) {
+                    entry[3] = alpha;
+                }
+            },
+
+            _ => ()
+        }
+    }
+
+    let passes: &[(usize, usize, usize, usize)] = if header[12] == 1 {
+        &ADAM7
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+
+    let bits   = channels * depth;
+    let stride = |width: usize| (width * bits).div_ceil(8);
+    let size   = |(x, y, dx, dy): (usize, usize, usize, usize)| {
+        (width.saturating_sub(x).div_ceil(dx), height.saturating_sub(y).div_ceil(dy))
+    };
+
+    let limit = passes.iter()
+                      .map(|&pass| {
+                          let (width, height) = size(pass);
+
+                          if width == 0 { 0 } else { (stride(width) + 1) * height }
+                      })
+                      .sum();
+
+    // zlib header without a preset dictionary
+    if compressed.len() < 2 || compressed[0] & 0x0f != 8 || compressed[1] & 0x20 != 0
+       || !(compressed[0] as u16 * 256 + compressed[1] as u16).is_multiple_of(31) {
+        return Err(Error::InvalidData);
+    }
+
+    let raw = inflate(&compressed[2..], limit)?;
+
+    if raw.len() < limit {
+        return Err(Error::InvalidData);
+    }
+
+    let scale = |value: u16| match depth {
+        16 => (value >> 8) as u8,
+        8  => value as u8,
+        _  => (value as u32 * 255 / ((1 << depth) - 1)) as u8
+    };
+
+    let mut offset = 0;
+
+    for &pass in passes {
+        let (pass_width, pass_height) = size(pass);
+
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+
+        let stride = stride(pass_width);
+        let step   = (bits / 8).max(1);
+
+        let mut previous = vec![0; stride];
+
+        for row in 0..pass_height {
+            let filter      = raw[offset];
+            let mut current = raw[offset + 1..offset + 1 + stride].to_vec();
+
+            offset += stride + 1;
+
+            unfilter(filter, &mut current, &previous, step)?;
+
+            for column in 0..pass_width {
+                let samples: Vec<u16> = (0..channels)
+                                            .map(|i| sample(&current, column * channels + i,
+                                                            depth))
+                                            .collect();
+
+                let alpha = if key.is_some_and(|key| key[..channels] == samples[..]) {
+                    0
+                } else {
+                    255
+                };
+
+                let pixel = match colour {
+                    0 => [scale(samples[0]), scale(samples[0]), scale(samples[0]), alpha],
+                    2 => [scale(samples[0]), scale(samples[1]), scale(samples[2]), alpha],
+                    3 => *palette.get(samples[0] as usize).ok_or(Error::InvalidData)?,
+                    4 => {
+                        let grey = scale(samples[0]);
+
+                        [grey, grey, grey, scale(samples[1])]
+                    },
+
+                    _ => [scale(samples[0]), scale(samples[1]), scale(samples[2]),
+                          scale(samples[3])]
+                };
+
+                pixels.set(pass.0 + column * pass.2, pass.1 + row * pass.3, pixel);
+            }
+
+            previous = current;
+        }
+    }
+
+    Ok(pixels)
+}
+
+// P1 to P6, ascii and binary bitmaps, greymaps and pixmaps at any maximum value
+fn read_pnm(data: &[u8]) -> Result<Pixels, Error> {
+    let kind         = data[1];
+    let mut position = 2;
+
+    let width  = token(data, &mut position)? as usize;
+    let height = token(data, &mut position)? as usize;
+    let max    = if kind == b'1' || kind == b'4' { 1 } else { token(data, &mut position)? };
+
+    if max == 0 || max > 65535 {
+        return Err(Error::InvalidData);
+    }
+
+    let mut pixels = Pixels::new(width, height)?;
+
+    let channels = if kind == b'3' || kind == b'6' { 3 } else { 1 };
+    let count    = width * height * channels;
+
+    let values: Vec<u32> = match kind {
+        // ascii bitmaps need no whitespace between their digits
+        b'1' => {
+            let mut values = Vec::with_capacity(count);
+
+            while values.len() < count {
+                match *data.get(position).ok_or(Error::InvalidData)? {
+                    b'0'                               => values.push(1),
+                    b'1'                               => values.push(0),
+                    b'#'                               => skip_comment(data, &mut position),
+                    byte if byte.is_ascii_whitespace() => (),
+                    _                                  => return Err(Error::InvalidData)
+                }
+
+                position += 1;
+            }
+
+            values
+        },
+
+        b'2' | b'3' => (0..count).map(|_| token(data, &mut position)).collect::<Result<_, _>>()?,
+
+        // a single whitespace byte separates the header from binary data
+        b'4' => {
+            let stride = width.div_ceil(8);
+            let rows   = data.get(position + 1..position + 1 + stride * height)
+                             .ok_or(Error::InvalidData)?;
+
+            rows.chunks(stride)
+                .flat_map(|row| (0..width).map(move |x| 1 - sample(row, x, 1) as u32))
+                .collect()
+        },
+
+        _ => {
+            let bytes   = if max < 256 { 1 } else { 2 };
+            let samples = data.get(position + 1..position + 1 + count * bytes)
+                              .ok_or(Error::InvalidData)?;
+
+            samples.chunks(bytes)
+                   .map(|sample| sample.iter().fold(0, |value, &byte| value << 8 | byte as u32))
+                   .collect()
+        }
+    };
+
+    let scale = |value: u32| ((value.min(max) * 255 + max / 2) / max) as u8;
+
+    for (i, pixel) in values.chunks(channels).enumerate() {
+        let colour = if channels == 3 {
+            [scale(pixel[0]), scale(pixel[1]), scale(pixel[2]), 255]
+        } else {
+            [scale(pixel[0]), scale(pixel[0]), scale(pixel[0]), 255]
+        };
+
+        pixels.set(i % width, i / width, colour);
+    }
+
+    Ok(pixels)
+}
+
+// colour mapped, truecolour and greyscale images, raw or run length encoded
+fn read_tga(data: &[u8]) -> Result<Pixels, Error> {
+    let header = data.get(..18).ok_or(Error::InvalidData)?;
+
+    let (map_type, image_type) = (header[1], header[2]);
+    let (map_first, map_length) = (u16_le(header, 3)? as usize, u16_le(header, 5)? as usize);
+
+    let (width, height) = (u16_le(header, 12)? as usize, u16_le(header, 14)? as usize);
+    let (depth, descriptor) = (header[16], header[17]);
+
+    let alpha = descriptor & 0x0f != 0;
+
+    let valid = match image_type & 7 {
+        1 => map_type == 1 && depth == 8,
+        2 => matches!(depth, 15 | 16 | 24 | 32),
+        3 => depth == 8,
+        _ => false
+    };
+
+    if !valid || image_type & !0x0b != 0 {
+        return Err(Error::Unsupported);
+    }
+
+    let mut pixels   = Pixels::new(width, height)?;
+    let mut position = 18 + header[0] as usize;
+    let mut palette  = Vec::new();
+
+    if map_type == 1 {
+        let bytes = (header[7] as usize).div_ceil(8);
+
+        for _ in 0..map_length {
+            let entry = data.get(position..position + bytes).ok_or(Error::InvalidData)?;
+
+            palette.push(tga_colour(entry, alpha)?);
+            position += bytes;
+        }
+    }
+
+    let bytes = (depth as usize).div_ceil(8);
+
+    let colour = |entry: &[u8]| match image_type & 7 {
+        1 => {
+            (entry[0] as usize).checked_sub(map_first)
+                               .and_then(|index| palette.get(index).copied())
+                               .ok_or(Error::InvalidData)
+        },
+
+        3 => Ok([entry[0], entry[0], entry[0], 255]),
+        _ => tga_colour(entry, alpha)
+    };
+
+    let mut index = 0;
+
+    while index < width * height {
+        // run length packets repeat one pixel, raw packets hold several
+        let (count, repeat) = if image_type & 8 != 0 {
+            let packet = *data.get(position).ok_or(Error::InvalidData)?;
+
+            position += 1;
+
+            ((packet & 0x7f) as usize + 1, packet & 0x80 != 0)
+        } else {
+            (width * height, false)
+        };
+
+        for i in 0..count.min(width * height - index) {
+            let start = if repeat { position } else { position + i * bytes };
+            let entry = data.get(start..start + bytes).ok_or(Error::InvalidData)?;
+
+            let (x, y) = (index % width, index / width);
+
+            let x = if descriptor & 0x10 != 0 { width - 1 - x } else { x };
+            let y = if descriptor & 0x20 != 0 { y } else { height - 1 - y };
+
+            pixels.set(x, y, colour(entry)?);
+            index += 1;
+        }
+
+        position += if repeat { bytes } else { count * bytes };
+    }
+
+    Ok(pixels)
+}
+
+// an msb first sample of 1 to 16 bits
+fn sample(row: &[u8], index: usize, depth: usize) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8  => row[index] as u16,
+        _  => {
+            let bit = index * depth;
+
+            (row[bit / 8] >> (8 - depth - bit % 8)) as u16 & ((1 << depth) - 1)
+        }
+    }
+}
+
+fn skip_comment(data: &[u8], position: &mut usize) {
+    while *position < data.len() && data[*position] != b'\n' {
+        *position += 1;
+    }
+}
+
+// little endian BGR(A), 15 and 16 bit colours are 5 bits per channel with an alpha bit
+fn tga_colour(entry: &[u8], alpha: bool) -> Result<[u8; 4], Error> {
+    match *entry {
+        [low, high] => {
+            let value = u16::from_le_bytes([low, high]) as u32;
+            let a     = if alpha && value & 0x8000 == 0 { 0 } else { 255 };
+
+            Ok([bitfield(value, 0x7c00), bitfield(value, 0x03e0), bitfield(value, 0x001f), a])
+        },
+
+        [b, g, r]    => Ok([r, g, b, 255]),
+        [b, g, r, a] => Ok([r, g, b, if alpha { a } else { 255 }]),
+        _            => Err(Error::Unsupported)
+    }
+}
+
+// a decimal header field of a portable anymap, whitespace and comments are skipped
+fn token(data: &[u8], position: &mut usize) -> Result<u32, Error> {
+    loop {
+        match data.get(*position) {
+            Some(b'#')                               => skip_comment(data, position),
+            Some(byte) if byte.is_ascii_whitespace() => *position += 1,
+            _                                        => break
+        }
+    }
+
+    let start = *position;
+
+    while data.get(*position).is_some_and(|byte| byte.is_ascii_digit()) {
+        *position += 1;
+    }
+
+    std::str::from_utf8(&data[start..*position]).ok()
+                                                .and_then(|digits| digits.parse().ok())
+                                                .ok_or(Error::InvalidData)
+}
+
+fn u16_be(data: &[u8], offset: usize) -> Result<u16, Error> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None        => Err(Error::InvalidData)
+    }
+}
+
+fn u16_le(data: &[u8], offset: usize) -> Result<u32, Error> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]]) as u32),
+        None        => Err(Error::InvalidData)
+    }
+}
+
+fn u32_be(data: &[u8], offset: usize) -> Result<u32, Error> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None        => Err(Error::InvalidData)
+    }
+}
+
+fn u32_le(data: &[u8], offset: usize) -> Result<u32, Error> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None        => Err(Error::InvalidData)
+    }
+}
+
+// undoes one of the five per-row PNG filters, step is the bytes per pixel rounded up
+fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], step: usize) -> Result<(), Error> {
+    if filter > 4 {
+        return Err(Error::InvalidData);
+    }
+
+    for i in 0..row.len() {
+        let a = if i >= step { row[i - step] } else { 0 };
+        let b = previous[i];
+        let c = if i >= step { previous[i - step] } else { 0 };
+
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth(a, b, c)
+        };
+
+        row[i] = row[i].wrapping_add(predictor);
+    }
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use capture::CapturedImage;
+
+    use image::ImageType;
+
+    use super::{ decode,
+                 inflate,
+                 Decoded,
+                 Error,
+                 Options };
+
+    fn bmp(info: &[u8], extra: &[u8], rows: &[u8]) -> Vec<u8> {
+        let offset = 14 + info.len() + extra.len();
+
+        let mut data = b"BM".to_vec();
+
+        data.extend_from_slice(&((offset + rows.len()) as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(offset as u32).to_le_bytes());
+        data.extend_from_slice(info);
+        data.extend_from_slice(extra);
+        data.extend_from_slice(rows);
+
+        data
+    }
+
+    fn bmp_info(size: usize, width: i32, height: i32, depth: u16, compression: u32,
+                used: u32) -> Vec<u8> {
+        let mut info = (size as u32).to_le_bytes().to_vec();
+
+        info.extend_from_slice(&width.to_le_bytes());
+        info.extend_from_slice(&height.to_le_bytes());
+        info.extend_from_slice(&1u16.to_le_bytes());
+        info.extend_from_slice(&depth.to_le_bytes());
+        info.extend_from_slice(&compression.to_le_bytes());
+        info.extend_from_slice(&[0; 12]);
+        info.extend_from_slice(&used.to_le_bytes());
+        info.resize(size, 0);
+
+        info
+    }
+
+    fn decoded(data: &[u8]) -> Decoded {
+        decode(data, &Options::new()).unwrap()
+    }
+
+    // chunk crcs and the adler32 are not checked so they are left as zero
+    fn png(header: [u8; 13], chunks: &[(&[u8], &[u8])], raw: &[u8]) -> Vec<u8> {
+        let mut zlib = vec![0x78, 0x01, 1];
+
+        zlib.extend_from_slice(&(raw.len() as u16).to_le_bytes());
+        zlib.extend_from_slice(&(!(raw.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(raw);
+        zlib.extend_from_slice(&[0; 4]);
+
+        let mut data = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+        let chunks = [(&b"IHDR"[..], &header[..])].iter()
+                                                  .chain(chunks)
+                                                  .chain(&[(&b"IDAT"[..], &zlib[..]),
+                                                           (&b"IEND"[..], &[][..])])
+                                                  .cloned()
+                                                  .collect::<Vec<_>>();
+
+        for (kind, chunk) in chunks {
+            data.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+            data.extend_from_slice(kind);
+            data.extend_from_slice(chunk);
+            data.extend_from_slice(&[0; 4]);
+        }
+
+        data
+    }
+
+    fn png_header(width: u32, height: u32, depth: u8, colour: u8, interlace: u8) -> [u8; 13] {
+        let mut header = [0; 13];
+
+        header[..4].copy_from_slice(&width.to_be_bytes());
+        header[4..8].copy_from_slice(&height.to_be_bytes());
+        header[8]  = depth;
+        header[9]  = colour;
+        header[12] = interlace;
+
+        header
+    }
+
+    #[test]
+    pub fn bmp_pass() {
+        let mut out = Vec::new();
+
+        CapturedImage {
+            width:  3,
+            height: 2,
+            type_:  ImageType::RGB888,
+            data:   (0..18).collect()
+        }.write_bmp(&mut out)
+         .unwrap();
+
+        let image = decoded(&out);
+
+        assert_eq!((image.width, image.height, image.type_, image.pitch),
+                   (3, 2, ImageType::RGB888, 32));
+        assert_eq!(&image.data[..9], &[0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(&image.data[32..41], &[9, 10, 11, 12, 13, 14, 15, 16, 17]);
+
+        // top-down with a version 4 header carrying an alpha mask
+        let mut info = bmp_info(108, 2, -1, 32, 3, 0);
+
+        for (i, mask) in [0x00ff_0000u32, 0x0000_ff00, 0x0000_00ff, 0xff00_0000].iter()
+                                                                                .enumerate() {
+            info[40 + i * 4..44 + i * 4].copy_from_slice(&mask.to_le_bytes());
+        }
+
+        let image = decoded(&bmp(&info, &[], &[3, 2, 1, 128, 6, 5, 4, 255]));
+
+        assert_eq!(image.type_, ImageType::RGBA32);
+        assert_eq!(&image.data[..8], &[1, 2, 3, 128, 4, 5, 6, 255]);
+
+        // two entry palette and rows padded to 4 bytes
+        let info    = bmp_info(40, 3, 1, 1, 0, 2);
+        let palette = [0, 0, 0, 0, 255, 255, 255, 0];
+
+        let image = decoded(&bmp(&info, &palette, &[0b0100_0000, 0, 0, 0]));
+
+        assert_eq!(&image.data[..9], &[0, 0, 0, 255, 255, 255, 0, 0, 0]);
+
+        let info = bmp_info(40, 1, 1, 8, 1, 0);
+
+        assert!(matches!(decode(&bmp(&info, &[], &[0; 4])[..], &Options::new()),
+                         Err(Error::Unsupported)));
+
+        // a pixel offset that overflows the end of the rows on 32 bit targets, and more pixels
+        // than are decoded
+        let mut data = bmp(&bmp_info(40, 1, 1, 24, 0, 0), &[], &[0; 4]);
+
+        data[10..14].copy_from_slice(&[0xff; 4]);
+
+        assert!(matches!(decode(&data[..], &Options::new()), Err(Error::InvalidData)));
+
+        let info = bmp_info(40, 16384, 16384, 24, 0, 0);
+
+        assert!(matches!(decode(&bmp(&info, &[], &[0; 4])[..], &Options::new()),
+                         Err(Error::Unsupported)));
+    }
+
+    #[test]
+    pub fn decode_pass() {
+        assert!(matches!(decode(&[][..], &Options::new()), Err(Error::InvalidData)));
+        assert!(matches!(decode(&b"P6 0 1 255\n"[..], &Options::new()),
+                         Err(Error::InvalidData)));
+
+        let ppm = b"P5 4 2 255\n\x00\xff\x00\xff\xff\x00\xff\x00";
+
+        let options = Options {
+            rgb565: true,
+            ..Options::new()
+        };
+
+        let image = decode(&ppm[..], &options).unwrap();
+
+        assert_eq!((image.type_, image.pitch, image.data.len()), (ImageType::RGB565, 32, 64));
+        assert_eq!(&image.data[..4], &[0, 0, 0xff, 0xff]);
+
+        // each output pixel averages a 2 x 2 block and the aspect ratio is kept
+        let options = Options {
+            max_width: Some(2),
+            ..Options::new()
+        };
+
+        let image = decode(&ppm[..], &options).unwrap();
+
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(&image.data[..6], &[128; 6]);
+
+        let options = Options {
+            max_width:  Some(8),
+            max_height: Some(8),
+            ..Options::new()
+        };
+
+        assert_eq!(decode(&ppm[..], &options).unwrap().width, 4);
+    }
+
+    #[test]
+    pub fn inflate_pass() {
+        assert_eq!(inflate(&[75, 76, 74, 78, 68, 69, 0], 100).unwrap(), b"abcabcabcabcabcabc");
+
+        let dynamic = [5, 193, 1, 1, 0, 48, 12, 194, 48, 173, 101, 133, 251, 119, 240, 132, 84,
+                       231, 201, 150, 245, 29, 17, 210, 15];
+
+        assert_eq!(inflate(&dynamic, 100).unwrap(), b"abeddfdcdaffbfegcabdaabe");
+        assert_eq!(inflate(&[1, 3, 0, 0xfc, 0xff, 1, 2, 3], 3).unwrap(), [1, 2, 3]);
+
+        // truncated, too long, a bad stored length and a distance before the start
+        assert!(inflate(&dynamic[..12], 100).is_err());
+        assert!(inflate(&[75, 76, 74, 78, 68, 69, 0], 10).is_err());
+        assert!(inflate(&[1, 3, 0, 0xfd, 0xff, 1, 2, 3], 3).is_err());
+        assert!(inflate(&[3, 2, 0], 100).is_err());
+    }
+
+    #[test]
+    pub fn png_pass() {
+        // sub and paeth filters
+        let raw = [1, 10, 20, 30, 30, 30, 30, 30, 30, 30, 4, 5, 5, 5, 60, 60, 60, 100, 100, 100];
+
+        let image = decoded(&png(png_header(3, 2, 8, 2, 0), &[], &raw));
+
+        assert_eq!((image.width, image.height, image.type_), (3, 2, ImageType::RGB888));
+        assert_eq!(&image.data[..9], &[10, 20, 30, 40, 50, 60, 70, 80, 90]);
+        assert_eq!(&image.data[32..41], &[15, 25, 35, 100, 110, 120, 200, 210, 220]);
+
+        // average and up filters
+        let raw = [3, 0, 64, 96, 191, 2, 255, 64, 192, 1];
+
+        let image = decoded(&png(png_header(4, 2, 8, 0, 0), &[], &raw));
+
+        assert_eq!(&image.data[..12], &[0, 0, 0, 64, 64, 64, 128, 128, 128, 255, 255, 255]);
+        assert_eq!(&image.data[32..35], &[255, 255, 255]);
+
+        // 2 bit palette indices with a partly transparent palette
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        let chunks  = [(&b"PLTE"[..], &palette[..]), (&b"tRNS"[..], &[255, 128][..])];
+
+        let image = decoded(&png(png_header(3, 1, 2, 3, 0), &chunks, &[0, 0x90]));
+
+        assert_eq!(image.type_, ImageType::RGBA32);
+        assert_eq!(&image.data[..12], &[0, 0, 255, 255, 0, 255, 0, 128, 255, 0, 0, 255]);
+
+        // 16 bit grey over three interlaced passes with a transparent grey
+        let raw = [0, 0xff, 0xff, 0, 0x80, 0x00, 0, 0x00, 0x00, 0x12, 0x34];
+
+        let image = decoded(&png(png_header(2, 2, 16, 0, 1), &[(&b"tRNS"[..], &[0x12, 0x34][..])],
+                                 &raw));
+
+        assert_eq!(&image.data[..8], &[255, 255, 255, 255, 128, 128, 128, 255]);
+        assert_eq!(&image.data[32..40], &[0, 0, 0, 255, 0x12, 0x12, 0x12, 0]);
+
+        assert!(matches!(decode(&png(png_header(1, 1, 8, 0, 2), &[], &[0, 0])[..],
+                                &Options::new()),
+                         Err(Error::Unsupported)));
+        assert!(matches!(decode(&png(png_header(1, 1, 8, 0, 0), &[], &[5, 0])[..],
+                                &Options::new()),
+                         Err(Error::InvalidData)));
+
+        // a chunk length far past the end of the data, which overflows the end on 32 bit targets
+        let mut data = png(png_header(1, 1, 8, 0, 0), &[], &[0, 0]);
+
+        data[8..12].copy_from_slice(&[0xff; 4]);
+
+        assert!(matches!(decode(&data[..], &Options::new()), Err(Error::InvalidData)));
+    }
+
+    #[test]
+    pub fn pnm_pass() {
+        let image = decoded(b"P3\n# comment\n2 1\n15\n15 0 0  0 15 # x\n 0\n");
+
+        assert_eq!(&image.data[..6], &[255, 0, 0, 0, 255, 0]);
+
+        let image = decoded(b"P1 2 2 0110");
+
+        assert_eq!(&image.data[..6], &[255, 255, 255, 0, 0, 0]);
+        assert_eq!(&image.data[32..38], &[0, 0, 0, 255, 255, 255]);
+
+        let image = decoded(b"P4\n3 1\n\xa0");
+
+        assert_eq!(&image.data[..9], &[0, 0, 0, 255, 255, 255, 0, 0, 0]);
+
+        let image = decoded(b"P5 2 1 65535\n\xff\xff\x80\x00");
+
+        assert_eq!(&image.data[..6], &[255, 255, 255, 128, 128, 128]);
+
+        let mut out = Vec::new();
+
+        CapturedImage {
+            width:  2,
+            height: 1,
+            type_:  ImageType::RGBA32,
+            data:   vec![1, 2, 3, 4, 5, 6, 7, 8]
+        }.write_ppm(&mut out)
+         .unwrap();
+
+        assert_eq!(&decoded(&out).data[..6], &[1, 2, 3, 5, 6, 7]);
+        assert!(decode(&out[..out.len() - 1], &Options::new()).is_err());
+    }
+
+    #[test]
+    pub fn tga_pass() {
+        let header = |image_type: u8, map: u8, depth: u8, descriptor: u8| {
+            let mut header = [0; 18];
+
+            header[1]  = map;
+            header[2]  = image_type;
+            header[12] = 2;
+            header[14] = 2;
+            header[16] = depth;
+            header[17] = descriptor;
+
+            header.to_vec()
+        };
+
+        // run length encoded and bottom-up
+        let mut data = header(10, 0, 24, 0);
+
+        data.extend_from_slice(&[0x81, 3, 2, 1, 0x01, 6, 5, 4, 9, 8, 7]);
+
+        let image = decoded(&data);
+
+        assert_eq!((image.width, image.height, image.type_), (2, 2, ImageType::RGB888));
+        assert_eq!(&image.data[..6], &[4, 5, 6, 7, 8, 9]);
+        assert_eq!(&image.data[32..38], &[1, 2, 3, 1, 2, 3]);
+
+        // top-down with 8 bits of alpha
+        let mut data = header(2, 0, 32, 0x28);
+
+        data.extend_from_slice(&[3, 2, 1, 0, 3, 2, 1, 255, 3, 2, 1, 255, 3, 2, 1, 255]);
+
+        let image = decoded(&data);
+
+        assert_eq!(image.type_, ImageType::RGBA32);
+        assert_eq!(&image.data[..8], &[1, 2, 3, 0, 1, 2, 3, 255]);
+
+        // colour mapped with 24 bit entries
+        let mut data = header(1, 1, 8, 0x20);
+
+        data[5] = 2;
+        data[7] = 24;
+
+        data.extend_from_slice(&[0, 0, 255, 255, 0, 0, 0, 1, 1, 0]);
+
+        let image = decoded(&data);
+
+        assert_eq!(&image.data[..6], &[255, 0, 0, 0, 0, 255]);
+        assert_eq!(&image.data[32..38], &[0, 0, 255, 255, 0, 0]);
+
+        assert!(matches!(decode(&header(2, 0, 12, 0)[..], &Options::new()),
+                         Err(Error::Unsupported)));
+        assert!(matches!(decode(&header(2, 0, 24, 0)[..], &Options::new()),
+                         Err(Error::InvalidData)));
+    }
+
+    #[cfg(videocore_software)]
+    mod software {
+        use decode::{ Error,
+                      Options };
+
+        use dispmanx::Resource;
+
+        use image::{ ImageType,
+                     Rect };
+
+        #[test]
+        pub fn from_reader_pass() {
+            let options = Options::new();
+            let ppm     = b"P6 2 1 255\n\x01\x02\x03\x04\x05\x06";
+
+            let (resource, width, height) = Resource::from_reader(&ppm[..], &options).unwrap();
+
+            assert_eq!((width, height, resource.image_type()), (2, 1, ImageType::RGB888));
+
+            let mut data = [0; 32];
+
+            resource.read(&mut data, 32, &Rect::new(0, 0, 2, 1)).unwrap();
+
+            assert_eq!(&data[..6], &[1, 2, 3, 4, 5, 6]);
+
+            assert!(matches!(Resource::from_file("/nonexistent.png", &options), Err(Error::Io(_))));
+        }
+    }
+}
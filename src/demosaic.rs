@@ -0,0 +1,361 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use libc::uint32_t;
+
+// local
+use convert::{ self,
+               Error,
+               Layout };
+
+use image::{ ImageBayerOrder,
+             ImageType };
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    // copies the missing colours from the 2x2 cell a pixel is in
+    Nearest,
+
+    // averages the neighbours of each missing colour
+    Bilinear,
+
+    // corrects the bilinear estimate with the gradient of the known colour, from malvar, he and
+    // cutler's high quality linear interpolation
+    Malvar
+}
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// unpacked samples as produced by bayer::unpack, width per row
+pub struct Mosaic<'a> {
+    pub samples: &'a [u16],
+    pub width:   uint32_t,
+    pub height:  uint32_t,
+    pub bits:    uint32_t,
+    pub order:   ImageBayerOrder
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+const RED:   usize = 0;
+const GREEN: usize = 1;
+const BLUE:  usize = 2;
+
+// malvar-he-cutler kernels as offsets and weights in sixteenths
+const GREEN_AT_RED_BLUE: [(isize, isize, i32); 9] = [
+    (0, -2, -2), (0, -1, 4), (-2, 0, -2), (-1, 0, 4), (0, 0, 8), (1, 0, 4), (2, 0, -2), (0, 1, 4),
+    (0, 2, -2)
+];
+
+const RED_BLUE_AT_GREEN_ROW: [(isize, isize, i32); 11] = [
+    (0, -2, 1), (-1, -1, -2), (1, -1, -2), (-2, 0, -2), (-1, 0, 8), (0, 0, 10), (1, 0, 8),
+    (2, 0, -2), (-1, 1, -2), (1, 1, -2), (0, 2, 1)
+];
+
+const RED_BLUE_AT_GREEN_COLUMN: [(isize, isize, i32); 11] = [
+    (0, -2, -2), (-1, -1, -2), (0, -1, 8), (1, -1, -2), (-2, 0, 1), (0, 0, 10), (2, 0, 1),
+    (-1, 1, -2), (0, 1, 8), (1, 1, -2), (0, 2, -2)
+];
+
+const RED_BLUE_AT_BLUE_RED: [(isize, isize, i32); 9] = [
+    (0, -2, -3), (-1, -1, 4), (1, -1, 4), (-2, 0, -3), (0, 0, 12), (2, 0, -3), (-1, 1, 4),
+    (1, 1, 4), (0, 2, -3)
+];
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl<'a> Mosaic<'a> {
+    pub fn new(samples: &'a [u16], width: uint32_t, height: uint32_t, bits: uint32_t,
+               order: ImageBayerOrder) -> Mosaic<'a> {
+        Mosaic {
+            samples,
+            width,
+            height,
+            bits,
+            order
+        }
+    }
+
+    fn bilinear(&self, x: isize, y: isize, colour: usize) -> i32 {
+        let (mut sum, mut count) = (0, 0);
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if self.colour(x + dx, y + dy) == colour {
+                    sum   += self.get(x + dx, y + dy);
+                    count += 1;
+                }
+            }
+        }
+
+        (sum + count / 2) / count
+    }
+
+    // colour of the filter over a pixel
+    fn colour(&self, x: isize, y: isize) -> usize {
+        let (even_row, odd_row) = match self.order {
+            ImageBayerOrder::RGGB => ([RED, GREEN], [GREEN, BLUE]),
+            ImageBayerOrder::GBRG => ([GREEN, BLUE], [RED, GREEN]),
+            ImageBayerOrder::BGGR => ([BLUE, GREEN], [GREEN, RED]),
+            ImageBayerOrder::GRBG => ([GREEN, RED], [BLUE, GREEN])
+        };
+
+        if y & 1 == 0 { even_row[(x & 1) as usize] } else { odd_row[(x & 1) as usize] }
+    }
+
+    // sample at a position mirrored back inside the image without repeating the edge, which keeps
+    // the colour of the filter the same as it would have been
+    fn get(&self, x: isize, y: isize) -> i32 {
+        let x = reflect(x, self.width as isize);
+        let y = reflect(y, self.height as isize);
+
+        self.samples[y * self.width as usize + x] as i32
+    }
+
+    fn malvar(&self, x: isize, y: isize, colour: usize) -> i32 {
+        let own = self.colour(x, y);
+
+        let kernel: &[(isize, isize, i32)] = if colour == GREEN {
+            &GREEN_AT_RED_BLUE
+        } else if own != GREEN {
+            &RED_BLUE_AT_BLUE_RED
+        } else if self.colour(x + 1, y) == colour {
+            &RED_BLUE_AT_GREEN_ROW
+        } else {
+            &RED_BLUE_AT_GREEN_COLUMN
+        };
+
+        let sum: i32 = kernel.iter()
+                             .map(|&(dx, dy, weight)| self.get(x + dx, y + dy) * weight)
+                             .sum();
+
+        (sum + 8).div_euclid(16)
+    }
+
+    fn nearest(&self, x: isize, y: isize, colour: usize) -> i32 {
+        let (x1, y1) = (x ^ 1, y ^ 1);
+
+        [(x1, y), (x, y1), (x1, y1)].iter()
+                                    .find(|&&(x, y)| self.colour(x, y) == colour)
+                                    .map_or(0, |&(x, y)| self.get(x, y))
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// fills in the two colours missing at each pixel and writes the result scaled to 8 bits into an
+// rgb format supported by convert
+pub fn demosaic(mosaic: &Mosaic, dst: &mut [u8], dst_layout: Layout,
+                algorithm: Algorithm) -> Result<(), Error> {
+    // samples are u16, so depths outside 1 to 16 bits cannot be scaled
+    if !convert::is_supported(dst_layout.type_) || !(1..=16).contains(&mosaic.bits) {
+        return Err(Error::Unsupported(ImageType::BAYER, dst_layout.type_));
+    }
+
+    let (width, height) = (mosaic.width, mosaic.height);
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    // a single row or column holds half the filter pattern, so one colour is never sampled
+    if width < 2 || height < 2 {
+        return Err(Error::Unsupported(ImageType::BAYER, dst_layout.type_));
+    }
+
+    if mosaic.samples.len() < width as usize * height as usize {
+        return Err(Error::BufferTooSmall);
+    }
+
+    dst_layout.check(dst.len(), width, height)?;
+
+    let max = (1i32 << mosaic.bits) - 1;
+
+    for y in 0..height as isize {
+        let row = &mut dst[y as usize * dst_layout.pitch..];
+
+        for x in 0..width as isize {
+            let own = mosaic.colour(x, y);
+
+            let mut pixel = [0, 0, 0, 255];
+
+            for (colour, channel) in pixel[..3].iter_mut().enumerate() {
+                let value = if colour == own {
+                    mosaic.get(x, y)
+                } else {
+                    match algorithm {
+                        Algorithm::Nearest  => mosaic.nearest(x, y, colour),
+                        Algorithm::Bilinear => mosaic.bilinear(x, y, colour),
+                        Algorithm::Malvar   => mosaic.malvar(x, y, colour)
+                    }
+                };
+
+                *channel = ((value.clamp(0, max) * 255 + max / 2) / max) as u8;
+            }
+
+            convert::write_pixel(dst_layout.type_, row, x as usize, pixel);
+        }
+    }
+
+    Ok(())
+}
+
+// size is at least 2, demosaic rejects anything narrower
+fn reflect(i: isize, size: isize) -> usize {
+    // the period is even so the parity of the position and with it the colour is kept
+    let period = 2 * (size - 1);
+    let i      = i.rem_euclid(period);
+
+    (if i >= size { period - i } else { i }) as usize
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use convert::{ Error,
+                   Layout };
+
+    use image::{ ImageBayerOrder,
+                 ImageType };
+
+    use super::{ demosaic,
+                 Algorithm,
+                 Mosaic };
+
+    const ALGORITHMS: [Algorithm; 3] = [Algorithm::Nearest, Algorithm::Bilinear, Algorithm::Malvar];
+
+    const ORDERS: [ImageBayerOrder; 4] = [
+        ImageBayerOrder::RGGB, ImageBayerOrder::GBRG, ImageBayerOrder::BGGR, ImageBayerOrder::GRBG
+    ];
+
+    // samples a scene through the colour filter of an order
+    fn mosaic(order: ImageBayerOrder, width: u32, height: u32,
+              scene: &dyn Fn(u32, u32) -> [u16; 3]) -> Vec<u16> {
+        let filter = Mosaic::new(&[], width, height, 10, order);
+
+        (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+                   .map(|(x, y)| scene(x, y)[filter.colour(x as isize, y as isize)])
+                   .collect()
+    }
+
+    fn run(samples: &[u16], order: ImageBayerOrder, width: u32, height: u32,
+           algorithm: Algorithm) -> Vec<u8> {
+        let mut out = vec![0; (width * height * 3) as usize];
+
+        demosaic(&Mosaic::new(samples, width, height, 10, order), &mut out,
+                 Layout::new(ImageType::RGB888, width as usize * 3), algorithm).unwrap();
+
+        out
+    }
+
+    #[test]
+    pub fn demosaic_errors_pass() {
+        let samples = [0; 16];
+        let mosaic  = Mosaic::new(&samples, 4, 4, 10, ImageBayerOrder::RGGB);
+        let mut out = [0; 48];
+
+        assert_eq!(demosaic(&mosaic, &mut out, Layout::new(ImageType::YUV420, 4),
+                            Algorithm::Nearest),
+                   Err(Error::Unsupported(ImageType::BAYER, ImageType::YUV420)));
+        assert_eq!(demosaic(&mosaic, &mut out[..40], Layout::new(ImageType::RGB888, 12),
+                            Algorithm::Nearest),
+                   Err(Error::BufferTooSmall));
+        assert_eq!(demosaic(&Mosaic::new(&samples[..15], 4, 4, 10, ImageBayerOrder::RGGB),
+                            &mut out, Layout::new(ImageType::RGB888, 12), Algorithm::Nearest),
+                   Err(Error::BufferTooSmall));
+
+        for &bits in &[0, 17, 31, 32] {
+            assert_eq!(demosaic(&Mosaic::new(&samples, 4, 4, bits, ImageBayerOrder::RGGB),
+                                &mut out, Layout::new(ImageType::RGB888, 12), Algorithm::Nearest),
+                       Err(Error::Unsupported(ImageType::BAYER, ImageType::RGB888)));
+        }
+
+        for &(width, height) in &[(1, 4), (4, 1), (1, 1)] {
+            assert_eq!(demosaic(&Mosaic::new(&samples, width, height, 10, ImageBayerOrder::RGGB),
+                                &mut out, Layout::new(ImageType::RGB888, 12), Algorithm::Bilinear),
+                       Err(Error::Unsupported(ImageType::BAYER, ImageType::RGB888)));
+        }
+    }
+
+    #[test]
+    pub fn demosaic_flat_pass() {
+        // a flat colour comes back everywhere including the borders, odd sizes included
+        for &order in &ORDERS {
+            for &algorithm in &ALGORITHMS {
+                for &(width, height) in &[(8, 6), (7, 5), (2, 2)] {
+                    let samples = mosaic(order, width, height, &|_, _| [1023, 512, 0]);
+                    let out     = run(&samples, order, width, height, algorithm);
+
+                    assert!(out.chunks(3).all(|pixel| pixel == [255, 128, 0]),
+                            "{:?} {:?} {}x{}", order, algorithm, width, height);
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn demosaic_gradient_pass() {
+        // interpolating filters are exact on a linear ramp away from the borders
+        let scene = |x: u32, _: u32| [(x * 100) as u16, (x * 100) as u16, (x * 100) as u16];
+
+        for &order in &ORDERS {
+            for &algorithm in &[Algorithm::Bilinear, Algorithm::Malvar] {
+                let samples = mosaic(order, 10, 6, &scene);
+                let out     = run(&samples, order, 10, 6, algorithm);
+
+                for y in 2..4 {
+                    for x in 2..8 {
+                        let i     = ((y * 10 + x) * 3) as usize;
+                        let value = ((x * 100 * 255 + 511) / 1023) as u8;
+
+                        assert_eq!(out[i..i + 3], [value; 3], "{:?} {:?}", order, algorithm);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn demosaic_nearest_pass() {
+        // each pixel takes the missing colours from its own 2x2 cell
+        let samples = [1023, 0, 1023, 0, 0, 1023, 0, 1023];
+        let out     = run(&samples, ImageBayerOrder::RGGB, 4, 2, Algorithm::Nearest);
+
+        assert_eq!(out[..3], [255, 0, 255]);
+        assert_eq!(out[15..18], [255, 0, 255]);
+    }
+}
@@ -23,6 +23,31 @@
 // -------------------------------------------------------------------------------------------------
 
 // system
+use std::convert::TryFrom;
+use std::fs::File;
+use std::{ error,
+           fmt,
+           mem,
+           ptr };
+
+use std::io::{ BufReader,
+               Read };
+
+use std::panic::{ self,
+                  AssertUnwindSafe };
+
+use std::path::Path;
+
+use std::sync::{ Arc,
+                 Condvar,
+                 Mutex };
+
+use std::sync::atomic::{ AtomicUsize,
+                         Ordering };
+
+use std::time::{ Duration,
+                 Instant };
+
 use libc::{ c_int,
             c_void,
             int32_t,
@@ -30,13 +55,29 @@ use libc::{ c_int,
             uint32_t };
 
 // local
+use backend::{ self,
+               Backend };
+
+use convert::{ self,
+               Layout };
+
+use decode::{ self,
+              Decoded };
+
 use display::{ _3dFormat,
                Info,
                InputFormat };
 
 use image::{ Image,
+             ImageTransform,
              ImageType,
-             Rect };
+             Rect,
+             SrcRect };
+
+use palette::{ self,
+               Palette };
+
+use tformat;
 
 use vchi::MemHandle;
 
@@ -52,20 +93,33 @@ pub type UpdateHandle   = uint32_t;
 pub type CallbackFunc = extern "C" fn(handle: UpdateHandle, arg: *mut c_void);
 pub type Protection   = uint32_t;
 
+type UpdateClosure = Box<dyn FnOnce(UpdateHandle) + Send>;
+type VsyncClosure  = Arc<Mutex<dyn FnMut() + Send>>;
+
 // -------------------------------------------------------------------------------------------------
 // ENUMS
 // -------------------------------------------------------------------------------------------------
 
-#[repr(C)]
-pub enum FlagsAlpha {
-    // bottom 2 bits sets the alpha mode
-    FROM_SOURCE       = 0,
-    FIXED_ALL_PIXELS  = 1,
-    FIXED_NON_ZERO    = 2,
-    FIXED_EXCEED_0X07 = 3,
+// alpha mode held in the bottom 2 bits of FlagsAlpha
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaSource {
+    FromSource,
+    FixedAllPixels,
+    FixedNonZero,
+    FixedExceed0x07
+}
 
-    PREMULT = 1 << 16,
-    MIX     = 1 << 17
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    BufferTooSmall,
+    InvalidFlags,
+    InvalidPalette,
+    InvalidPitch,
+    InvalidRect,
+    NoHandle,
+    NotPalettised,
+    Status(Status),
+    UnsupportedFormat
 }
 
 #[repr(C)]
@@ -76,39 +130,22 @@ pub enum FlagsClamp {
     REPLACE          = 3
 }
 
-#[repr(C)]
-pub enum FlagsKeymask {
-    OVERRIDE = 1,
-    SMOOTH   = 1 << 1,
-    CR_INV   = 1 << 2,
-    CB_INV   = 1 << 3,
-    YY_INV   = 1 << 4
+// rotation held in the bottom 2 bits of Transform
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    Rotate0,
+    Rotate90,
+    Rotate180,
+    Rotate270
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub enum Status {
     SUCCESS = 0,
     INVALID = -1
 }
 
-#[repr(C)]
-pub enum Transform {
-    NO_ROTATE  = 0,
-    ROTATE_90  = 1,
-    ROTATE_180 = 2,
-    ROTATE_270 = 3,
-
-    FLIP_HRIZ = 1 << 16,
-    FLIP_VERT = 1 << 17,
-
-    // extra flags for controlling snapshot behaviour
-    SNAPSHOT_NO_YUV        = 1 << 24,
-    SNAPSHOT_NO_RGB        = 1 << 25,
-    SNAPSHOT_FILL          = 1 << 26,
-    SNAPSHOT_SWAP_RED_BLUE = 1 << 27,
-    SNAPSHOT_PACK          = 1 << 28
-}
-
 // -------------------------------------------------------------------------------------------------
 // STRUCTS
 // -------------------------------------------------------------------------------------------------
@@ -117,7 +154,7 @@ pub enum Transform {
 pub struct Alpha {
     pub flags:   FlagsAlpha,
     pub opacity: uint32_t,
-    pub mask:    Image
+    pub mask:    *mut Image
 }
 
 #[repr(C)]
@@ -148,6 +185,11 @@ pub struct ClampKeysYUV {
     pub cb_lower: uint8_t
 }
 
+// owned display handle, closed when dropped
+pub struct Display {
+    handle: DisplayHandle
+}
+
 #[repr(C)]
 pub struct DisplayFuncs {
     pub get_hvs_config: extern "C" fn(instance: *mut c_void, pchan: *mut uint32_t,
@@ -182,6 +224,55 @@ pub struct DisplayFuncs {
     pub get_3d_format: extern "C" fn(instance: *mut c_void) -> _3dFormat
 }
 
+// element handle along with the display it was added to
+pub struct Element {
+    handle:  ElementHandle,
+    display: DisplayHandle
+}
+
+// element_change_attributes arguments, each setter records its ELEMENT_CHANGE_* bit so only the
+// attributes that were set are sent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ElementChange {
+    flags:     uint32_t,
+    layer:     int32_t,
+    opacity:   uint8_t,
+    dest_rect: Rect,
+    src_rect:  SrcRect,
+    mask:      ResourceHandle,
+    transform: Transform
+}
+
+// element_add arguments with named setters, the destination rect and source resource are
+// required and tracked in the type so a missing one fails to compile
+pub struct ElementBuilder<'a, D, S> {
+    layer:      int32_t,
+    dest:       D,
+    src:        Option<SrcRect>,
+    source:     S,
+    opacity:    uint8_t,
+    alpha_mode: FlagsAlpha,
+    alpha_mask: Option<&'a Resource>,
+    clamp:      Option<Clamp>,
+    protection: Protection,
+    transform:  Transform
+}
+
+// failed firmware call along with the reason
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Error {
+    call: &'static str,
+    kind: ErrorKind
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(transparent)]
+pub struct FlagsAlpha(uint32_t);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(transparent)]
+pub struct FlagsKeymask(uint32_t);
+
 #[repr(C)]
 pub struct Modeinfo {
     pub width:        int32_t,
@@ -190,6 +281,28 @@ pub struct Modeinfo {
     pub input_format: InputFormat
 }
 
+// owned resource handle that remembers its format and size, deleted when dropped
+pub struct Resource {
+    handle: ResourceHandle,
+    type_:  ImageType,
+    width:  uint32_t,
+    height: uint32_t
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(transparent)]
+pub struct Transform(uint32_t);
+
+// required ElementBuilder field that has not been set yet
+pub struct Unset;
+
+// pending update, submitted when dropped without being committed, queued counts the changes
+// made through it so an uncommitted drop can be reported
+pub struct Update {
+    handle: UpdateHandle,
+    queued: usize
+}
+
 #[repr(C)]
 pub struct VCAlpha {
     pub flags:   FlagsAlpha,
@@ -197,6 +310,41 @@ pub struct VCAlpha {
     pub mask:    ResourceHandle
 }
 
+// a vsync as seen by the consumer, missed counts the vsyncs that happened since the previous
+// event was read
+#[derive(Clone, Copy, Debug)]
+pub struct VsyncEvent {
+    pub frame:     u64,
+    pub timestamp: Instant,
+    pub missed:    u64
+}
+
+// registered vsync closure, unregistered when dropped, a vsync already running keeps its own
+// reference so the closure outlives it
+pub struct VsyncGuard {
+    display:  DisplayHandle,
+    id:       usize,
+    callback: VsyncClosure
+}
+
+// consumer side of the vsync counter, last is the frame of the previous event read
+pub struct VsyncReceiver {
+    shared: Arc<VsyncShared>,
+    last:   u64
+}
+
+// producer side of the vsync counter, updated from the callback thread
+struct VsyncShared {
+    state: Mutex<(u64, Instant)>,
+    ready: Condvar
+}
+
+// vsync events of a display, the callback is unregistered when dropped
+pub struct VsyncStream {
+    receiver: VsyncReceiver,
+    guard:    VsyncGuard
+}
+
 #[repr(C)]
 pub struct Window {
     pub element: ElementHandle,
@@ -221,315 +369,1844 @@ pub const DISPMANX_ID_FORCE_LCD:   uint32_t = 4;
 pub const DISPMANX_ID_FORCE_TV:    uint32_t = 5;
 pub const DISPMANX_ID_FORCE_OTHER: uint32_t = 6; // non-default display
 
+pub const ELEMENT_CHANGE_LAYER:         uint32_t = 1 << 0;
+pub const ELEMENT_CHANGE_OPACITY:       uint32_t = 1 << 1;
+pub const ELEMENT_CHANGE_DEST_RECT:     uint32_t = 1 << 2;
+pub const ELEMENT_CHANGE_SRC_RECT:      uint32_t = 1 << 3;
+pub const ELEMENT_CHANGE_MASK_RESOURCE: uint32_t = 1 << 4;
+pub const ELEMENT_CHANGE_TRANSFORM:     uint32_t = 1 << 5;
+
 // -------------------------------------------------------------------------------------------------
-// FUNCTIONS
+// STATICS
+// -------------------------------------------------------------------------------------------------
+
+// id and closure of the registration the host library currently calls on vsync, the id is what
+// the host passes back as the callback argument
+static VSYNC_ACTIVE: Mutex<Option<(usize, VsyncClosure)>> = Mutex::new(None);
+
+static VSYNC_NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
 // -------------------------------------------------------------------------------------------------
 
-pub fn display_close(display: DisplayHandle) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_display_close(display) > 0
+impl Display {
+    pub fn open(device: uint32_t) -> Result<Display, Error> {
+        Ok(Display::from_raw(display_open(device)?))
     }
-}
 
-pub fn display_get_info(display: DisplayHandle, modeinfo: *mut Modeinfo) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_display_get_info(display, modeinfo) > 0
+    pub fn open_mode(device: uint32_t, mode: uint32_t) -> Result<Display, Error> {
+        Ok(Display::from_raw(display_open_mode(device, mode)?))
     }
-}
 
-pub fn display_open(device: uint32_t) -> DisplayHandle {
-    unsafe {
-        ffi::vc_dispmanx_display_open(device)
+    pub fn open_offscreen(dest: ResourceHandle, orientation: Transform) -> Result<Display, Error> {
+        Ok(Display::from_raw(display_open_offscreen(dest, orientation)?))
     }
-}
 
-pub fn display_open_mode(device: uint32_t, mode: uint32_t) -> DisplayHandle {
-    unsafe {
-        ffi::vc_dispmanx_display_open_mode(device, mode)
+    // takes ownership of a handle opened elsewhere, it will be closed when dropped
+    pub fn from_raw(handle: DisplayHandle) -> Display {
+        Display {
+            handle
+        }
     }
-}
 
-pub fn display_open_offscreen(dest: ResourceHandle, orientation: Transform) -> DisplayHandle {
-    unsafe {
-        ffi::vc_dispmanx_display_open_offscreen(dest, orientation)
+    pub fn as_raw(&self) -> DisplayHandle {
+        self.handle
     }
-}
 
-pub fn display_reconfigure(display: DisplayHandle, mode: uint32_t) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_display_reconfigure(display, mode) > 0
+    // releases ownership of the handle without closing it
+    pub fn into_raw(self) -> DisplayHandle {
+        let handle = self.handle;
+
+        mem::forget(self);
+
+        handle
     }
-}
 
-pub fn display_set_background(update: UpdateHandle, display: DisplayHandle,
-                              red: uint8_t, green: uint8_t, blue: uint8_t) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_display_set_background(update, display, red, green, blue) > 0
+    pub fn get_info(&self) -> Result<Modeinfo, Error> {
+        let mut modeinfo = Modeinfo {
+            width:        0,
+            height:       0,
+            transform:    Transform::NO_ROTATE,
+            input_format: InputFormat::INVALID
+        };
+
+        display_get_info(self.handle, &mut modeinfo)?;
+
+        Ok(modeinfo)
     }
-}
 
-pub fn display_set_destination(display: DisplayHandle, dest: ResourceHandle) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_display_set_destination(display, dest) > 0
+    // the closure is called from the firmware callback thread on every vsync until the returned
+    // guard is dropped
+    pub fn on_vsync<F>(&self, callback: F) -> Result<VsyncGuard, Error>
+        where F: FnMut() + Send + 'static {
+        register_vsync(self.handle, Arc::new(Mutex::new(callback)))
     }
-}
 
-pub fn element_add(update: UpdateHandle, display: DisplayHandle, layer: int32_t,
-                   dest_rect: *mut Rect, src: ResourceHandle, src_rect: *mut Rect,
-                   protection: Protection, alpha: *mut VCAlpha, clamp: *mut Clamp,
-                   transform: Transform) -> ElementHandle {
-    unsafe {
-        ffi::vc_dispmanx_element_add(update, display, layer, dest_rect, src, src_rect,
-                                     protection, alpha, clamp, transform)
+    pub fn reconfigure(&self, mode: uint32_t) -> Result<(), Error> {
+        display_reconfigure(self.handle, mode)
     }
-}
 
-pub fn element_change_attributes(update: UpdateHandle, element: ElementHandle,
-                                 change_flags: uint32_t, layer: int32_t, opacity: uint8_t,
-                                 dest_rect: *const Rect, src_rect: *const Rect,
-                                 mask: ResourceHandle, transform: Transform) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_element_change_attributes(update, element, change_flags, layer,
-                                                   opacity, dest_rect, src_rect,
-                                                   mask, transform) > 0
+    pub fn set_background(&self, update: &mut Update, red: uint8_t, green: uint8_t,
+                          blue: uint8_t) -> Result<(), Error> {
+        update.display_set_background(self, red, green, blue)
     }
-}
 
-pub fn element_change_layer(update: UpdateHandle, element: ElementHandle,
-                            layer: int32_t) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_element_change_layer(update, element, layer) > 0
+    pub fn set_destination(&self, dest: ResourceHandle) -> Result<(), Error> {
+        display_set_destination(self.handle, dest)
     }
-}
 
-pub fn element_change_source(update: UpdateHandle, element: ElementHandle,
-                             src: ResourceHandle) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_element_change_source(update, element, src) > 0
+    pub fn vsync_stream(&self) -> Result<VsyncStream, Error> {
+        VsyncStream::new(self.handle)
     }
 }
 
-pub fn element_modified(update: UpdateHandle, element: ElementHandle, rect: *mut Rect) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_element_modified(update, element, rect) > 0
+impl Drop for Display {
+    fn drop(&mut self) {
+        let _ = display_close(self.handle);
     }
 }
 
-pub fn element_remove(update: UpdateHandle, element: ElementHandle) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_element_remove(update, element) > 0
+impl Element {
+    pub fn from_raw(handle: ElementHandle, display: DisplayHandle) -> Element {
+        Element {
+            handle,
+            display
+        }
     }
-}
 
-pub fn query_image_formats(supported_formats: *mut uint32_t) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_query_image_formats(supported_formats) > 0
+    pub fn as_raw(&self) -> ElementHandle {
+        self.handle
     }
-}
 
-pub fn rect_set(rect: *mut Rect, x_offset: uint32_t, y_offset: uint32_t, width: uint32_t,
-                height: uint32_t) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_rect_set(rect, x_offset, y_offset, width, height) > 0
+    pub fn display(&self) -> DisplayHandle {
+        self.display
     }
 }
 
-pub fn resource_create(type_: ImageType, width: uint32_t, height: uint32_t,
-                       native_image_handle: *mut uint32_t) -> ResourceHandle {
-    unsafe {
-        ffi::vc_dispmanx_resource_create(type_, width, height, native_image_handle)
+impl ElementChange {
+    pub fn new() -> ElementChange {
+        ElementChange {
+            flags:     0,
+            layer:     0,
+            opacity:   0,
+            dest_rect: Rect::new(0, 0, 0, 0),
+            src_rect:  SrcRect::new(0, 0, 0, 0),
+            mask:      DISPMANX_NO_HANDLE,
+            transform: Transform::NO_ROTATE
+        }
     }
-}
 
-pub fn resource_delete(res: ResourceHandle) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_resource_delete(res) > 0
+    pub fn dest_rect(mut self, dest_rect: Rect) -> ElementChange {
+        self.flags    |= ELEMENT_CHANGE_DEST_RECT;
+        self.dest_rect = dest_rect;
+        self
     }
-}
 
-pub fn resource_read_data(res: ResourceHandle, rect: *const Rect, dst_address: *mut c_void,
-                          dst_pitch: uint32_t) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_resource_read_data(res, rect, dst_address, dst_pitch) > 0
+    pub fn flags(&self) -> uint32_t {
+        self.flags
     }
-}
 
-pub fn resource_set_palette(res: ResourceHandle, src_address: *mut c_void, offset: int32_t,
-                            size: int32_t) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_resource_set_palette(res, src_address, offset, size) > 0
+    pub fn layer(mut self, layer: int32_t) -> ElementChange {
+        self.flags |= ELEMENT_CHANGE_LAYER;
+        self.layer  = layer;
+        self
+    }
+
+    pub fn mask(mut self, mask: &Resource) -> ElementChange {
+        self.flags |= ELEMENT_CHANGE_MASK_RESOURCE;
+        self.mask   = mask.as_raw();
+        self
+    }
+
+    pub fn opacity(mut self, opacity: uint8_t) -> ElementChange {
+        self.flags  |= ELEMENT_CHANGE_OPACITY;
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn src_rect(mut self, src_rect: SrcRect) -> ElementChange {
+        self.flags   |= ELEMENT_CHANGE_SRC_RECT;
+        self.src_rect = src_rect;
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> ElementChange {
+        self.flags    |= ELEMENT_CHANGE_TRANSFORM;
+        self.transform = transform;
+        self
     }
 }
 
-pub fn resource_write_data(res: ResourceHandle, src_type: ImageType, src_pitch: int32_t,
-                           src_address: *mut c_void, rect: *const Rect) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_resource_write_data(res, src_type, src_pitch, src_address, rect) > 0
+impl Default for ElementChange {
+    fn default() -> ElementChange {
+        ElementChange::new()
     }
 }
 
-pub fn resource_write_data_handle(res: ResourceHandle, src_type: ImageType, src_pitch: int32_t,
-                                  handle: MemHandle, offset: uint32_t,
-                                  rect: *const Rect) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_resource_write_data_handle(res, src_type, src_pitch, handle, offset,
-                                                    rect) > 0
+impl<'a> ElementBuilder<'a, Unset, Unset> {
+    pub fn new() -> ElementBuilder<'a, Unset, Unset> {
+        ElementBuilder {
+            layer:      0,
+            dest:       Unset,
+            src:        None,
+            source:     Unset,
+            opacity:    255,
+            alpha_mode: FlagsAlpha::FROM_SOURCE,
+            alpha_mask: None,
+            clamp:      None,
+            protection: DISPMANX_PROTECTION_NONE,
+            transform:  Transform::NO_ROTATE
+        }
     }
 }
 
-pub fn snapshot(display: DisplayHandle, snapshot_resource: ResourceHandle,
-                transform: Transform) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_snapshot(display, snapshot_resource, transform) > 0
+impl<'a> Default for ElementBuilder<'a, Unset, Unset> {
+    fn default() -> ElementBuilder<'a, Unset, Unset> {
+        ElementBuilder::new()
     }
 }
 
-pub fn stop() {
-    unsafe {
-        ffi::vc_dispmanx_stop()
+impl<'a, D, S> ElementBuilder<'a, D, S> {
+    pub fn alpha_mask(mut self, mask: &'a Resource) -> ElementBuilder<'a, D, S> {
+        self.alpha_mask = Some(mask);
+        self
+    }
+
+    pub fn alpha_mode(mut self, alpha_mode: FlagsAlpha) -> ElementBuilder<'a, D, S> {
+        self.alpha_mode = alpha_mode;
+        self
+    }
+
+    pub fn clamp(mut self, clamp: Clamp) -> ElementBuilder<'a, D, S> {
+        self.clamp = Some(clamp);
+        self
+    }
+
+    // position on the display in pixels
+    pub fn dest(self, dest: Rect) -> ElementBuilder<'a, Rect, S> {
+        ElementBuilder {
+            layer:      self.layer,
+            dest,
+            src:        self.src,
+            source:     self.source,
+            opacity:    self.opacity,
+            alpha_mode: self.alpha_mode,
+            alpha_mask: self.alpha_mask,
+            clamp:      self.clamp,
+            protection: self.protection,
+            transform:  self.transform
+        }
+    }
+
+    pub fn layer(mut self, layer: int32_t) -> ElementBuilder<'a, D, S> {
+        self.layer = layer;
+        self
+    }
+
+    pub fn opacity(mut self, opacity: uint8_t) -> ElementBuilder<'a, D, S> {
+        self.opacity = opacity;
+        self
+    }
+
+    pub fn protection(mut self, protection: Protection) -> ElementBuilder<'a, D, S> {
+        self.protection = protection;
+        self
+    }
+
+    pub fn source(self, source: &'a Resource) -> ElementBuilder<'a, D, &'a Resource> {
+        ElementBuilder {
+            layer:      self.layer,
+            dest:       self.dest,
+            src:        self.src,
+            source,
+            opacity:    self.opacity,
+            alpha_mode: self.alpha_mode,
+            alpha_mask: self.alpha_mask,
+            clamp:      self.clamp,
+            protection: self.protection,
+            transform:  self.transform
+        }
+    }
+
+    // region of the source resource, defaults to the whole resource
+    pub fn src(mut self, src: SrcRect) -> ElementBuilder<'a, D, S> {
+        self.src = Some(src);
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> ElementBuilder<'a, D, S> {
+        self.transform = transform;
+        self
     }
 }
 
-pub fn update_start(priority: int32_t) -> UpdateHandle {
-    unsafe {
-        ffi::vc_dispmanx_update_start(priority)
+impl Error {
+    pub fn new(call: &'static str, kind: ErrorKind) -> Error {
+        Error {
+            call,
+            kind
+        }
+    }
+
+    // name of the firmware call that failed, or would have been made
+    pub fn call(&self) -> &'static str {
+        self.call
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
     }
 }
 
-pub fn update_submit(update: UpdateHandle, callback_func: CallbackFunc,
-                     callback_arg: *mut c_void) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_update_submit(update, callback_func, callback_arg) > 0
+impl error::Error for Error {
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::BufferTooSmall    => write!(f, "{}: buffer too small", self.call),
+            ErrorKind::InvalidFlags      => write!(f, "{}: invalid flags", self.call),
+            ErrorKind::InvalidPalette    => write!(f, "{}: invalid palette", self.call),
+            ErrorKind::InvalidPitch      => write!(f, "{}: invalid pitch", self.call),
+            ErrorKind::InvalidRect       => write!(f, "{}: invalid rect", self.call),
+            ErrorKind::NoHandle          => write!(f, "{}: no handle returned", self.call),
+            ErrorKind::NotPalettised     => write!(f, "{}: image is not palettised", self.call),
+            ErrorKind::Status(status)    => write!(f, "{}: failed with status {:?}", self.call,
+                                                   status),
+            ErrorKind::UnsupportedFormat => write!(f, "{}: unsupported image format", self.call)
+        }
     }
 }
 
-pub fn update_submit_sync(update: UpdateHandle) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_update_submit_sync(update) > 0
+impl FlagsAlpha {
+    // bottom 2 bits sets the alpha mode
+    pub const FROM_SOURCE:       FlagsAlpha = FlagsAlpha(0);
+    pub const FIXED_ALL_PIXELS:  FlagsAlpha = FlagsAlpha(1);
+    pub const FIXED_NON_ZERO:    FlagsAlpha = FlagsAlpha(2);
+    pub const FIXED_EXCEED_0X07: FlagsAlpha = FlagsAlpha(3);
+
+    pub const PREMULT: FlagsAlpha = FlagsAlpha(1 << 16);
+    pub const MIX:     FlagsAlpha = FlagsAlpha(1 << 17);
+
+    const ALL: uint32_t = 3 | 1 << 16 | 1 << 17;
+
+    pub fn alpha_source(&self) -> AlphaSource {
+        match self.0 & 3 {
+            0 => AlphaSource::FromSource,
+            1 => AlphaSource::FixedAllPixels,
+            2 => AlphaSource::FixedNonZero,
+            _ => AlphaSource::FixedExceed0x07
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.0 & !FlagsAlpha::ALL == 0
+    }
+
+    pub fn mix(&self) -> bool {
+        self.contains(FlagsAlpha::MIX)
+    }
+
+    pub fn premultiplied(&self) -> bool {
+        self.contains(FlagsAlpha::PREMULT)
     }
 }
 
-pub fn vsync_callback(display: DisplayHandle, callback_func: CallbackFunc,
-                      callback_arg: *mut c_void) -> bool {
-    unsafe {
-        ffi::vc_dispmanx_vsync_callback(display, callback_func, callback_arg) > 0
+flags!(FlagsAlpha);
+
+impl FlagsKeymask {
+    pub const OVERRIDE: FlagsKeymask = FlagsKeymask(1);
+    pub const SMOOTH:   FlagsKeymask = FlagsKeymask(1 << 1);
+    pub const CR_INV:   FlagsKeymask = FlagsKeymask(1 << 2);
+    pub const CB_INV:   FlagsKeymask = FlagsKeymask(1 << 3);
+    pub const YY_INV:   FlagsKeymask = FlagsKeymask(1 << 4);
+
+    const ALL: uint32_t = 0x1f;
+
+    pub fn is_valid(&self) -> bool {
+        self.0 & !FlagsKeymask::ALL == 0
     }
 }
 
-// -------------------------------------------------------------------------------------------------
-// FFI
-// -------------------------------------------------------------------------------------------------
+flags!(FlagsKeymask);
 
-mod ffi {
-    use libc::{ c_void,
-                int32_t,
-                uint8_t,
-                uint32_t };
+impl Resource {
+    pub fn create(type_: ImageType, width: uint32_t, height: uint32_t) -> Result<Resource, Error> {
+        let mut native_image_handle: uint32_t = 0;
 
-    use image::{ ImageType,
-                 Rect };
+        Ok(Resource {
+               handle: resource_create(type_, width, height, &mut native_image_handle)?,
+               type_,
+               width,
+               height
+           })
+    }
 
-    use vchi::MemHandle;
+    // decodes a PNG, BMP, TGA or PPM file into a new resource, returning its size
+    pub fn from_file<P: AsRef<Path>>(path: P, options: &decode::Options)
+                                     -> Result<(Resource, uint32_t, uint32_t), decode::Error> {
+        Resource::from_reader(BufReader::new(File::open(path)?), options)
+    }
 
-    use super::*;
+    // takes ownership of a handle created elsewhere, it will be deleted when dropped
+    pub fn from_raw(handle: ResourceHandle, type_: ImageType, width: uint32_t,
+                    height: uint32_t) -> Resource {
+        Resource {
+            handle,
+            type_,
+            width,
+            height
+        }
+    }
 
-    extern {
-        // deprecated
-        pub fn vc_dispman_init() -> int32_t;
+    pub fn from_reader<R: Read>(reader: R, options: &decode::Options)
+                                -> Result<(Resource, uint32_t, uint32_t), decode::Error> {
+        let Decoded { width, height, type_, pitch, data } = decode::decode(reader, options)?;
 
-        pub fn vc_dispmanx_display_close(display: DisplayHandle) -> int32_t;
+        let resource = Resource::create(type_, width, height)?;
 
-        pub fn vc_dispmanx_display_get_info(display: DisplayHandle,
-                                            pinfo: *mut Modeinfo) -> int32_t;
+        resource.write(&data, pitch, &Rect::from_size(width, height))?;
 
-        pub fn vc_dispmanx_display_open(device: uint32_t) -> DisplayHandle;
+        Ok((resource, width, height))
+    }
 
-        pub fn vc_dispmanx_display_open_mode(device: uint32_t, mode: uint32_t) -> DisplayHandle;
+    pub fn as_raw(&self) -> ResourceHandle {
+        self.handle
+    }
 
-        pub fn vc_dispmanx_display_open_offscreen(dest: ResourceHandle,
-                                                  orientation: Transform) -> DisplayHandle;
+    // releases ownership of the handle without deleting it
+    pub fn into_raw(self) -> ResourceHandle {
+        let handle = self.handle;
 
-        pub fn vc_dispmanx_display_reconfigure(display: DisplayHandle, mode: uint32_t) -> int32_t;
+        mem::forget(self);
 
-        pub fn vc_dispmanx_display_set_background(update: UpdateHandle,
-                                                  display: DisplayHandle,
-                                                  red: uint8_t, green: uint8_t,
-                                                  blue: uint8_t) -> int32_t;
+        handle
+    }
 
-        pub fn vc_dispmanx_display_set_destination(display: DisplayHandle,
-                                                   dest: ResourceHandle) -> int32_t;
+    pub fn height(&self) -> uint32_t {
+        self.height
+    }
 
-        pub fn vc_dispmanx_element_add(update: UpdateHandle, display: DisplayHandle,
-                                       layer: int32_t, dest_rect: *mut Rect,
-                                       src: ResourceHandle, src_rect: *mut Rect,
-                                       protection: Protection, alpha: *mut VCAlpha,
-                                       clamp: *mut Clamp, transform: Transform) -> ElementHandle;
+    pub fn image_type(&self) -> ImageType {
+        self.type_
+    }
 
-        pub fn vc_dispmanx_element_change_attributes(update: UpdateHandle,
-                                                     element: ElementHandle,
-                                                     change_flags: uint32_t, layer: int32_t,
-                                                     opacity: uint8_t, dest_rect: *const Rect,
-                                                     src_rect: *const Rect, mask: ResourceHandle,
-                                                     transform: Transform) -> int32_t;
+    pub fn width(&self) -> uint32_t {
+        self.width
+    }
 
-        pub fn vc_dispmanx_element_change_layer(update: UpdateHandle, element: ElementHandle,
-                                                layer: int32_t) -> int32_t;
+    pub fn read(&self, data: &mut [u8], pitch: uint32_t, rect: &Rect) -> Result<(), Error> {
+        self.validate("vc_dispmanx_resource_read_data", data.len(), pitch, rect)?;
 
-        pub fn vc_dispmanx_element_change_source(update: UpdateHandle, element: ElementHandle,
-                                                 src: ResourceHandle) -> int32_t;
+        resource_read_data(self.handle, rect, data.as_mut_ptr() as *mut c_void, pitch)
+    }
 
-        pub fn vc_dispmanx_element_modified(update: UpdateHandle, element: ElementHandle,
-                                            rect: *mut Rect) -> int32_t;
+    // the planes of a YUV420 resource laid out as vc_image keeps them, the luma rows padded to a
+    // multiple of 16 and then the U and V planes at half the pitch. the firmware transfers
+    // pitch * rect.height bytes from row rect.y, so each plane is read on its own as the luma
+    // rows it spans from its offset, the luma pitch is returned
+    pub fn read_planes(&self, data: &mut [u8]) -> Result<uint32_t, Error> {
+        let call = "vc_dispmanx_resource_read_data";
 
-        pub fn vc_dispmanx_element_remove(update: UpdateHandle, element: ElementHandle) -> int32_t;
+        if self.type_ != ImageType::YUV420 {
+            return Err(Error::new(call, ErrorKind::UnsupportedFormat));
+        }
 
-        pub fn vc_dispmanx_query_image_formats(supported_formats: *mut uint32_t) -> int32_t;
+        let (pitch, rows) = match (self.type_.required_pitch(self.width),
+                                   self.type_.resource_rows(self.height)) {
+            (Some(pitch), Some(rows)) => (pitch, rows),
+            _                         => {
+                return Err(Error::new(call, ErrorKind::UnsupportedFormat))
+            }
+        };
 
-        pub fn vc_dispmanx_rect_set(rect: *mut Rect, x_offset: uint32_t, y_offset: uint32_t,
-                                    width: uint32_t, height: uint32_t) -> int32_t;
+        // a chroma plane is half the pitch and half the rows, so a quarter of the luma rows
+        let chroma = rows / 4;
 
-        pub fn vc_dispmanx_resource_create(type_: ImageType, width: uint32_t, height: uint32_t,
-                                           native_image_handle: *mut uint32_t) -> ResourceHandle;
+        if data.len() < pitch as usize * (rows + 2 * chroma) as usize {
+            return Err(Error::new(call, ErrorKind::BufferTooSmall));
+        }
 
-        pub fn vc_dispmanx_resource_delete(res: ResourceHandle) -> int32_t;
+        for &(y, height) in &[(0, self.height), (rows, chroma), (rows + chroma, chroma)] {
+            let rect = Rect::new(0, y as int32_t, self.width as int32_t, height as int32_t);
 
-        // deprecated
-        pub fn vc_dispmanx_resource_get_image_handle(res: ResourceHandle) -> uint32_t;
+            resource_read_data(self.handle, &rect, data.as_mut_ptr() as *mut c_void, pitch)?;
+        }
 
-        pub fn vc_dispmanx_resource_read_data(handle: ResourceHandle, p_rect: *const Rect,
-                                              dst_address: *mut c_void,
-                                              dst_pitch: uint32_t) -> int32_t;
+        Ok(pitch)
+    }
 
-        pub fn vc_dispmanx_resource_set_palette(handle: ResourceHandle,
-                                                src_address: *mut c_void, offset: int32_t,
-                                                size: int32_t) -> int32_t;
+    // palette entries are RGB565, offset is the index of the first entry to replace
+    pub fn set_palette(&self, palette: &[u16], offset: usize) -> Result<(), Error> {
+        let call = "vc_dispmanx_resource_set_palette";
 
-        pub fn vc_dispmanx_resource_write_data(res: ResourceHandle, src_type: ImageType,
-                                               src_pitch: int32_t, src_address: *mut c_void,
-                                               rect: *const Rect) -> int32_t;
+        let entries = match self.type_ {
+            ImageType::_4BPP | ImageType::TF_PAL4 => 16,
+            ImageType::_8BPP | ImageType::TF_PAL8 => 256,
+            _                                     => {
+                return Err(Error::new(call, ErrorKind::NotPalettised))
+            }
+        };
 
-        pub fn vc_dispmanx_resource_write_data_handle(res: ResourceHandle, src_type: ImageType,
-                                                      src_pitch: int32_t, handle: MemHandle,
-                                                      offset: uint32_t,
-                                                      rect: *const Rect) -> int32_t;
+        if palette.is_empty() || offset + palette.len() > entries {
+            return Err(Error::new(call, ErrorKind::InvalidPalette));
+        }
 
-        pub fn vc_dispmanx_snapshot(display: DisplayHandle,
-                                    snapshot_resource: ResourceHandle,
-                                    transform: Transform) -> int32_t;
+        resource_set_palette(self.handle, palette.as_ptr() as *mut c_void,
+                             (offset * 2) as int32_t, (palette.len() * 2) as int32_t)
+    }
 
-        pub fn vc_dispmanx_stop();
+    pub fn write(&self, data: &[u8], pitch: uint32_t, rect: &Rect) -> Result<(), Error> {
+        self.validate("vc_dispmanx_resource_write_data", data.len(), pitch, rect)?;
 
-        pub fn vc_dispmanx_update_start(priority: int32_t) -> UpdateHandle;
+        resource_write_data(self.handle, self.type_, pitch as int32_t,
+                            data.as_ptr() as *mut c_void, rect)
+    }
 
-        pub fn vc_dispmanx_update_submit(update: UpdateHandle, cb_func: CallbackFunc,
-                                         cb_arg: *mut c_void) -> int32_t;
+    // uploads the palette and one index per pixel covering the whole resource. TF_PAL4 and TF_PAL8
+    // indices are packed like 4BPP and 8BPP then tiled, and sent as one block with the pitch
+    // stretched so the `pitch * height` bytes the firmware transfers take in every tile
+    pub fn write_indexed(&self, palette: &Palette, indices: &[u8]) -> Result<(), Error> {
+        let call = "vc_dispmanx_resource_write_data";
 
-        pub fn vc_dispmanx_update_submit_sync(update: UpdateHandle) -> int32_t;
+        if !self.type_.is_palettised() {
+            return Err(Error::new(call, ErrorKind::NotPalettised));
+        }
 
-        pub fn vc_dispmanx_vsync_callback(display: DisplayHandle, cb_func: CallbackFunc,
-                                          cb_arg: *mut c_void) -> int32_t;
+        if indices.iter().any(|&index| index as usize >= palette.len()) {
+            return Err(Error::new(call, ErrorKind::InvalidPalette));
+        }
 
-        // call this instead of vc_dispman_init()
-        //pub fn vc_vchi_dispmanx_init(VCHI_INSTANCE_T initialise_instance, VCHI_CONNECTION_T **connections, uint32_t num_connections );
+        let convert_error = |error| {
+            let kind = if error == convert::Error::BufferTooSmall {
+                ErrorKind::BufferTooSmall
+            } else {
+                ErrorKind::UnsupportedFormat
+            };
+
+            Error::new(call, kind)
+        };
+
+        let raster = match self.type_ {
+            ImageType::TF_PAL4 => ImageType::_4BPP,
+            ImageType::TF_PAL8 => ImageType::_8BPP,
+            type_              => type_
+        };
+
+        let pitch = raster.required_pitch(self.width)
+                          .ok_or_else(|| Error::new(call, ErrorKind::UnsupportedFormat))?;
+
+        let mut data = vec![0; pitch as usize * self.height as usize];
+
+        palette::pack(indices, &mut data, Layout::new(raster, pitch as usize), self.width,
+                      self.height).map_err(convert_error)?;
+
+        let rect = Rect {
+            x:      0,
+            y:      0,
+            width:  self.width as i32,
+            height: self.height as i32
+        };
+
+        if !self.type_.is_tformat() {
+            self.set_palette(palette.entries(), 0)?;
+
+            return self.write(&data, pitch, &rect);
+        }
+
+        let tiling = tformat::tiling(self.type_, self.width, self.height)
+                             .ok_or_else(|| Error::new(call, ErrorKind::UnsupportedFormat))?;
+
+        let size = tformat::size(self.type_, self.width, self.height, tiling).unwrap();
+
+        let tiled_pitch = uint32_t::try_from(size.div_ceil(self.height as usize))
+                                   .map_err(|_| Error::new(call, ErrorKind::UnsupportedFormat))?;
+
+        let mut tiled = vec![0; tiled_pitch as usize * self.height as usize];
+
+        tformat::encode(&data, pitch as usize, &mut tiled, self.type_, self.width, self.height,
+                        tiling).map_err(convert_error)?;
+
+        self.set_palette(palette.entries(), 0)?;
+
+        resource_write_data(self.handle, self.type_, tiled_pitch as int32_t,
+                            tiled.as_mut_ptr() as *mut c_void, &rect)
+    }
+
+    // the firmware transfers `pitch * rect.height` bytes starting at row `rect.y` of the buffer
+    fn validate(&self, call: &'static str, len: usize, pitch: uint32_t,
+                rect: &Rect) -> Result<(), Error> {
+        // only single plane raster images can be addressed by rect and pitch
+        let bits = match self.type_.planes() {
+            [plane] if !self.type_.is_tformat() => plane.bits_per_pixel as u64,
+            _                                   => {
+                return Err(Error::new(call, ErrorKind::UnsupportedFormat))
+            }
+        };
+
+        if rect.x < 0 || rect.y < 0 || rect.width <= 0 || rect.height <= 0
+           || rect.x as u64 + rect.width as u64 > self.width as u64
+           || rect.y as u64 + rect.height as u64 > self.height as u64 {
+            return Err(Error::new(call, ErrorKind::InvalidRect));
+        }
+
+        // host rows are laid out like the resource's so they have to reach the right of the rect
+        if (pitch as u64) < ((rect.x as u64 + rect.width as u64) * bits).div_ceil(8)
+           || pitch > i32::MAX as u32 {
+            return Err(Error::new(call, ErrorKind::InvalidPitch));
+        }
+
+        if (len as u64) < pitch as u64 * (rect.y as u64 + rect.height as u64) {
+            return Err(Error::new(call, ErrorKind::BufferTooSmall));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Resource {
+    fn drop(&mut self) {
+        let _ = resource_delete(self.handle);
+    }
+}
+
+impl Transform {
+    pub const NO_ROTATE:  Transform = Transform(0);
+    pub const ROTATE_90:  Transform = Transform(1);
+    pub const ROTATE_180: Transform = Transform(2);
+    pub const ROTATE_270: Transform = Transform(3);
+
+    pub const FLIP_HRIZ: Transform = Transform(1 << 16);
+    pub const FLIP_VERT: Transform = Transform(1 << 17);
+
+    // extra flags for controlling snapshot behaviour
+    pub const SNAPSHOT_NO_YUV:        Transform = Transform(1 << 24);
+    pub const SNAPSHOT_NO_RGB:        Transform = Transform(1 << 25);
+    pub const SNAPSHOT_FILL:          Transform = Transform(1 << 26);
+    pub const SNAPSHOT_SWAP_RED_BLUE: Transform = Transform(1 << 27);
+    pub const SNAPSHOT_PACK:          Transform = Transform(1 << 28);
+
+    const ALL:         uint32_t = 3 | 3 << 16 | 0x1f << 24;
+    const ORIENTATION: uint32_t = 3 | 3 << 16;
+
+    pub fn flip_horizontal(&self) -> bool {
+        self.contains(Transform::FLIP_HRIZ)
+    }
+
+    pub fn flip_vertical(&self) -> bool {
+        self.contains(Transform::FLIP_VERT)
+    }
+
+    // the snapshot flags are kept
+    pub fn inverse(&self) -> Transform {
+        self.with_orientation(ImageTransform::from(*self).inverse())
+    }
+
+    // a snapshot excluding both yuv and rgb layers would capture nothing
+    pub fn is_valid(&self) -> bool {
+        self.0 & !Transform::ALL == 0
+        && !self.contains(Transform::SNAPSHOT_NO_YUV | Transform::SNAPSHOT_NO_RGB)
+    }
+
+    // where a corner of the unit square ends up, rotating clockwise before flipping
+    fn map(&self, u: int32_t, v: int32_t) -> (int32_t, int32_t) {
+        let (u, v) = match self.rotation() {
+            Rotation::Rotate0   => (u, v),
+            Rotation::Rotate90  => (1 - v, u),
+            Rotation::Rotate180 => (1 - u, 1 - v),
+            Rotation::Rotate270 => (v, 1 - u)
+        };
+
+        (if self.flip_horizontal() { 1 - u } else { u },
+         if self.flip_vertical() { 1 - v } else { v })
+    }
+
+    pub fn rotation(&self) -> Rotation {
+        match self.0 & 3 {
+            0 => Rotation::Rotate0,
+            1 => Rotation::Rotate90,
+            2 => Rotation::Rotate180,
+            _ => Rotation::Rotate270
+        }
+    }
+
+    // applies this orientation and then the next one, keeping the snapshot flags of both
+    pub fn then(&self, next: Transform) -> Transform {
+        let orientation = ImageTransform::from(*self).then(ImageTransform::from(next));
+
+        Transform((self.0 | next.0) & !Transform::ORIENTATION).with_orientation(orientation)
+    }
+
+    fn with_orientation(&self, orientation: ImageTransform) -> Transform {
+        Transform(self.0 & !Transform::ORIENTATION | Transform::from(orientation).0)
+    }
+}
+
+flags!(Transform);
+
+// every orientation has one encoding without a vertical flip
+impl From<ImageTransform> for Transform {
+    fn from(transform: ImageTransform) -> Transform {
+        (0..8).map(|i| Transform(i & 3 | (i >> 2) << 16))
+              .find(|&candidate| ImageTransform::from(candidate) == transform)
+              .unwrap_or(Transform::NO_ROTATE)
+    }
+}
+
+// the snapshot flags are dropped
+impl From<Transform> for ImageTransform {
+    fn from(transform: Transform) -> ImageTransform {
+        ImageTransform::from_map(|u, v| transform.map(u, v))
+    }
+}
+
+impl Update {
+    // takes ownership of an update started elsewhere
+    pub fn from_raw(handle: UpdateHandle) -> Update {
+        Update {
+            handle,
+            queued: 0
+        }
+    }
+
+    pub fn as_raw(&self) -> UpdateHandle {
+        self.handle
+    }
+
+    // releases ownership of the update without submitting it
+    pub fn into_raw(self) -> UpdateHandle {
+        let handle = self.handle;
+
+        mem::forget(self);
+
+        handle
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued
+    }
+
+    pub fn display_set_background(&mut self, display: &Display, red: uint8_t, green: uint8_t,
+                                  blue: uint8_t) -> Result<(), Error> {
+        self.queued += 1;
+
+        display_set_background(self.handle, display.as_raw(), red, green, blue)
+    }
+
+    pub fn element_add(&mut self, display: &Display,
+                       element: ElementBuilder<Rect, &Resource>) -> Result<Element, Error> {
+        let ElementBuilder { layer, mut dest, src, source, opacity, alpha_mode, alpha_mask,
+                             clamp, protection, transform } = element;
+
+        check_flags("vc_dispmanx_element_add",
+                    alpha_mode.is_valid()
+                    && clamp.as_ref().is_none_or(|clamp| clamp.key_mask.is_valid()))?;
+
+        let mut src = src.unwrap_or(SrcRect::from_size(source.width(), source.height()));
+
+        let mut alpha = VCAlpha {
+            flags:   alpha_mode,
+            opacity: opacity as uint32_t,
+            mask:    alpha_mask.map_or(DISPMANX_NO_HANDLE, |mask| mask.as_raw())
+        };
+
+        let mut clamp = clamp;
+
+        let clamp = clamp.as_mut().map_or(ptr::null_mut(), |clamp| clamp as *mut Clamp);
+
+        self.queued += 1;
+
+        let handle = element_add(self.handle, display.as_raw(), layer, &mut dest,
+                                 source.as_raw(), &mut src as *mut SrcRect as *mut Rect,
+                                 protection, &mut alpha, clamp, transform)?;
+
+        Ok(Element {
+               handle,
+               display: display.as_raw()
+           })
+    }
+
+    pub fn element_change_attributes(&mut self, element: &Element,
+                                     change: &ElementChange) -> Result<(), Error> {
+        if change.flags == 0 {
+            return Ok(());
+        }
+
+        let dest_rect = if change.flags & ELEMENT_CHANGE_DEST_RECT != 0 {
+            &change.dest_rect as *const Rect
+        } else {
+            ptr::null()
+        };
+
+        let src_rect = if change.flags & ELEMENT_CHANGE_SRC_RECT != 0 {
+            &change.src_rect as *const SrcRect as *const Rect
+        } else {
+            ptr::null()
+        };
+
+        self.queued += 1;
+
+        element_change_attributes(self.handle, element.handle, change.flags, change.layer,
+                                  change.opacity, dest_rect, src_rect, change.mask,
+                                  change.transform)
+    }
+
+    pub fn element_change_layer(&mut self, element: &Element, layer: int32_t) -> Result<(), Error> {
+        self.queued += 1;
+
+        element_change_layer(self.handle, element.handle, layer)
+    }
+
+    pub fn element_change_source(&mut self, element: &Element,
+                                 src: &Resource) -> Result<(), Error> {
+        self.queued += 1;
+
+        element_change_source(self.handle, element.handle, src.as_raw())
+    }
+
+    pub fn element_modified(&mut self, element: &Element, rect: &Rect) -> Result<(), Error> {
+        self.queued += 1;
+
+        element_modified(self.handle, element.handle, rect as *const Rect as *mut Rect)
+    }
+
+    pub fn element_remove(&mut self, element: Element) -> Result<(), Error> {
+        self.queued += 1;
+
+        element_remove(self.handle, element.handle)
+    }
+
+    pub fn submit(self, callback_func: CallbackFunc,
+                  callback_arg: *mut c_void) -> Result<(), Error> {
+        update_submit(self.into_raw(), callback_func, callback_arg)
+    }
+
+    pub fn submit_sync(self) -> Result<(), Error> {
+        update_submit_sync(self.into_raw())
+    }
+
+    // the closure is called from the firmware callback thread once the update has been applied
+    pub fn submit_with<F>(self, callback: F) -> Result<(), Error>
+        where F: FnOnce(UpdateHandle) + Send + 'static {
+        let callback: Box<UpdateClosure> = Box::new(Box::new(callback));
+        let arg = Box::into_raw(callback) as *mut c_void;
+
+        match update_submit(self.into_raw(), update_trampoline, arg) {
+            Ok(()) => Ok(()),
+
+            Err(error) => {
+                drop(unsafe {
+                    Box::from_raw(arg as *mut UpdateClosure)
+                });
+
+                Err(error)
+            }
+        }
+    }
+}
+
+// an update cannot be cancelled once started, so the firmware is given whatever was queued
+// rather than being left waiting on it. this happens silently, callers that want to know about
+// changes applied this way check queued() on the paths that bail out early
+impl Drop for Update {
+    fn drop(&mut self) {
+        let _ = update_submit_sync(self.handle);
+    }
+}
+
+impl VsyncReceiver {
+    fn new() -> (Arc<VsyncShared>, VsyncReceiver) {
+        let shared = Arc::new(VsyncShared {
+                                  state: Mutex::new((0, Instant::now())),
+                                  ready: Condvar::new()
+                              });
+
+        let receiver = VsyncReceiver {
+            shared: shared.clone(),
+            last:   0
+        };
+
+        (shared, receiver)
+    }
+
+    pub fn try_recv(&mut self) -> Option<VsyncEvent> {
+        let state = *self.shared.state.lock().unwrap();
+
+        self.event(state)
+    }
+
+    pub fn wait(&mut self) -> VsyncEvent {
+        let state = {
+            let mut state = self.shared.state.lock().unwrap();
+
+            while state.0 == self.last {
+                state = self.shared.ready.wait(state).unwrap();
+            }
+
+            *state
+        };
+
+        self.event(state).unwrap()
+    }
+
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<VsyncEvent> {
+        let last = self.last;
+
+        let state = {
+            let state = self.shared.state.lock().unwrap();
+
+            *self.shared.ready.wait_timeout_while(state, timeout, |state| state.0 == last)
+                              .unwrap().0
+        };
+
+        self.event(state)
+    }
+
+    fn event(&mut self, (frame, timestamp): (u64, Instant)) -> Option<VsyncEvent> {
+        if frame == self.last {
+            return None;
+        }
+
+        let missed = frame - self.last - 1;
+
+        self.last = frame;
+
+        Some(VsyncEvent {
+                 frame,
+                 timestamp,
+                 missed
+             })
+    }
+}
+
+impl Iterator for VsyncReceiver {
+    type Item = VsyncEvent;
+
+    fn next(&mut self) -> Option<VsyncEvent> {
+        Some(self.wait())
+    }
+}
+
+impl VsyncShared {
+    fn tick(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        state.0 += 1;
+        state.1  = Instant::now();
+
+        self.ready.notify_all();
+    }
+}
+
+impl VsyncStream {
+    pub fn new(display: DisplayHandle) -> Result<VsyncStream, Error> {
+        let (shared, receiver) = VsyncReceiver::new();
+
+        let guard = register_vsync(display, Arc::new(Mutex::new(move || shared.tick())))?;
+
+        Ok(VsyncStream {
+               receiver,
+               guard
+           })
+    }
+
+    pub fn try_recv(&mut self) -> Option<VsyncEvent> {
+        self.receiver.try_recv()
+    }
+
+    pub fn wait(&mut self) -> VsyncEvent {
+        self.receiver.wait()
+    }
+
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<VsyncEvent> {
+        self.receiver.wait_timeout(timeout)
+    }
+}
+
+impl Iterator for VsyncStream {
+    type Item = VsyncEvent;
+
+    fn next(&mut self) -> Option<VsyncEvent> {
+        Some(self.wait())
+    }
+}
+
+impl VsyncGuard {
+    // false once a later registration has replaced this closure
+    pub fn is_active(&self) -> bool {
+        VSYNC_ACTIVE.lock().unwrap().as_ref().is_some_and(|&(id, _)| id == self.id)
+    }
+}
+
+// a guard that was replaced by a later registration leaves the newer callback in place, the
+// closure itself is freed by whichever of the guard and a running vsync lets go of it last
+impl Drop for VsyncGuard {
+    fn drop(&mut self) {
+        let mut active = VSYNC_ACTIVE.lock().unwrap();
+
+        if active.as_ref().is_some_and(|&(id, _)| id == self.id) {
+            let _ = vsync_callback(self.display, None, ptr::null_mut());
+
+            *active = None;
+        }
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// the firmware returns 0 on success and -1 on failure
+fn check(call: &'static str, status: int32_t) -> Result<(), Error> {
+    if status == Status::SUCCESS as int32_t {
+        Ok(())
+    } else {
+        Err(Error::new(call, ErrorKind::Status(Status::INVALID)))
+    }
+}
+
+fn check_handle(call: &'static str, handle: uint32_t) -> Result<uint32_t, Error> {
+    if handle == DISPMANX_NO_HANDLE {
+        Err(Error::new(call, ErrorKind::NoHandle))
+    } else {
+        Ok(handle)
+    }
+}
+
+fn check_flags(call: &'static str, valid: bool) -> Result<(), Error> {
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::new(call, ErrorKind::InvalidFlags))
+    }
+}
+
+pub fn display_close(display: DisplayHandle) -> Result<(), Error> {
+    let status = backend::get().display_close(display);
+
+    check("vc_dispmanx_display_close", status)
+}
+
+pub fn display_get_info(display: DisplayHandle, modeinfo: *mut Modeinfo) -> Result<(), Error> {
+    let status = backend::get().display_get_info(display, modeinfo);
+
+    check("vc_dispmanx_display_get_info", status)
+}
+
+pub fn display_open(device: uint32_t) -> Result<DisplayHandle, Error> {
+    let handle = backend::get().display_open(device);
+
+    check_handle("vc_dispmanx_display_open", handle)
+}
+
+pub fn display_open_mode(device: uint32_t, mode: uint32_t) -> Result<DisplayHandle, Error> {
+    let handle = backend::get().display_open_mode(device, mode);
+
+    check_handle("vc_dispmanx_display_open_mode", handle)
+}
+
+pub fn display_open_offscreen(dest: ResourceHandle,
+                              orientation: Transform) -> Result<DisplayHandle, Error> {
+    check_flags("vc_dispmanx_display_open_offscreen", orientation.is_valid())?;
+
+    let handle = backend::get().display_open_offscreen(dest, orientation);
+
+    check_handle("vc_dispmanx_display_open_offscreen", handle)
+}
+
+pub fn display_reconfigure(display: DisplayHandle, mode: uint32_t) -> Result<(), Error> {
+    let status = backend::get().display_reconfigure(display, mode);
+
+    check("vc_dispmanx_display_reconfigure", status)
+}
+
+pub fn display_set_background(update: UpdateHandle, display: DisplayHandle,
+                              red: uint8_t, green: uint8_t, blue: uint8_t) -> Result<(), Error> {
+    let status = backend::get().display_set_background(update, display, red, green, blue);
+
+    check("vc_dispmanx_display_set_background", status)
+}
+
+pub fn display_set_destination(display: DisplayHandle, dest: ResourceHandle) -> Result<(), Error> {
+    let status = backend::get().display_set_destination(display, dest);
+
+    check("vc_dispmanx_display_set_destination", status)
+}
+
+pub fn element_add(update: UpdateHandle, display: DisplayHandle, layer: int32_t,
+                   dest_rect: *mut Rect, src: ResourceHandle, src_rect: *mut Rect,
+                   protection: Protection, alpha: *mut VCAlpha, clamp: *mut Clamp,
+                   transform: Transform) -> Result<ElementHandle, Error> {
+    check_flags("vc_dispmanx_element_add", transform.is_valid())?;
+
+    let handle = backend::get().element_add(update, display, layer, dest_rect, src, src_rect,
+                                            protection, alpha, clamp, transform);
+
+    check_handle("vc_dispmanx_element_add", handle)
+}
+
+pub fn element_change_attributes(update: UpdateHandle, element: ElementHandle,
+                                 change_flags: uint32_t, layer: int32_t, opacity: uint8_t,
+                                 dest_rect: *const Rect, src_rect: *const Rect,
+                                 mask: ResourceHandle, transform: Transform) -> Result<(), Error> {
+    check_flags("vc_dispmanx_element_change_attributes", transform.is_valid())?;
+
+    let status = backend::get().element_change_attributes(update, element, change_flags, layer,
+                                                          opacity, dest_rect, src_rect, mask,
+                                                          transform);
+
+    check("vc_dispmanx_element_change_attributes", status)
+}
+
+pub fn element_change_layer(update: UpdateHandle, element: ElementHandle,
+                            layer: int32_t) -> Result<(), Error> {
+    let status = backend::get().element_change_layer(update, element, layer);
+
+    check("vc_dispmanx_element_change_layer", status)
+}
+
+pub fn element_change_source(update: UpdateHandle, element: ElementHandle,
+                             src: ResourceHandle) -> Result<(), Error> {
+    let status = backend::get().element_change_source(update, element, src);
+
+    check("vc_dispmanx_element_change_source", status)
+}
+
+pub fn element_modified(update: UpdateHandle, element: ElementHandle,
+                        rect: *mut Rect) -> Result<(), Error> {
+    let status = backend::get().element_modified(update, element, rect);
+
+    check("vc_dispmanx_element_modified", status)
+}
+
+pub fn element_remove(update: UpdateHandle, element: ElementHandle) -> Result<(), Error> {
+    let status = backend::get().element_remove(update, element);
+
+    check("vc_dispmanx_element_remove", status)
+}
+
+pub fn query_image_formats(supported_formats: *mut uint32_t) -> Result<(), Error> {
+    let status = backend::get().query_image_formats(supported_formats);
+
+    check("vc_dispmanx_query_image_formats", status)
+}
+
+// fills the rect in rust, the firmware call does nothing more than this
+pub fn rect_set(rect: &mut Rect, x_offset: uint32_t, y_offset: uint32_t, width: uint32_t,
+                height: uint32_t) -> Result<(), Error> {
+    let field = |value: uint32_t| {
+        int32_t::try_from(value).map_err(|_| Error::new("vc_dispmanx_rect_set",
+                                                        ErrorKind::InvalidRect))
+    };
+
+    *rect = Rect::new(field(x_offset)?, field(y_offset)?, field(width)?, field(height)?);
+
+    Ok(())
+}
+
+// the host library keeps a single vsync callback per process, registering replaces any previous
+// one and makes it the active registration
+fn register_vsync(display: DisplayHandle, callback: VsyncClosure) -> Result<VsyncGuard, Error> {
+    let id = VSYNC_NEXT_ID.fetch_add(1, Ordering::Relaxed);
+
+    let mut active = VSYNC_ACTIVE.lock().unwrap();
+
+    vsync_callback(display, Some(vsync_trampoline), id as *mut c_void)?;
+
+    *active = Some((id, callback.clone()));
+
+    Ok(VsyncGuard {
+           display,
+           id,
+           callback
+       })
+}
+
+pub fn resource_create(type_: ImageType, width: uint32_t, height: uint32_t,
+                       native_image_handle: *mut uint32_t) -> Result<ResourceHandle, Error> {
+    let handle = backend::get().resource_create(type_, width, height, native_image_handle);
+
+    check_handle("vc_dispmanx_resource_create", handle)
+}
+
+pub fn resource_delete(res: ResourceHandle) -> Result<(), Error> {
+    let status = backend::get().resource_delete(res);
+
+    check("vc_dispmanx_resource_delete", status)
+}
+
+pub fn resource_read_data(res: ResourceHandle, rect: *const Rect, dst_address: *mut c_void,
+                          dst_pitch: uint32_t) -> Result<(), Error> {
+    let status = backend::get().resource_read_data(res, rect, dst_address, dst_pitch);
+
+    check("vc_dispmanx_resource_read_data", status)
+}
+
+pub fn resource_set_palette(res: ResourceHandle, src_address: *mut c_void, offset: int32_t,
+                            size: int32_t) -> Result<(), Error> {
+    let status = backend::get().resource_set_palette(res, src_address, offset, size);
+
+    check("vc_dispmanx_resource_set_palette", status)
+}
+
+pub fn resource_write_data(res: ResourceHandle, src_type: ImageType, src_pitch: int32_t,
+                           src_address: *mut c_void, rect: *const Rect) -> Result<(), Error> {
+    let status = backend::get().resource_write_data(res, src_type, src_pitch, src_address, rect);
+
+    check("vc_dispmanx_resource_write_data", status)
+}
+
+pub fn resource_write_data_handle(res: ResourceHandle, src_type: ImageType, src_pitch: int32_t,
+                                  handle: MemHandle, offset: uint32_t,
+                                  rect: *const Rect) -> Result<(), Error> {
+    let status = backend::get().resource_write_data_handle(res, src_type, src_pitch, handle,
+                                                           offset, rect);
+
+    check("vc_dispmanx_resource_write_data_handle", status)
+}
+
+pub fn snapshot(display: DisplayHandle, snapshot_resource: ResourceHandle,
+                transform: Transform) -> Result<(), Error> {
+    check_flags("vc_dispmanx_snapshot", transform.is_valid())?;
+
+    let status = backend::get().snapshot(display, snapshot_resource, transform);
+
+    check("vc_dispmanx_snapshot", status)
+}
+
+pub fn stop() {
+    backend::get().stop()
+}
+
+// changes queued on the update are applied when it is dropped without submit_sync(), submit()
+// or submit_with(), so an early return still reaches the screen
+pub fn update_start(priority: int32_t) -> Result<Update, Error> {
+    let handle = backend::get().update_start(priority);
+
+    Ok(Update {
+           handle: check_handle("vc_dispmanx_update_start", handle)?,
+           queued: 0
+       })
+}
+
+pub fn update_submit(update: UpdateHandle, callback_func: CallbackFunc,
+                     callback_arg: *mut c_void) -> Result<(), Error> {
+    let status = backend::get().update_submit(update, callback_func, callback_arg);
+
+    check("vc_dispmanx_update_submit", status)
+}
+
+pub fn update_submit_sync(update: UpdateHandle) -> Result<(), Error> {
+    let status = backend::get().update_submit_sync(update);
+
+    check("vc_dispmanx_update_submit_sync", status)
+}
+
+// passing None as the callback unregisters the previous one
+pub fn vsync_callback(display: DisplayHandle, callback_func: Option<CallbackFunc>,
+                      callback_arg: *mut c_void) -> Result<(), Error> {
+    let status = backend::get().vsync_callback(display, callback_func, callback_arg);
+
+    check("vc_dispmanx_vsync_callback", status)
+}
+
+// runs and frees the closure boxed by Update::submit_with, a panic is dropped rather than
+// unwinding into the firmware callback thread
+extern "C" fn update_trampoline(update: UpdateHandle, arg: *mut c_void) {
+    if arg.is_null() {
+        return;
+    }
+
+    let callback = unsafe {
+        Box::from_raw(arg as *mut UpdateClosure)
+    };
+
+    let _ = panic::catch_unwind(AssertUnwindSafe(move || callback(update)));
+}
+
+// runs the active vsync closure, arg is its registration id. the closure is cloned out so it is
+// called without the registry locked, a vsync that arrives while the closure is still running
+// from the last one is skipped and a panic is dropped like in update_trampoline
+extern "C" fn vsync_trampoline(_: UpdateHandle, arg: *mut c_void) {
+    if arg.is_null() {
+        return;
+    }
+
+    let callback = match *VSYNC_ACTIVE.lock().unwrap() {
+        Some((id, ref callback)) if id == arg as usize => callback.clone(),
+        _                                             => return
+    };
+
+    if let Ok(mut callback) = callback.try_lock() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| (*callback)()));
+    };
+}
+
+// -------------------------------------------------------------------------------------------------
+// FFI
+// -------------------------------------------------------------------------------------------------
+
+pub mod ffi {
+    use libc::{ c_void,
+                int32_t,
+                uint8_t,
+                uint32_t };
+
+    use image::{ ImageType,
+                 Rect };
+
+    use vchi::MemHandle;
+
+    use super::*;
+
+    extern {
+        // deprecated
+        pub fn vc_dispman_init() -> int32_t;
+
+        pub fn vc_dispmanx_display_close(display: DisplayHandle) -> int32_t;
+
+        pub fn vc_dispmanx_display_get_info(display: DisplayHandle,
+                                            pinfo: *mut Modeinfo) -> int32_t;
+
+        pub fn vc_dispmanx_display_open(device: uint32_t) -> DisplayHandle;
+
+        pub fn vc_dispmanx_display_open_mode(device: uint32_t, mode: uint32_t) -> DisplayHandle;
+
+        pub fn vc_dispmanx_display_open_offscreen(dest: ResourceHandle,
+                                                  orientation: Transform) -> DisplayHandle;
+
+        pub fn vc_dispmanx_display_reconfigure(display: DisplayHandle, mode: uint32_t) -> int32_t;
+
+        pub fn vc_dispmanx_display_set_background(update: UpdateHandle,
+                                                  display: DisplayHandle,
+                                                  red: uint8_t, green: uint8_t,
+                                                  blue: uint8_t) -> int32_t;
+
+        pub fn vc_dispmanx_display_set_destination(display: DisplayHandle,
+                                                   dest: ResourceHandle) -> int32_t;
+
+        pub fn vc_dispmanx_element_add(update: UpdateHandle, display: DisplayHandle,
+                                       layer: int32_t, dest_rect: *mut Rect,
+                                       src: ResourceHandle, src_rect: *mut Rect,
+                                       protection: Protection, alpha: *mut VCAlpha,
+                                       clamp: *mut Clamp, transform: Transform) -> ElementHandle;
+
+        pub fn vc_dispmanx_element_change_attributes(update: UpdateHandle,
+                                                     element: ElementHandle,
+                                                     change_flags: uint32_t, layer: int32_t,
+                                                     opacity: uint8_t, dest_rect: *const Rect,
+                                                     src_rect: *const Rect, mask: ResourceHandle,
+                                                     transform: Transform) -> int32_t;
+
+        pub fn vc_dispmanx_element_change_layer(update: UpdateHandle, element: ElementHandle,
+                                                layer: int32_t) -> int32_t;
+
+        pub fn vc_dispmanx_element_change_source(update: UpdateHandle, element: ElementHandle,
+                                                 src: ResourceHandle) -> int32_t;
+
+        pub fn vc_dispmanx_element_modified(update: UpdateHandle, element: ElementHandle,
+                                            rect: *mut Rect) -> int32_t;
+
+        pub fn vc_dispmanx_element_remove(update: UpdateHandle, element: ElementHandle) -> int32_t;
+
+        pub fn vc_dispmanx_query_image_formats(supported_formats: *mut uint32_t) -> int32_t;
+
+        pub fn vc_dispmanx_rect_set(rect: *mut Rect, x_offset: uint32_t, y_offset: uint32_t,
+                                    width: uint32_t, height: uint32_t) -> int32_t;
+
+        pub fn vc_dispmanx_resource_create(type_: ImageType, width: uint32_t, height: uint32_t,
+                                           native_image_handle: *mut uint32_t) -> ResourceHandle;
+
+        pub fn vc_dispmanx_resource_delete(res: ResourceHandle) -> int32_t;
+
+        // deprecated
+        pub fn vc_dispmanx_resource_get_image_handle(res: ResourceHandle) -> uint32_t;
+
+        pub fn vc_dispmanx_resource_read_data(handle: ResourceHandle, p_rect: *const Rect,
+                                              dst_address: *mut c_void,
+                                              dst_pitch: uint32_t) -> int32_t;
+
+        pub fn vc_dispmanx_resource_set_palette(handle: ResourceHandle,
+                                                src_address: *mut c_void, offset: int32_t,
+                                                size: int32_t) -> int32_t;
+
+        pub fn vc_dispmanx_resource_write_data(res: ResourceHandle, src_type: ImageType,
+                                               src_pitch: int32_t, src_address: *mut c_void,
+                                               rect: *const Rect) -> int32_t;
+
+        pub fn vc_dispmanx_resource_write_data_handle(res: ResourceHandle, src_type: ImageType,
+                                                      src_pitch: int32_t, handle: MemHandle,
+                                                      offset: uint32_t,
+                                                      rect: *const Rect) -> int32_t;
+
+        pub fn vc_dispmanx_snapshot(display: DisplayHandle,
+                                    snapshot_resource: ResourceHandle,
+                                    transform: Transform) -> int32_t;
+
+        pub fn vc_dispmanx_stop();
+
+        pub fn vc_dispmanx_update_start(priority: int32_t) -> UpdateHandle;
+
+        pub fn vc_dispmanx_update_submit(update: UpdateHandle, cb_func: CallbackFunc,
+                                         cb_arg: *mut c_void) -> int32_t;
+
+        pub fn vc_dispmanx_update_submit_sync(update: UpdateHandle) -> int32_t;
+
+        pub fn vc_dispmanx_vsync_callback(display: DisplayHandle,
+                                          cb_func: Option<CallbackFunc>,
+                                          cb_arg: *mut c_void) -> int32_t;
+
+        // call this instead of vc_dispman_init()
+        //pub fn vc_vchi_dispmanx_init(VCHI_INSTANCE_T initialise_instance, VCHI_CONNECTION_T **connections, uint32_t num_connections );
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use image::{ ImageTransform,
+                 Rect };
+
+    use super::{ rect_set,
+                 AlphaSource,
+                 ElementChange,
+                 FlagsAlpha,
+                 Rotation,
+                 Transform,
+                 ELEMENT_CHANGE_DEST_RECT,
+                 ELEMENT_CHANGE_LAYER,
+                 ELEMENT_CHANGE_TRANSFORM };
+
+    #[test]
+    pub fn element_change_pass() {
+        let rect = Rect {
+            x:      1,
+            y:      2,
+            width:  3,
+            height: 4
+        };
+
+        assert_eq!(ElementChange::new().flags(), 0);
+
+        let change = ElementChange::new().layer(3)
+                                         .dest_rect(rect)
+                                         .transform(Transform::ROTATE_180);
+
+        assert_eq!(change.flags(),
+                   ELEMENT_CHANGE_LAYER | ELEMENT_CHANGE_DEST_RECT | ELEMENT_CHANGE_TRANSFORM);
+        assert_eq!(change.dest_rect, rect);
+        assert_eq!(change.layer, 3);
+    }
+
+    #[test]
+    pub fn flags_alpha_pass() {
+        let flags = FlagsAlpha::FIXED_ALL_PIXELS | FlagsAlpha::PREMULT;
+
+        assert_eq!(flags.bits(), 1 | 1 << 16);
+        assert_eq!(flags.alpha_source(), AlphaSource::FixedAllPixels);
+        assert!(flags.premultiplied());
+        assert!(!flags.mix());
+        assert!(flags.is_valid());
+        assert!(FlagsAlpha::from_bits(1 << 20).is_none());
+    }
+
+    #[test]
+    pub fn rect_set_pass() {
+        let mut rect = Rect::new(1, 1, 1, 1);
+
+        rect_set(&mut rect, 10, 20, 300, 400).unwrap();
+
+        assert_eq!(rect, Rect::new(10, 20, 300, 400));
+        assert!(rect_set(&mut rect, 0, 0, u32::MAX, 1).is_err());
+    }
+
+    #[test]
+    pub fn transform_pass() {
+        let transform = Transform::ROTATE_90 | Transform::FLIP_HRIZ | Transform::SNAPSHOT_PACK;
+
+        assert_eq!(transform.rotation(), Rotation::Rotate90);
+        assert!(transform.flip_horizontal());
+        assert!(!transform.flip_vertical());
+        assert!(transform.is_valid());
+        assert_eq!(transform & Transform::FLIP_HRIZ, Transform::FLIP_HRIZ);
+        assert_eq!(Transform::from_bits(transform.bits()), Some(transform));
+    }
+
+    #[test]
+    pub fn transform_invalid_pass() {
+        assert!(Transform::from_bits(1 << 20).is_none());
+        assert!(!(Transform::SNAPSHOT_NO_YUV | Transform::SNAPSHOT_NO_RGB).is_valid());
+    }
+
+    #[test]
+    pub fn transform_orientation_pass() {
+        assert_eq!(ImageTransform::from(Transform::ROTATE_90), ImageTransform::ROT90);
+        assert_eq!(ImageTransform::from(Transform::ROTATE_270), ImageTransform::ROT270);
+        assert_eq!(ImageTransform::from(Transform::FLIP_HRIZ), ImageTransform::MIRROR_ROT0);
+        assert_eq!(ImageTransform::from(Transform::ROTATE_180 | Transform::FLIP_HRIZ),
+                   ImageTransform::MIRROR_ROT180);
+        assert_eq!(Transform::from(ImageTransform::MIRROR_ROT180),
+                   Transform::ROTATE_180 | Transform::FLIP_HRIZ);
+
+        for i in 0..16 {
+            let transform = Transform(i & 3 | (i >> 2) << 16);
+            let image     = ImageTransform::from(transform);
+
+            assert_eq!(ImageTransform::from(Transform::from(image)), image);
+            assert_eq!(ImageTransform::from(transform.then(transform.inverse())),
+                       ImageTransform::ROT0);
+        }
+
+        let transform = Transform::ROTATE_90 | Transform::SNAPSHOT_PACK;
+
+        assert_eq!(transform.then(Transform::ROTATE_270), Transform::SNAPSHOT_PACK);
+        assert_eq!(transform.inverse(), Transform::ROTATE_270 | Transform::SNAPSHOT_PACK);
+    }
+
+    #[test]
+    pub fn vsync_receiver_missed_pass() {
+        let (shared, mut receiver) = super::VsyncReceiver::new();
+
+        assert!(receiver.try_recv().is_none());
+
+        shared.tick();
+
+        let event = receiver.try_recv().unwrap();
+
+        assert_eq!(event.frame, 1);
+        assert_eq!(event.missed, 0);
+        assert!(receiver.try_recv().is_none());
+
+        shared.tick();
+        shared.tick();
+        shared.tick();
+
+        let event = receiver.try_recv().unwrap();
+
+        assert_eq!(event.frame, 4);
+        assert_eq!(event.missed, 2);
+    }
+
+    #[test]
+    pub fn vsync_receiver_timeout_pass() {
+        let (_shared, mut receiver) = super::VsyncReceiver::new();
+
+        assert!(receiver.wait_timeout(Duration::from_millis(10)).is_none());
+    }
+
+    #[test]
+    pub fn vsync_receiver_wait_pass() {
+        let (shared, mut receiver) = super::VsyncReceiver::new();
+
+        let ticker = thread::spawn(move || {
+            for _ in 0..3 {
+                thread::sleep(Duration::from_millis(5));
+                shared.tick();
+            }
+        });
+
+        let first = receiver.by_ref().next().unwrap();
+
+        assert!(first.frame >= 1);
+
+        ticker.join().unwrap();
+
+        if first.frame < 3 {
+            let event = receiver.wait();
+
+            assert_eq!(event.frame, 3);
+            assert_eq!(event.missed, 3 - first.frame - 1);
+        }
+
+        assert!(receiver.try_recv().is_none());
+    }
+
+    #[cfg(videocore_software)]
+    mod software {
+        use std::sync::{ Arc,
+                         Mutex };
+
+        use std::sync::atomic::{ AtomicUsize,
+                                 Ordering };
+
+        use backend::software::SoftwareBackend;
+
+        use dispmanx::{ snapshot,
+                        update_start,
+                        Display,
+                        ElementBuilder,
+                        Error,
+                        ErrorKind,
+                        Resource,
+                        Transform };
+
+        use image::{ ImageType,
+                     Rect };
+
+        use palette::Palette;
+
+        // the vsync callback is process wide, so tests registering one run one at a time
+        static VSYNC_LOCK: Mutex<()> = Mutex::new(());
+
+        #[test]
+        pub fn palette_pass() {
+            let backend = SoftwareBackend::instance();
+
+            backend.set_display_size(105, 3, 1);
+
+            let display  = Display::open(105).unwrap();
+            let resource = Resource::create(ImageType::_4BPP, 3, 1).unwrap();
+            let palette  = Palette::from_rgb(&[[255, 0, 0], [0, 255, 0], [0, 0, 255]]);
+
+            assert_eq!(resource.write_indexed(&palette, &[2, 3, 0]).unwrap_err().kind(),
+                       ErrorKind::InvalidPalette);
+
+            resource.write_indexed(&palette, &[2, 0, 1]).unwrap();
+
+            let mut update = update_start(0).unwrap();
+
+            update.element_add(&display,
+                               ElementBuilder::new().dest(Rect::new(0, 0, 3, 1)).source(&resource))
+                  .unwrap();
+
+            update.submit_sync().unwrap();
+
+            let image = backend.composite(display.as_raw()).unwrap();
+
+            assert_eq!(image, [0, 0, 255, 255, 255, 0, 0, 255, 0, 255, 0, 255]);
+
+            // tformat indices are tiled on the way up and come back out in place, at 40 pixels
+            // TF_PAL8 is laid out in t-format tiles and TF_PAL4 in linear micro-tiles
+            let indices: Vec<u8> = (0..40 * 40).map(|i| (i % 7 % 3) as u8).collect();
+
+            backend.set_display_size(112, 40, 40);
+
+            let display = Display::open(112).unwrap();
+
+            for &type_ in &[ImageType::TF_PAL4, ImageType::TF_PAL8] {
+                let resource = Resource::create(type_, 40, 40).unwrap();
+
+                resource.write_indexed(&palette, &indices).unwrap();
+
+                let mut update = update_start(0).unwrap();
+
+                let element = update.element_add(&display,
+                                                 ElementBuilder::new().dest(Rect::new(0, 0, 40, 40))
+                                                                      .source(&resource))
+                                    .unwrap();
+
+                update.submit_sync().unwrap();
+
+                let image = backend.composite(display.as_raw()).unwrap();
+
+                for (pixel, &index) in image.chunks(4).zip(&indices) {
+                    assert_eq!(pixel[..3], palette.rgb(index), "{:?}", type_);
+                }
+
+                let mut update = update_start(0).unwrap();
+
+                update.element_remove(element).unwrap();
+                update.submit_sync().unwrap();
+            }
+        }
+
+        #[test]
+        pub fn read_planes_pass() {
+            let backend = SoftwareBackend::instance();
+
+            backend.set_display_size(110, 2, 2);
+
+            let display  = Display::open(110).unwrap();
+            let resource = Resource::create(ImageType::RGB888, 1, 1).unwrap();
+            let planes   = Resource::create(ImageType::YUV420, 2, 2).unwrap();
+
+            resource.write(&[255, 255, 255], 3, &Rect::new(0, 0, 1, 1)).unwrap();
+
+            let mut update = update_start(0).unwrap();
+
+            update.element_add(&display,
+                               ElementBuilder::new().dest(Rect::new(0, 0, 2, 2)).source(&resource))
+                  .unwrap();
+
+            update.submit_sync().unwrap();
+
+            snapshot(display.as_raw(), planes.as_raw(), Transform::NO_ROTATE).unwrap();
+
+            // 16 padded luma rows of 32 bytes, then 4 rows worth of U and 4 of V
+            let mut data = vec![0; 32 * 24];
+
+            assert_eq!(planes.read_planes(&mut data[..32 * 24 - 1]).unwrap_err().kind(),
+                       ErrorKind::BufferTooSmall);
+            assert_eq!(planes.read_planes(&mut data).unwrap(), 32);
+
+            assert_eq!(&data[..2], &[235, 235]);
+            assert_eq!(&data[32..34], &[235, 235]);
+            assert_eq!(data[64], 0);
+            assert_eq!(&data[32 * 16..32 * 16 + 1], &[128]);
+            assert_eq!(&data[32 * 20..32 * 20 + 1], &[128]);
+
+            assert_eq!(resource.read_planes(&mut data).unwrap_err().kind(),
+                       ErrorKind::UnsupportedFormat);
+        }
+
+        // an update abandoned by an early return still applies what was queued on it
+        #[test]
+        pub fn update_drop_pass() {
+            let backend = SoftwareBackend::instance();
+
+            backend.set_display_size(108, 2, 1);
+
+            let display  = Display::open(108).unwrap();
+            let resource = Resource::create(ImageType::RGB888, 1, 1).unwrap();
+
+            resource.write(&[255, 0, 0], 3, &Rect::new(0, 0, 1, 1)).unwrap();
+
+            let add = || -> Result<(), Error> {
+                let mut update = update_start(0)?;
+
+                update.element_add(&display, ElementBuilder::new().dest(Rect::new(1, 0, 1, 1))
+                                                                  .source(&resource))?;
+
+                assert_eq!(update.queued(), 1);
+
+                Err(Error::new("vc_dispmanx_element_add", ErrorKind::NoHandle))
+            };
+
+            assert!(add().is_err());
+
+            let image = backend.composite(display.as_raw()).unwrap();
+
+            assert_eq!(image, [0, 0, 0, 255, 255, 0, 0, 255]);
+        }
+
+        #[test]
+        pub fn vsync_panic_pass() {
+            let _lock   = VSYNC_LOCK.lock().unwrap();
+            let display = Display::open(111).unwrap();
+            let count   = Arc::new(AtomicUsize::new(0));
+            let counter = count.clone();
+
+            // a panicking closure stays registered and is called again on the next vsync
+            let guard = display.on_vsync(move || {
+                                   if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                                       panic!("vsync");
+                                   }
+                               })
+                               .unwrap();
+
+            SoftwareBackend::instance().vsync();
+            SoftwareBackend::instance().vsync();
+
+            assert!(guard.is_active());
+            assert!(count.load(Ordering::SeqCst) >= 2);
+
+            drop(guard);
+        }
+
+        #[test]
+        pub fn vsync_pass() {
+            let _lock   = VSYNC_LOCK.lock().unwrap();
+            let display = Display::open(104).unwrap();
+            let count   = Arc::new(AtomicUsize::new(0));
+            let counter = count.clone();
+
+            let guard = display.on_vsync(move || {
+                                   counter.fetch_add(1, Ordering::SeqCst);
+                               })
+                               .unwrap();
+
+            assert!(guard.is_active());
+
+            SoftwareBackend::instance().vsync();
+
+            assert!(count.load(Ordering::SeqCst) >= 1);
+
+            drop(guard);
+        }
+
+        #[test]
+        pub fn vsync_reentrant_pass() {
+            let _lock   = VSYNC_LOCK.lock().unwrap();
+            let display = Display::open(113).unwrap();
+            let inner   = Display::open(113).unwrap();
+            let guard   = Arc::new(Mutex::new(None));
+            let count   = Arc::new(AtomicUsize::new(0));
+
+            let (slot, counter) = (guard.clone(), count.clone());
+
+            // the first vsync drops the closure's own guard and registers a replacement from
+            // inside the callback
+            let first = display.on_vsync(move || {
+                                   let replaced = slot.lock().unwrap().take();
+
+                                   drop(replaced);
+
+                                   let counter = counter.clone();
+
+                                   let next = inner.on_vsync(move || {
+                                                       counter.fetch_add(1, Ordering::SeqCst);
+                                                   })
+                                                   .unwrap();
+
+                                   *slot.lock().unwrap() = Some(next);
+                               })
+                               .unwrap();
+
+            *guard.lock().unwrap() = Some(first);
+
+            SoftwareBackend::instance().vsync();
+            SoftwareBackend::instance().vsync();
+
+            assert!(count.load(Ordering::SeqCst) >= 1);
+            assert!(guard.lock().unwrap().as_ref().unwrap().is_active());
+
+            guard.lock().unwrap().take();
+        }
+
+        #[test]
+        pub fn vsync_replace_pass() {
+            let _lock   = VSYNC_LOCK.lock().unwrap();
+            let display = Display::open(109).unwrap();
+            let first   = Arc::new(AtomicUsize::new(0));
+            let second  = Arc::new(AtomicUsize::new(0));
+
+            let (counter_first, counter_second) = (first.clone(), second.clone());
+
+            let guard_first = display.on_vsync(move || {
+                                         counter_first.fetch_add(1, Ordering::SeqCst);
+                                     })
+                                     .unwrap();
+
+            let guard_second = display.on_vsync(move || {
+                                          counter_second.fetch_add(1, Ordering::SeqCst);
+                                      })
+                                      .unwrap();
+
+            assert!(!guard_first.is_active());
+            assert!(guard_second.is_active());
+
+            // dropping the replaced guard leaves the newer callback registered
+            drop(guard_first);
+
+            SoftwareBackend::instance().vsync();
+
+            assert_eq!(first.load(Ordering::SeqCst), 0);
+            assert!(second.load(Ordering::SeqCst) >= 1);
+
+            drop(guard_second);
+
+            let seen = second.load(Ordering::SeqCst);
+
+            SoftwareBackend::instance().vsync();
+
+            assert_eq!(second.load(Ordering::SeqCst), seen);
+        }
     }
 }
@@ -0,0 +1,356 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use libc::uint32_t;
+
+// local
+use convert::{ self,
+               Error,
+               Layout };
+
+use display::Dither;
+
+use image::ImageType;
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    // thresholds against an 8x8 bayer matrix, stable between frames
+    Ordered,
+
+    // pushes the rounding error of each pixel onto its unvisited neighbours
+    FloydSteinberg
+}
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// carries the diffused error from one row to the next
+struct Ditherer {
+    bits:   [uint32_t; 3],
+    method: Method,
+    errors: [Vec<[i32; 3]>; 2]
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+const BAYER: [[i32; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42], [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38], [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41], [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37], [63, 31, 55, 23, 61, 29, 53, 21]
+];
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl Ditherer {
+    fn new(bits: [uint32_t; 3], method: Method, width: usize) -> Ditherer {
+        Ditherer {
+            bits,
+            method,
+            errors: [vec![[0; 3]; width + 2], vec![[0; 3]; width + 2]]
+        }
+    }
+
+    // moves every colour channel of a row onto a level of its depth, alpha is left alone
+    fn row(&mut self, y: usize, pixels: &mut [[u8; 4]]) {
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            for (c, (channel, &bits)) in pixel[..3].iter_mut().zip(self.bits.iter()).enumerate() {
+                if bits >= 8 {
+                    continue;
+                }
+
+                *channel = match self.method {
+                    Method::Ordered => {
+                        let (lower, upper) = levels(*channel as i32, bits);
+
+                        if (*channel as i32 - lower) * 64 > BAYER[y % 8][x % 8] * (upper - lower) {
+                            upper as u8
+                        } else {
+                            lower as u8
+                        }
+                    },
+                    Method::FloydSteinberg => {
+                        let value          = *channel as i32 + self.errors[0][x + 1][c] / 16;
+                        let (lower, upper) = levels(value, bits);
+
+                        let shown = if value - lower > upper - value { upper } else { lower };
+                        let error = value - shown;
+
+                        self.errors[0][x + 2][c] += error * 7;
+                        self.errors[1][x][c]     += error * 3;
+                        self.errors[1][x + 1][c] += error * 5;
+                        self.errors[1][x + 2][c] += error;
+
+                        shown as u8
+                    }
+                };
+            }
+        }
+
+        self.errors.swap(0, 1);
+        self.errors[1].iter_mut().for_each(|error| *error = [0; 3]);
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// red, green and blue bits per channel the display keeps for a dither mode
+pub fn bits(target: &Dither) -> Option<[uint32_t; 3]> {
+    match *target {
+        Dither::RGB666 => Some([6, 6, 6]),
+        Dither::RGB565 => Some([5, 6, 5]),
+        Dither::RGB555 => Some([5, 5, 5]),
+        _              => None
+    }
+}
+
+fn channel_bits(type_: ImageType) -> [uint32_t; 3] {
+    match type_ {
+        ImageType::RGB565 | ImageType::RGBA565 => [5, 6, 5],
+        ImageType::RGBA16                      => [4, 4, 4],
+        _                                      => [8, 8, 8]
+    }
+}
+
+// converts width x height pixels like convert::convert, dithering down to the depth of dst
+pub fn dither(src: &[u8], src_layout: Layout, dst: &mut [u8], dst_layout: Layout, width: uint32_t,
+              height: uint32_t, method: Method) -> Result<(), Error> {
+    if !convert::is_supported(src_layout.type_) || !convert::is_supported(dst_layout.type_) {
+        return Err(Error::Unsupported(src_layout.type_, dst_layout.type_));
+    }
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    src_layout.check(src.len(), width, height)?;
+    dst_layout.check(dst.len(), width, height)?;
+
+    let width        = width as usize;
+    let mut ditherer = Ditherer::new(channel_bits(dst_layout.type_), method, width);
+    let mut pixels   = vec![[0; 4]; width];
+
+    for y in 0..height as usize {
+        let src = &src[y * src_layout.pitch..];
+        let dst = &mut dst[y * dst_layout.pitch..];
+
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = convert::read_pixel(src_layout.type_, src, x);
+        }
+
+        ditherer.row(y, &mut pixels);
+
+        for (x, &pixel) in pixels.iter().enumerate() {
+            convert::write_pixel(dst_layout.type_, dst, x, pixel);
+        }
+    }
+
+    Ok(())
+}
+
+// the 8 bit values either side of a value that a channel of `bits` can hold, expanded the way
+// convert reads them back so truncating them on write gives the level again
+fn levels(value: i32, bits: uint32_t) -> (i32, i32) {
+    let max    = (1 << bits) - 1;
+    let expand = |level: i32| level << (8 - bits) | level >> (2 * bits - 8);
+
+    let mut level = value.clamp(0, 255) >> (8 - bits);
+
+    if expand(level) > value {
+        level -= 1;
+    }
+
+    let level = level.max(0);
+
+    (expand(level), expand((level + 1).min(max)))
+}
+
+// dithers a buffer in place to the depth a display keeps after its output dither, so images
+// prepared for 18 and 16 bit panels do not band
+pub fn reduce(buffer: &mut [u8], layout: Layout, width: uint32_t, height: uint32_t, target: &Dither,
+              method: Method) -> Result<(), Error> {
+    if !convert::is_supported(layout.type_) {
+        return Err(Error::Unsupported(layout.type_, layout.type_));
+    }
+
+    let bits = match bits(target) {
+        Some(bits) if width != 0 && height != 0 => bits,
+        _                                       => return Ok(())
+    };
+
+    layout.check(buffer.len(), width, height)?;
+
+    let width        = width as usize;
+    let mut ditherer = Ditherer::new(bits, method, width);
+    let mut pixels   = vec![[0; 4]; width];
+
+    for y in 0..height as usize {
+        let row = &mut buffer[y * layout.pitch..];
+
+        for (x, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = convert::read_pixel(layout.type_, row, x);
+        }
+
+        ditherer.row(y, &mut pixels);
+
+        for (x, &pixel) in pixels.iter().enumerate() {
+            convert::write_pixel(layout.type_, row, x, pixel);
+        }
+    }
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use convert::{ self,
+                   AlphaMode,
+                   Error,
+                   Layout };
+
+    use display::Dither;
+
+    use image::ImageType;
+
+    use super::{ dither,
+                 reduce,
+                 Method };
+
+    // a horizontal ramp over the full range, 256 x 8 RGB888
+    fn ramp() -> Vec<u8> {
+        (0..256 * 8).flat_map(|i| [(i % 256) as u8, (i % 256) as u8, 255 - (i % 256) as u8])
+                    .collect()
+    }
+
+    // largest difference in red between the source and the output averaged over 8x8 blocks
+    fn block_error(src: &[u8], out: &[u8]) -> f64 {
+        (0..32).map(|block| {
+                   let sum = |image: &[u8]| {
+                       (0..8).flat_map(|y| (0..8).map(move |x| (y * 256 + block * 8 + x) * 3))
+                             .map(|i| image[i] as f64)
+                             .sum::<f64>()
+                   };
+
+                   (sum(src) - sum(out)).abs() / 64.0
+               })
+               .fold(0.0, f64::max)
+    }
+
+    fn to_rgb565(src: &[u8], method: Option<Method>) -> Vec<u8> {
+        let (src_layout, dst_layout) = (Layout::new(ImageType::RGB888, 768),
+                                        Layout::new(ImageType::RGB565, 512));
+
+        let mut rgb565 = vec![0; 512 * 8];
+        let mut out    = vec![0; 768 * 8];
+
+        match method {
+            Some(method) => dither(src, src_layout, &mut rgb565, dst_layout, 256, 8, method),
+            None         => convert::convert(src, src_layout, &mut rgb565, dst_layout, 256, 8,
+                                             AlphaMode::Keep)
+        }.unwrap();
+
+        convert::convert(&rgb565, dst_layout, &mut out, src_layout, 256, 8, AlphaMode::Keep)
+                .unwrap();
+
+        out
+    }
+
+    #[test]
+    pub fn dither_errors_pass() {
+        let mut out = [0; 16];
+
+        assert_eq!(dither(&[0; 12], Layout::new(ImageType::YUV420, 4), &mut out,
+                          Layout::new(ImageType::RGB565, 4), 2, 2, Method::Ordered),
+                   Err(Error::Unsupported(ImageType::YUV420, ImageType::RGB565)));
+        assert_eq!(reduce(&mut out, Layout::new(ImageType::RGB888, 6), 2, 3, &Dither::RGB666,
+                          Method::Ordered),
+                   Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    pub fn dither_gradient_pass() {
+        // truncating bands the ramp, dithering keeps the local average
+        let src = ramp();
+
+        assert!(block_error(&src, &to_rgb565(&src, None)) > 3.0);
+
+        for &method in &[Method::Ordered, Method::FloydSteinberg] {
+            let error = block_error(&src, &to_rgb565(&src, Some(method)));
+
+            assert!(error < 1.0, "{:?} {}", method, error);
+        }
+    }
+
+    #[test]
+    pub fn dither_levels_pass() {
+        // values the target can already show are left untouched
+        let src = [0, 130, 255, 8, 4, 247];
+
+        for &method in &[Method::Ordered, Method::FloydSteinberg] {
+            let mut out = src;
+
+            reduce(&mut out, Layout::new(ImageType::RGB888, 6), 2, 1, &Dither::RGB565, method)
+                    .unwrap();
+
+            assert_eq!(out, src);
+        }
+    }
+
+    #[test]
+    pub fn dither_reduce_pass() {
+        // every channel ends on a 6 bit level and alpha is kept
+        let mut out: Vec<u8> = (0..64 * 4).map(|i| (i * 7 % 256) as u8).collect();
+
+        reduce(&mut out, Layout::new(ImageType::RGBA32, 256), 64, 1, &Dither::RGB666,
+               Method::FloydSteinberg).unwrap();
+
+        for (i, &value) in out.iter().enumerate() {
+            if i % 4 == 3 {
+                assert_eq!(value, (i * 7 % 256) as u8);
+            } else {
+                assert_eq!(value, value & 0xfc | value >> 6);
+            }
+        }
+
+        let mut out = [1, 2, 3];
+
+        reduce(&mut out, Layout::new(ImageType::RGB888, 3), 1, 1, &Dither::NONE, Method::Ordered)
+                .unwrap();
+
+        assert_eq!(out, [1, 2, 3]);
+    }
+}
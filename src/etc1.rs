@@ -0,0 +1,601 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use std::convert::TryFrom;
+use std::{ error,
+           fmt };
+
+use libc::uint32_t;
+
+// local
+use convert::{ self,
+               Layout };
+
+use image::ImageType;
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    Convert(convert::Error),
+
+    // the pkm magic, version, format or sizes are wrong
+    InvalidHeader,
+
+    // the padded width or height does not fit the 16 bit fields of a pkm header
+    TooLarge
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quality {
+    // fits the modifiers around the quantized average of each half block
+    Fast,
+
+    // also searches the base colours one step either side of the average
+    High
+}
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// best fit of a base colour to one half of a block
+#[derive(Clone, Copy)]
+struct Fit {
+    error:   u64,
+    colour:  [i32; 3],
+    table:   u8,
+    indices: [u8; 16]
+}
+
+// the container written by the khronos etcpack tool, blocks follow a 16 byte big endian header
+pub struct Pkm {
+    pub width:  uint32_t,
+    pub height: uint32_t,
+    pub data:   Vec<u8>
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+// intensity modifiers per table, in the order of the 2 bit pixel index
+const MODIFIERS: [[i32; 4]; 8] = [
+    [2, 8, -2, -8], [5, 17, -5, -17], [9, 29, -9, -29], [13, 42, -13, -42], [18, 60, -18, -60],
+    [24, 80, -24, -80], [33, 106, -33, -106], [47, 183, -47, -183]
+];
+
+const PKM_HEADER: usize = 16;
+const PKM_MAGIC:  &[u8] = b"PKM 10";
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl error::Error for Error {
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Convert(ref error) => write!(f, "{}", error),
+            Error::InvalidHeader      => write!(f, "invalid pkm header"),
+            Error::TooLarge           => write!(f, "image too large for a pkm header")
+        }
+    }
+}
+
+impl From<convert::Error> for Error {
+    fn from(error: convert::Error) -> Error {
+        Error::Convert(error)
+    }
+}
+
+impl Pkm {
+    pub fn new(width: uint32_t, height: uint32_t, data: Vec<u8>) -> Pkm {
+        Pkm {
+            width,
+            height,
+            data
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Pkm, Error> {
+        if bytes.len() < PKM_HEADER || &bytes[..6] != PKM_MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+
+        let field = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]) as uint32_t;
+
+        // only ETC1_RGB_NO_MIPMAPS is defined for version 1.0
+        if field(6) != 0 {
+            return Err(Error::InvalidHeader);
+        }
+
+        let (width, height) = (field(12), field(14));
+
+        if field(8) < width || field(10) < height {
+            return Err(Error::InvalidHeader);
+        }
+
+        let size = size(width, height);
+
+        if bytes.len() < PKM_HEADER + size {
+            return Err(convert::Error::BufferTooSmall.into());
+        }
+
+        Ok(Pkm::new(width, height, bytes[PKM_HEADER..PKM_HEADER + size].to_vec()))
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let padded = |value: uint32_t| {
+            value.checked_next_multiple_of(4)
+                 .and_then(|padded| u16::try_from(padded).ok())
+                 .ok_or(Error::TooLarge)
+        };
+
+        let (padded_width, padded_height) = (padded(self.width)?, padded(self.height)?);
+
+        let mut bytes = Vec::with_capacity(PKM_HEADER + self.data.len());
+
+        bytes.extend_from_slice(PKM_MAGIC);
+        bytes.extend_from_slice(&[0, 0]);
+
+        // the padded sizes fit so the sizes themselves do too
+        for &value in &[padded_width, padded_height, self.width as u16, self.height as u16] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+
+        bytes.extend_from_slice(&self.data);
+
+        Ok(bytes)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+fn average(pixels: &[[i32; 3]; 16], members: &[usize]) -> [i32; 3] {
+    let mut sum = [0; 3];
+
+    for &i in members {
+        for (s, &p) in sum.iter_mut().zip(pixels[i].iter()) {
+            *s += p;
+        }
+    }
+
+    let count = members.len().max(1) as i32;
+
+    [(sum[0] + count / 2) / count, (sum[1] + count / 2) / count, (sum[2] + count / 2) / count]
+}
+
+// base colour candidates for a quantized average
+fn candidates(colour: [i32; 3], max: i32, quality: Quality) -> Vec<[i32; 3]> {
+    if quality == Quality::Fast {
+        return vec![colour];
+    }
+
+    let mut out = Vec::with_capacity(27);
+
+    for r in -1..=1 {
+        for g in -1..=1 {
+            for b in -1..=1 {
+                let candidate = [colour[0] + r, colour[1] + g, colour[2] + b];
+
+                if candidate.iter().all(|&c| (0..=max).contains(&c)) {
+                    out.push(candidate);
+                }
+            }
+        }
+    }
+
+    out
+}
+
+// unpacks width x height pixels of linear ETC1 blocks into an rgb format supported by convert
+pub fn decode(src: &[u8], dst: &mut [u8], dst_layout: Layout, width: uint32_t,
+              height: uint32_t) -> Result<(), Error> {
+    if !convert::is_supported(dst_layout.type_) {
+        return Err(convert::Error::Unsupported(ImageType::TF_ETC1, dst_layout.type_).into());
+    }
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    dst_layout.check(dst.len(), width, height)?;
+
+    if src.len() < size(width, height) {
+        return Err(convert::Error::BufferTooSmall.into());
+    }
+
+    let (width, height) = (width as usize, height as usize);
+
+    for (i, block) in src.chunks(8).take(size(width as u32, height as u32) / 8).enumerate() {
+        let (bx, by) = (i % width.div_ceil(4) * 4, i / width.div_ceil(4) * 4);
+        let pixels   = decode_block(block);
+
+        for (j, pixel) in pixels.iter().enumerate() {
+            let (x, y) = (bx + j / 4, by + j % 4);
+
+            if x < width && y < height {
+                convert::write_pixel(dst_layout.type_, &mut dst[y * dst_layout.pitch..], x,
+                                     [pixel[0], pixel[1], pixel[2], 255]);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// pixels are in column order, index 4 * x + y, matching the order of the index bits
+fn decode_block(block: &[u8]) -> [[u8; 3]; 16] {
+    let diff = block[3] & 2 != 0;
+    let flip = block[3] & 1 != 0;
+
+    let mut colours = [[0; 3]; 2];
+
+    for (c, &byte) in block[..3].iter().enumerate() {
+        if diff {
+            let base  = (byte >> 3) as i32;
+            let delta = ((byte & 7) as i32 ^ 4) - 4;
+
+            colours[0][c] = expand(base, 5);
+            colours[1][c] = expand(base + delta, 5);
+        } else {
+            colours[0][c] = expand((byte >> 4) as i32, 4);
+            colours[1][c] = expand((byte & 15) as i32, 4);
+        }
+    }
+
+    let tables  = [block[3] >> 5, (block[3] >> 2) & 7];
+    let indices = u32::from_be_bytes([block[4], block[5], block[6], block[7]]);
+
+    let mut pixels = [[0; 3]; 16];
+
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let half     = half(i, flip);
+        let index    = ((indices >> (16 + i)) & 1) << 1 | ((indices >> i) & 1);
+        let modifier = MODIFIERS[tables[half] as usize][index as usize];
+
+        for (out, &colour) in pixel.iter_mut().zip(colours[half].iter()) {
+            *out = (colour + modifier).clamp(0, 255) as u8;
+        }
+    }
+
+    pixels
+}
+
+fn distance(pixel: &[i32; 3], base: &[i32; 3], modifier: i32) -> u64 {
+    pixel.iter()
+         .zip(base.iter())
+         .map(|(&p, &b)| ((b + modifier).clamp(0, 255) - p).pow(2) as u64)
+         .sum()
+}
+
+// compresses width x height pixels into linear ETC1 blocks, pixels past the edge of partial blocks
+// are left out of the fit and alpha is dropped
+pub fn encode(src: &[u8], src_layout: Layout, dst: &mut [u8], width: uint32_t, height: uint32_t,
+              quality: Quality) -> Result<(), Error> {
+    if !convert::is_supported(src_layout.type_) {
+        return Err(convert::Error::Unsupported(src_layout.type_, ImageType::TF_ETC1).into());
+    }
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    src_layout.check(src.len(), width, height)?;
+
+    if dst.len() < size(width, height) {
+        return Err(convert::Error::BufferTooSmall.into());
+    }
+
+    let (width, height) = (width as usize, height as usize);
+
+    for (i, block) in dst.chunks_mut(8).take(size(width as u32, height as u32) / 8).enumerate() {
+        let (bx, by)   = (i % width.div_ceil(4) * 4, i / width.div_ceil(4) * 4);
+        let mut pixels = [[0; 3]; 16];
+        let mut valid  = [false; 16];
+
+        for (j, (pixel, valid)) in pixels.iter_mut().zip(valid.iter_mut()).enumerate() {
+            let (x, y) = (bx + j / 4, by + j % 4);
+
+            if x < width && y < height {
+                let value = convert::read_pixel(src_layout.type_, &src[y * src_layout.pitch..], x);
+
+                *pixel = [value[0] as i32, value[1] as i32, value[2] as i32];
+                *valid = true;
+            }
+        }
+
+        block.copy_from_slice(&encode_block(&pixels, &valid, quality));
+    }
+
+    Ok(())
+}
+
+// tries both orientations in individual and differential mode and keeps the closest block
+fn encode_block(pixels: &[[i32; 3]; 16], valid: &[bool; 16], quality: Quality) -> [u8; 8] {
+    let mut best = (u64::MAX, [0; 8]);
+
+    for &flip in &[false, true] {
+        let mut members = [Vec::with_capacity(8), Vec::with_capacity(8)];
+
+        for i in (0..16).filter(|&i| valid[i]) {
+            members[half(i, flip)].push(i);
+        }
+
+        let averages = [average(pixels, &members[0]), average(pixels, &members[1])];
+
+        let individual = [
+            fit_best(pixels, &members[0], averages[0], 4, quality),
+            fit_best(pixels, &members[1], averages[1], 4, quality)
+        ];
+
+        let mut differential = [
+            fit_best(pixels, &members[0], averages[0], 5, quality),
+            fit_best(pixels, &members[1], averages[1], 5, quality)
+        ];
+
+        // the second colour must be within -4..=3 of the first, pull it in and fit it again
+        let base = differential[0].colour;
+        let mut colour = differential[1].colour;
+
+        for (c, &b) in colour.iter_mut().zip(base.iter()) {
+            *c = (*c).clamp(b - 4, b + 3);
+        }
+
+        if colour != differential[1].colour {
+            differential[1] = fit(pixels, &members[1], colour, 5);
+        }
+
+        for &(diff, fits) in &[(false, individual), (true, differential)] {
+            let error = fits[0].error + fits[1].error;
+
+            if error < best.0 {
+                best = (error, pack_block(diff, flip, &fits));
+            }
+        }
+    }
+
+    best.1
+}
+
+fn expand(value: i32, bits: u32) -> i32 {
+    value << (8 - bits) | value >> (2 * bits - 8)
+}
+
+// closest table and indices for the members of a half block around a quantized base colour
+fn fit(pixels: &[[i32; 3]; 16], members: &[usize], colour: [i32; 3], bits: u32) -> Fit {
+    let base = [expand(colour[0], bits), expand(colour[1], bits), expand(colour[2], bits)];
+
+    let mut best = Fit {
+        error:   u64::MAX,
+        colour,
+        table:   0,
+        indices: [0; 16]
+    };
+
+    for (table, modifiers) in MODIFIERS.iter().enumerate() {
+        let mut error   = 0;
+        let mut indices = [0; 16];
+
+        for &i in members {
+            let (index, distance) = modifiers.iter()
+                                             .map(|&m| distance(&pixels[i], &base, m))
+                                             .enumerate()
+                                             .min_by_key(|&(_, d)| d)
+                                             .unwrap();
+
+            indices[i] = index as u8;
+            error     += distance;
+        }
+
+        if error < best.error {
+            best = Fit {
+                error,
+                colour,
+                table: table as u8,
+                indices
+            };
+        }
+    }
+
+    best
+}
+
+fn fit_best(pixels: &[[i32; 3]; 16], members: &[usize], average: [i32; 3], bits: u32,
+            quality: Quality) -> Fit {
+    let max    = (1 << bits) - 1;
+    let colour = [quantize(average[0], max), quantize(average[1], max), quantize(average[2], max)];
+
+    candidates(colour, max, quality).into_iter()
+                                    .map(|candidate| fit(pixels, members, candidate, bits))
+                                    .min_by_key(|fit| fit.error)
+                                    .unwrap()
+}
+
+// flip splits the block into top and bottom halves instead of left and right
+fn half(i: usize, flip: bool) -> usize {
+    if flip { (i % 4 >= 2) as usize } else { (i / 4 >= 2) as usize }
+}
+
+fn pack_block(diff: bool, flip: bool, fits: &[Fit; 2]) -> [u8; 8] {
+    let mut block = [0; 8];
+
+    for (c, byte) in block[..3].iter_mut().enumerate() {
+        let (first, second) = (fits[0].colour[c], fits[1].colour[c]);
+
+        *byte = if diff {
+            (first << 3 | ((second - first) & 7)) as u8
+        } else {
+            (first << 4 | second) as u8
+        };
+    }
+
+    block[3] = fits[0].table << 5 | fits[1].table << 2 | (diff as u8) << 1 | flip as u8;
+
+    let mut indices = 0u32;
+
+    for i in 0..16 {
+        let index = fits[half(i, flip)].indices[i] as u32;
+
+        indices |= (index >> 1) << (16 + i) | (index & 1) << i;
+    }
+
+    block[4..].copy_from_slice(&indices.to_be_bytes());
+    block
+}
+
+fn quantize(value: i32, max: i32) -> i32 {
+    (value * max + 127) / 255
+}
+
+// bytes of linear ETC1 blocks covering width x height pixels
+pub fn size(width: uint32_t, height: uint32_t) -> usize {
+    width.div_ceil(4) as usize * height.div_ceil(4) as usize * 8
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use convert::{ self,
+                   Layout };
+
+    use image::ImageType;
+
+    use super::{ decode,
+                 encode,
+                 size,
+                 Error,
+                 Pkm,
+                 Quality };
+
+    fn image(width: u32, height: u32, pixel: &dyn Fn(u32, u32) -> [u8; 3]) -> Vec<u8> {
+        (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+                   .flat_map(|(x, y)| pixel(x, y).to_vec())
+                   .collect()
+    }
+
+    fn psnr(width: u32, height: u32, src: &[u8], quality: Quality) -> f64 {
+        let layout  = Layout::new(ImageType::RGB888, width as usize * 3);
+        let mut etc = vec![0; size(width, height)];
+        let mut out = vec![0; src.len()];
+
+        encode(src, layout, &mut etc, width, height, quality).unwrap();
+        decode(&etc, &mut out, layout, width, height).unwrap();
+
+        let error = src.iter()
+                       .zip(out.iter())
+                       .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+                       .sum::<f64>() / src.len() as f64;
+
+        10.0 * (255.0 * 255.0 / error).log10()
+    }
+
+    #[test]
+    pub fn etc1_block_pass() {
+        // differential, base (16, 8, 4) and delta (1, 0, -1), tables 0 and 7, left and right
+        // halves, the first pixel uses index 3 and every other pixel index 0
+        let block   = [129, 64, 39, 30, 0, 1, 0, 1];
+        let mut out = [0; 48];
+
+        decode(&block, &mut out, Layout::new(ImageType::RGB888, 12), 4, 4).unwrap();
+
+        assert_eq!(out[..3], [124, 58, 25]);
+        assert_eq!(out[3..6], [134, 68, 35]);
+        assert_eq!(out[9..12], [187, 113, 71]);
+        assert_eq!(out[45..48], [187, 113, 71]);
+    }
+
+    #[test]
+    pub fn etc1_errors_pass() {
+        let src     = [0; 48];
+        let mut etc = [0; 8];
+
+        assert_eq!(encode(&src, Layout::new(ImageType::YUV420, 4), &mut etc, 4, 4, Quality::Fast),
+                   Err(Error::Convert(convert::Error::Unsupported(ImageType::YUV420,
+                                                                  ImageType::TF_ETC1))));
+        assert_eq!(encode(&src, Layout::new(ImageType::RGB888, 12), &mut etc[..7], 4, 4,
+                          Quality::Fast),
+                   Err(Error::Convert(convert::Error::BufferTooSmall)));
+        assert_eq!(decode(&etc, &mut [0; 60], Layout::new(ImageType::RGB888, 15), 5, 4),
+                   Err(Error::Convert(convert::Error::BufferTooSmall)));
+        assert_eq!(Pkm::parse(b"PKM 20\0\0\0\x04\0\x04\0\x04\0\x04").err(),
+                   Some(Error::InvalidHeader));
+    }
+
+    #[test]
+    pub fn etc1_pkm_pass() {
+        let pkm   = Pkm::new(5, 3, (0..16).collect());
+        let bytes = pkm.to_bytes().unwrap();
+
+        assert_eq!(bytes[..16], *b"PKM 10\0\0\0\x08\0\x04\0\x05\0\x03");
+
+        let parsed = Pkm::parse(&bytes).unwrap();
+
+        assert_eq!((parsed.width, parsed.height), (5, 3));
+        assert_eq!(parsed.data, pkm.data);
+        assert_eq!(Pkm::parse(&bytes[..31]).err(),
+                   Some(Error::Convert(convert::Error::BufferTooSmall)));
+
+        // 65532 is the widest size whose padding fits, 65533 pads to 65536
+        assert!(Pkm::new(65532, 4, Vec::new()).to_bytes().is_ok());
+
+        for &(width, height) in &[(65533, 4), (4, 65536), (u32::MAX, 4)] {
+            assert_eq!(Pkm::new(width, height, Vec::new()).to_bytes().err(),
+                       Some(Error::TooLarge));
+        }
+    }
+
+    #[test]
+    pub fn etc1_psnr_pass() {
+        // a smooth gradient, a noisy texture and hard edges between the halves of each block, at a
+        // size that is not a multiple of 4
+        let gradient = image(37, 21, &|x, y| [(x * 7) as u8, (y * 12) as u8, ((x + y) * 4) as u8]);
+        let noise = image(37, 21, &|x, y| {
+            let n = (x * 7919 + y * 104729) ^ (x * y * 31);
+
+            [(128 + (n % 48)) as u8, (96 + (n / 48 % 40)) as u8, (64 + (n / 1920 % 32)) as u8]
+        });
+
+        let edges = image(37, 21, &|x, y| if (x / 2 + y / 4) % 2 == 0 {
+            [230, 40, 30]
+        } else {
+            [20, 60, 200]
+        });
+
+        for &(src, fast, high) in &[(&gradient, 34.0, 34.5), (&noise, 28.5, 28.5),
+                                    (&edges, 33.0, 36.5)] {
+            let (a, b) = (psnr(37, 21, src, Quality::Fast), psnr(37, 21, src, Quality::High));
+
+            assert!(a > fast && b > high && b >= a, "fast {:.2} high {:.2}", a, b);
+        }
+    }
+}
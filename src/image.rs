@@ -22,12 +22,28 @@
 // DEPENDENCIES
 // -------------------------------------------------------------------------------------------------
 
-use libc::int32_t;
+// system
+use std::convert::TryFrom;
+use std::marker::PhantomData;
+use std::{ error,
+           fmt,
+           ptr };
+
+use libc::{ c_int,
+            c_void,
+            int32_t,
+            uint8_t,
+            uint16_t,
+            uint32_t };
+
+// local
+use vchi::MemHandle;
 
 // -------------------------------------------------------------------------------------------------
 // ENUMS
 // -------------------------------------------------------------------------------------------------
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub enum ImageBayerFormat {
     //defined to be identical to register bits
@@ -48,6 +64,7 @@ pub enum ImageBayerFormat {
     RAW4             = 15,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub enum ImageBayerOrder {
     //defined to be identical to register bits
@@ -57,18 +74,7 @@ pub enum ImageBayerOrder {
     GRBG = 3
 }
 
-#[repr(C)]
-pub enum ImageTransform {
-    ROT0           = 0,
-    MIRROR_ROT0    = (1<<0),
-    MIRROR_ROT180  = (1<<1),
-    ROT180         = (1<<0)|(1<<1),
-    MIRROR_ROT90   = (1<<2),
-    ROT270         = (1<<2)|(1<<0),
-    ROT90          = (1<<2)|(1<<1),
-    MIRROR_ROT270  = (1<<2)|(1<<0)|(1<<1)
-}
-
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub enum ImageType {
     MIN    = 0,    //bounds for error checking
@@ -131,13 +137,96 @@ pub enum ImageType {
     FORCE_ENUM_16BIT = 0xffff,
 }
 
+// reasons a buffer cannot be viewed as an image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageViewError {
+    BufferTooSmall,
+    InvalidDimensions,
+    InvalidPitch,
+    Unsupported(ImageType)
+}
+
 // -------------------------------------------------------------------------------------------------
 // STRUCTS
 // -------------------------------------------------------------------------------------------------
 
+// VC_IMAGE_T, type is an 8 bit field in C so it is stored as the raw value
+#[derive(Clone, Copy)]
 #[repr(C)]
-pub struct Image;
+pub struct Image {
+    pub type_:                  uint8_t,
+    pub info:                   ImageInfo,
+    pub width:                  uint16_t,
+    pub height:                 uint16_t,
+    pub pitch:                  int32_t,
+    pub size:                   int32_t,
+    pub image_data:             *mut c_void,
+    pub extra:                  ImageExtra,
+    pub metadata:               *mut c_void,
+    pub pool_object:            *mut c_void,
+    pub mem_handle:             MemHandle,
+    pub metadata_size:          c_int,
+    pub channel_offset:         c_int,
+    pub video_timestamp:        uint32_t,
+    pub num_channels:           uint8_t,
+    pub current_channel:        uint8_t,
+    pub linked_multichann_flag: uint8_t,
+    pub is_channel_linked:      uint8_t,
+    pub channel_index:          uint8_t,
+    pub _dummy:                 [uint8_t; 3]
+}
+
+// VC_IMAGE_EXTRA_T, which member is live depends on the image type
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub union ImageExtra {
+    pub bayer:   ImageExtraBayer,
+    pub palette: ImageExtraPalette
+}
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct ImageExtraBayer {
+    pub order:        ImageBayerOrder,
+    pub format:       ImageBayerFormat,
+    pub block_length: int32_t
+}
+
+// RGB565 entries of palettised images
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct ImageExtraPalette {
+    pub palette: *mut uint16_t
+}
+
+// VC_IMAGE_INFO_T, a colour space in the low bits and siting and layout flags above
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[repr(transparent)]
+pub struct ImageInfo(pub uint16_t);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(transparent)]
+pub struct ImageTransform(uint32_t);
+
+// an Image describing a borrowed buffer, the pointers inside stay valid for 'a
+pub struct ImageView<'a> {
+    image: Image,
+    data:  PhantomData<&'a [u8]>
+}
+
+// value that does not name an ImageType
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageTypeError(pub uint32_t);
+
+// one plane of an image, chroma planes are subsampled by the given factors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub bits_per_pixel: uint32_t,
+    pub h_subsampling:  uint32_t,
+    pub v_subsampling:  uint32_t
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[repr(C)]
 pub struct Rect {
     pub x:      int32_t,
@@ -145,3 +234,780 @@ pub struct Rect {
     pub width:  int32_t,
     pub height: int32_t
 }
+
+// source rects are in 16.16 fixed point, laid out like Rect so either can be passed as VC_RECT_T
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct SrcRect {
+    pub x:      int32_t,
+    pub y:      int32_t,
+    pub width:  int32_t,
+    pub height: int32_t
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+// raster pitches are padded to this many bytes
+const PITCH_ALIGN: uint32_t = 32;
+
+const PACKED_1:  Plane = Plane { bits_per_pixel: 1,  h_subsampling: 1, v_subsampling: 1 };
+const PACKED_4:  Plane = Plane { bits_per_pixel: 4,  h_subsampling: 1, v_subsampling: 1 };
+const PACKED_8:  Plane = Plane { bits_per_pixel: 8,  h_subsampling: 1, v_subsampling: 1 };
+const PACKED_16: Plane = Plane { bits_per_pixel: 16, h_subsampling: 1, v_subsampling: 1 };
+const PACKED_24: Plane = Plane { bits_per_pixel: 24, h_subsampling: 1, v_subsampling: 1 };
+const PACKED_32: Plane = Plane { bits_per_pixel: 32, h_subsampling: 1, v_subsampling: 1 };
+const PACKED_48: Plane = Plane { bits_per_pixel: 48, h_subsampling: 1, v_subsampling: 1 };
+
+const CHROMA_420:    Plane = Plane { bits_per_pixel: 8,  h_subsampling: 2, v_subsampling: 2 };
+const CHROMA_420_UV: Plane = Plane { bits_per_pixel: 16, h_subsampling: 2, v_subsampling: 2 };
+const CHROMA_422:    Plane = Plane { bits_per_pixel: 8,  h_subsampling: 2, v_subsampling: 1 };
+
+// every ImageType between MIN and MAX in value order
+const IMAGE_TYPES: [ImageType; 55] = [
+    ImageType::RGB565, ImageType::_1BPP, ImageType::YUV420, ImageType::_48BPP, ImageType::RGB888,
+    ImageType::_8BPP, ImageType::_4BPP, ImageType::_3D32, ImageType::_3D32B, ImageType::_3D32MAT,
+    ImageType::RGB2X9, ImageType::RGB666, ImageType::PAL4_OBSOLETE, ImageType::PAL8_OBSOLETE,
+    ImageType::RGBA32, ImageType::YUV422, ImageType::RGBA565, ImageType::RGBA16,
+    ImageType::YUV_UV, ImageType::TF_RGBA32, ImageType::TF_RGBX32, ImageType::TF_FLOAT,
+    ImageType::TF_RGBA16, ImageType::TF_RGBA5551, ImageType::TF_RGB565, ImageType::TF_YA88,
+    ImageType::TF_BYTE, ImageType::TF_PAL8, ImageType::TF_PAL4, ImageType::TF_ETC1,
+    ImageType::BGR888, ImageType::BGR888_NP, ImageType::BAYER, ImageType::CODEC,
+    ImageType::YUV_UV32, ImageType::TF_Y8, ImageType::TF_A8, ImageType::TF_SHORT,
+    ImageType::TF_1BPP, ImageType::OPENGL, ImageType::YUV444I, ImageType::YUV422PLANAR,
+    ImageType::ARGB8888, ImageType::XRGB8888, ImageType::YUV422YUYV, ImageType::YUV422YVYU,
+    ImageType::YUV422UYVY, ImageType::YUV422VYUY, ImageType::RGBX32, ImageType::RGBX8888,
+    ImageType::BGRX8888, ImageType::YUV420SP, ImageType::YUV444PLANAR, ImageType::TF_U8,
+    ImageType::TF_V8
+];
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl Image {
+    pub fn image_type(&self) -> Result<ImageType, ImageTypeError> {
+        ImageType::try_from(self.type_ as uint32_t)
+    }
+}
+
+impl ImageInfo {
+    pub const UNSPECIFIED:           ImageInfo = ImageInfo(0);
+    pub const CSC_ITUR_BT601:        ImageInfo = ImageInfo(1);
+    pub const CSC_ITUR_BT709:        ImageInfo = ImageInfo(2);
+    pub const CSC_JPEG_JFIF:         ImageInfo = ImageInfo(3);
+    pub const CSC_FCC:               ImageInfo = ImageInfo(4);
+    pub const CSC_SMPTE_240M:        ImageInfo = ImageInfo(5);
+    pub const CSC_ITUR_BT470_2_M:    ImageInfo = ImageInfo(6);
+    pub const CSC_ITUR_BT470_2_BG:   ImageInfo = ImageInfo(7);
+    pub const CSC_JPEG_JFIF_Y16_255: ImageInfo = ImageInfo(8);
+    pub const H_COSITED:             ImageInfo = ImageInfo(1 << 8);
+    pub const V_COSITED:             ImageInfo = ImageInfo(1 << 9);
+    pub const TOP_BOTTOM:            ImageInfo = ImageInfo(1 << 10);
+    pub const DECIMATED:             ImageInfo = ImageInfo(1 << 11);
+    pub const PACKED:                ImageInfo = ImageInfo(1 << 12);
+    pub const IS_VU:                 ImageInfo = ImageInfo(1 << 15);
+
+    pub fn colour_space(&self) -> ImageInfo {
+        ImageInfo(self.0 & 0xff)
+    }
+
+    pub fn contains(&self, other: ImageInfo) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl<'a> ImageView<'a> {
+    // the buffer holds every plane at the given pitch, the firmware only reads through the view
+    pub fn new(data: &'a [u8], type_: ImageType, width: uint32_t, height: uint32_t,
+               pitch: uint32_t) -> Result<ImageView<'a>, ImageViewError> {
+        let size = type_.buffer_size_at_pitch(height, pitch)
+                        .ok_or(ImageViewError::Unsupported(type_))?;
+
+        if width == 0 || height == 0 || width > uint16_t::MAX as uint32_t
+           || height > uint16_t::MAX as uint32_t {
+            return Err(ImageViewError::InvalidDimensions);
+        }
+
+        let row = (width as u64 * type_.planes()[0].bits_per_pixel as u64).div_ceil(8);
+
+        if (pitch as u64) < row || pitch > i32::MAX as uint32_t {
+            return Err(ImageViewError::InvalidPitch);
+        }
+
+        if data.len() < size {
+            return Err(ImageViewError::BufferTooSmall);
+        }
+
+        let extra = ImageExtra {
+            palette: ImageExtraPalette {
+                palette: ptr::null_mut()
+            }
+        };
+
+        Ok(ImageView {
+               image: Image {
+                   type_:                  type_ as uint8_t,
+                   info:                   ImageInfo::UNSPECIFIED,
+                   width:                  width as uint16_t,
+                   height:                 height as uint16_t,
+                   pitch:                  pitch as int32_t,
+                   size:                   data.len().min(i32::MAX as usize) as int32_t,
+                   image_data:             data.as_ptr() as *mut c_void,
+                   extra,
+                   metadata:               ptr::null_mut(),
+                   pool_object:            ptr::null_mut(),
+                   mem_handle:             0,
+                   metadata_size:          0,
+                   channel_offset:         0,
+                   video_timestamp:        0,
+                   num_channels:           1,
+                   current_channel:        0,
+                   linked_multichann_flag: 0,
+                   is_channel_linked:      0,
+                   channel_index:          0,
+                   _dummy:                 [0; 3]
+               },
+               data:  PhantomData
+           })
+    }
+
+    pub fn as_ptr(&self) -> *const Image {
+        &self.image
+    }
+
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    pub fn info(mut self, info: ImageInfo) -> ImageView<'a> {
+        self.image.info = info;
+        self
+    }
+
+    // palettised images point at their RGB565 entries
+    pub fn palette(mut self, palette: &'a [u16]) -> ImageView<'a> {
+        self.image.extra = ImageExtra {
+            palette: ImageExtraPalette {
+                palette: palette.as_ptr() as *mut uint16_t
+            }
+        };
+
+        self
+    }
+}
+
+impl ImageTransform {
+    pub const ROT0:          ImageTransform = ImageTransform(0);
+    pub const MIRROR_ROT0:   ImageTransform = ImageTransform(1 << 0);
+    pub const MIRROR_ROT180: ImageTransform = ImageTransform(1 << 1);
+    pub const ROT180:        ImageTransform = ImageTransform((1 << 0) | (1 << 1));
+    pub const MIRROR_ROT90:  ImageTransform = ImageTransform(1 << 2);
+    pub const ROT270:        ImageTransform = ImageTransform((1 << 2) | (1 << 0));
+    pub const ROT90:         ImageTransform = ImageTransform((1 << 2) | (1 << 1));
+    pub const MIRROR_ROT270: ImageTransform = ImageTransform((1 << 2) | (1 << 0) | (1 << 1));
+
+    const ALL: uint32_t = 7;
+
+    // the orientation that moves the corners of the unit square the way `map` does
+    pub(crate) fn from_map<F>(map: F) -> ImageTransform
+        where F: Fn(int32_t, int32_t) -> (int32_t, int32_t) {
+        (0..8).map(ImageTransform)
+              .find(|transform| {
+                  [(0, 0), (1, 0), (0, 1)].iter()
+                                          .all(|&(u, v)| transform.map(u, v) == map(u, v))
+              })
+              .unwrap_or(ImageTransform::ROT0)
+    }
+
+    // bit 0
+    pub fn flip_horizontal(&self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    // bit 1
+    pub fn flip_vertical(&self) -> bool {
+        self.0 & 2 != 0
+    }
+
+    pub fn inverse(&self) -> ImageTransform {
+        (0..8).map(ImageTransform)
+              .find(|&inverse| self.then(inverse) == ImageTransform::ROT0)
+              .unwrap_or(ImageTransform::ROT0)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.0 & !ImageTransform::ALL == 0
+    }
+
+    // where a corner of the unit square ends up
+    pub(crate) fn map(&self, u: int32_t, v: int32_t) -> (int32_t, int32_t) {
+        let u = if self.flip_horizontal() { 1 - u } else { u };
+        let v = if self.flip_vertical() { 1 - v } else { v };
+
+        if self.transpose() { (v, u) } else { (u, v) }
+    }
+
+    // applies this orientation and then the next one
+    pub fn then(&self, next: ImageTransform) -> ImageTransform {
+        ImageTransform::from_map(|u, v| {
+            let (u, v) = self.map(u, v);
+
+            next.map(u, v)
+        })
+    }
+
+    // bit 2, swaps rows and columns after the flips have been applied
+    pub fn transpose(&self) -> bool {
+        self.0 & 4 != 0
+    }
+}
+
+flags!(ImageTransform);
+
+impl ImageType {
+    // average over all planes, so 12 for YUV420, None for formats without a fixed layout
+    pub fn bits_per_pixel(&self) -> Option<uint32_t> {
+        let planes = self.planes();
+
+        if planes.is_empty() {
+            return None;
+        }
+
+        Some(planes.iter()
+                   .map(|plane| plane.bits_per_pixel / (plane.h_subsampling * plane.v_subsampling))
+                   .sum())
+    }
+
+    // bytes needed to hold every plane of a width x height image at the required pitch
+    pub fn buffer_size(&self, width: uint32_t, height: uint32_t) -> Option<usize> {
+        self.buffer_size_at_pitch(height, self.required_pitch(width)?)
+    }
+
+    // bytes needed to hold every plane of an image whose first plane has the given pitch
+    pub fn buffer_size_at_pitch(&self, height: uint32_t, pitch: uint32_t) -> Option<usize> {
+        let planes = self.planes();
+        let rows   = self.tile().map_or(1, |(_, rows)| rows) as u64;
+
+        if planes.is_empty() {
+            return None;
+        }
+
+        let size = planes.iter().enumerate().fold(0, |size, (i, plane)| {
+            let plane_pitch  = self.plane_pitch(i, pitch).unwrap() as u64;
+            let plane_height = (height as u64).div_ceil(plane.v_subsampling as u64)
+                                              .next_multiple_of(rows);
+
+            size + plane_pitch * plane_height
+        });
+
+        usize::try_from(size).ok()
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(*self, ImageType::RGBA32 | ImageType::RGBA16 | ImageType::TF_RGBA32
+                        | ImageType::TF_RGBA16 | ImageType::TF_RGBA5551 | ImageType::TF_YA88
+                        | ImageType::TF_A8 | ImageType::ARGB8888)
+    }
+
+    pub fn is_palettised(&self) -> bool {
+        matches!(*self, ImageType::_8BPP | ImageType::_4BPP | ImageType::PAL4_OBSOLETE
+                        | ImageType::PAL8_OBSOLETE | ImageType::TF_PAL8 | ImageType::TF_PAL4)
+    }
+
+    pub fn is_tformat(&self) -> bool {
+        matches!(*self, ImageType::TF_RGBA32 | ImageType::TF_RGBX32 | ImageType::TF_FLOAT
+                        | ImageType::TF_RGBA16 | ImageType::TF_RGBA5551 | ImageType::TF_RGB565
+                        | ImageType::TF_YA88 | ImageType::TF_BYTE | ImageType::TF_PAL8
+                        | ImageType::TF_PAL4 | ImageType::TF_ETC1 | ImageType::TF_Y8
+                        | ImageType::TF_A8 | ImageType::TF_SHORT | ImageType::TF_1BPP
+                        | ImageType::TF_U8 | ImageType::TF_V8)
+    }
+
+    pub fn is_yuv(&self) -> bool {
+        matches!(*self, ImageType::YUV420 | ImageType::YUV422 | ImageType::YUV_UV
+                        | ImageType::TF_YA88 | ImageType::YUV_UV32 | ImageType::TF_Y8
+                        | ImageType::YUV444I | ImageType::YUV422PLANAR | ImageType::YUV422YUYV
+                        | ImageType::YUV422YVYU | ImageType::YUV422UYVY | ImageType::YUV422VYUY
+                        | ImageType::YUV420SP | ImageType::YUV444PLANAR | ImageType::TF_U8
+                        | ImageType::TF_V8)
+    }
+
+    // pitch of a plane given the pitch of the first, scaled by its depth and subsampling
+    pub fn plane_pitch(&self, plane: usize, pitch: uint32_t) -> Option<uint32_t> {
+        let planes = self.planes();
+        let plane  = planes.get(plane)?;
+
+        let pitch = (pitch as u64 * plane.bits_per_pixel as u64)
+                    .div_ceil((planes[0].bits_per_pixel * plane.h_subsampling) as u64);
+
+        Some(pitch as uint32_t)
+    }
+
+    // planes in memory order, empty for bayer, codec and opengl images whose layout lives
+    // elsewhere, YUV422 interleaves its planes line by line but takes the same space
+    pub fn planes(&self) -> &'static [Plane] {
+        match *self {
+            ImageType::_1BPP | ImageType::TF_1BPP => &[PACKED_1],
+
+            ImageType::_4BPP | ImageType::PAL4_OBSOLETE | ImageType::TF_PAL4 |
+            ImageType::TF_ETC1 => &[PACKED_4],
+
+            ImageType::_8BPP | ImageType::PAL8_OBSOLETE | ImageType::TF_BYTE |
+            ImageType::TF_PAL8 | ImageType::TF_Y8 | ImageType::TF_A8 | ImageType::TF_U8 |
+            ImageType::TF_V8 => &[PACKED_8],
+
+            ImageType::RGB565 | ImageType::RGBA565 | ImageType::RGBA16 | ImageType::TF_RGBA16 |
+            ImageType::TF_RGBA5551 | ImageType::TF_RGB565 | ImageType::TF_YA88 |
+            ImageType::TF_SHORT | ImageType::YUV422YUYV | ImageType::YUV422YVYU |
+            ImageType::YUV422UYVY | ImageType::YUV422VYUY => &[PACKED_16],
+
+            ImageType::RGB888 | ImageType::BGR888 | ImageType::BGR888_NP |
+            ImageType::YUV444I => &[PACKED_24],
+
+            ImageType::_3D32 | ImageType::_3D32B | ImageType::_3D32MAT | ImageType::RGB2X9 |
+            ImageType::RGB666 | ImageType::RGBA32 | ImageType::TF_RGBA32 | ImageType::TF_RGBX32 |
+            ImageType::TF_FLOAT | ImageType::ARGB8888 | ImageType::XRGB8888 |
+            ImageType::RGBX32 | ImageType::RGBX8888 | ImageType::BGRX8888 => &[PACKED_32],
+
+            ImageType::_48BPP => &[PACKED_48],
+
+            ImageType::YUV420 => &[PACKED_8, CHROMA_420, CHROMA_420],
+
+            ImageType::YUV420SP | ImageType::YUV_UV | ImageType::YUV_UV32 => {
+                &[PACKED_8, CHROMA_420_UV]
+            },
+
+            ImageType::YUV422 | ImageType::YUV422PLANAR => &[PACKED_8, CHROMA_422, CHROMA_422],
+
+            ImageType::YUV444PLANAR => &[PACKED_8, PACKED_8, PACKED_8],
+
+            _ => &[]
+        }
+    }
+
+    // pitch of the first plane, raster rows are padded to 32 bytes and t-format rows to whole
+    // tiles, the other planes have pitches scaled from this one
+    pub fn required_pitch(&self, width: uint32_t) -> Option<uint32_t> {
+        let plane = self.planes().first()?;
+
+        let align = match self.tile() {
+            Some((bytes, _)) => bytes,
+            None             => PITCH_ALIGN
+        };
+
+        let bytes = (width as u64 * plane.bits_per_pixel as u64).div_ceil(8)
+                                                                 .next_multiple_of(align as u64);
+
+        uint32_t::try_from(bytes).ok()
+    }
+
+    // rows vc_image allocates for the first plane of a resource, the luma plane of YUV420 is
+    // padded to a multiple of 16 rows and its chroma planes follow at half that
+    pub fn resource_rows(&self, height: uint32_t) -> Option<uint32_t> {
+        match *self {
+            ImageType::YUV420 => height.checked_next_multiple_of(16),
+            _                 => Some(height)
+        }
+    }
+
+    // width in bytes and height in rows of a 4k t-format tile
+    fn tile(&self) -> Option<(uint32_t, uint32_t)> {
+        if !self.is_tformat() {
+            return None;
+        }
+
+        if self.planes()[0].bits_per_pixel >= 16 {
+            Some((128, 32))
+        } else {
+            Some((64, 64))
+        }
+    }
+}
+
+impl TryFrom<uint32_t> for ImageType {
+    type Error = ImageTypeError;
+
+    fn try_from(value: uint32_t) -> Result<ImageType, ImageTypeError> {
+        match value {
+            1..=55 => Ok(IMAGE_TYPES[value as usize - 1]),
+            _      => Err(ImageTypeError(value))
+        }
+    }
+}
+
+impl error::Error for ImageTypeError {
+}
+
+impl fmt::Display for ImageTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not an image type", self.0)
+    }
+}
+
+impl error::Error for ImageViewError {
+}
+
+impl fmt::Display for ImageViewError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImageViewError::BufferTooSmall     => write!(f, "buffer too small"),
+            ImageViewError::InvalidDimensions  => write!(f, "invalid dimensions"),
+            ImageViewError::InvalidPitch       => write!(f, "invalid pitch"),
+            ImageViewError::Unsupported(type_) => write!(f, "cannot view {:?} as an image", type_)
+        }
+    }
+}
+
+impl Rect {
+    pub fn new(x: int32_t, y: int32_t, width: int32_t, height: int32_t) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height
+        }
+    }
+
+    // a rect at the origin, as wide and high as a display or resource
+    pub fn from_size(width: uint32_t, height: uint32_t) -> Rect {
+        Rect::new(0, 0, width.min(i32::MAX as uint32_t) as int32_t,
+                  height.min(i32::MAX as uint32_t) as int32_t)
+    }
+
+    pub fn bottom(&self) -> int32_t {
+        self.y.saturating_add(self.height)
+    }
+
+    // the part of the rect on a display of the given size
+    pub fn clip_to(&self, width: uint32_t, height: uint32_t) -> Option<Rect> {
+        self.intersect(&Rect::from_size(width, height))
+    }
+
+    pub fn contains(&self, x: int32_t, y: int32_t) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    pub fn intersect(&self, other: &Rect) -> Option<Rect> {
+        let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+
+        let rect = Rect::new(x, y, span(x, self.right().min(other.right())),
+                             span(y, self.bottom().min(other.bottom())));
+
+        if rect.is_empty() { None } else { Some(rect) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    pub fn right(&self) -> int32_t {
+        self.x.saturating_add(self.width)
+    }
+
+    // scales both corners about the origin and rounds them to the nearest pixel
+    pub fn scale(&self, x_scale: f64, y_scale: f64) -> Rect {
+        let x      = (self.x as f64 * x_scale).round();
+        let y      = (self.y as f64 * y_scale).round();
+        let right  = (self.right() as f64 * x_scale).round();
+        let bottom = (self.bottom() as f64 * y_scale).round();
+
+        Rect::new(x as int32_t, y as int32_t, (right - x) as int32_t, (bottom - y) as int32_t)
+    }
+
+    pub fn translate(&self, dx: int32_t, dy: int32_t) -> Rect {
+        Rect::new(self.x.saturating_add(dx), self.y.saturating_add(dy), self.width, self.height)
+    }
+
+    // the smallest rect covering both, empty rects are ignored
+    pub fn union(&self, other: &Rect) -> Rect {
+        if other.is_empty() {
+            return *self;
+        }
+
+        if self.is_empty() {
+            return *other;
+        }
+
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+
+        Rect::new(x, y, span(x, self.right().max(other.right())),
+                  span(y, self.bottom().max(other.bottom())))
+    }
+}
+
+impl SrcRect {
+    // whole pixels, clamped to the -32768 to 32767 that fit in 16.16
+    pub fn new(x: int32_t, y: int32_t, width: int32_t, height: int32_t) -> SrcRect {
+        let fixed = |value: int32_t| value.clamp(i16::MIN as int32_t, i16::MAX as int32_t) << 16;
+
+        SrcRect {
+            x:      fixed(x),
+            y:      fixed(y),
+            width:  fixed(width),
+            height: fixed(height)
+        }
+    }
+
+    pub fn from_f64(x: f64, y: f64, width: f64, height: f64) -> SrcRect {
+        let fixed = |value: f64| (value * 65536.0).round() as int32_t;
+
+        SrcRect {
+            x:      fixed(x),
+            y:      fixed(y),
+            width:  fixed(width),
+            height: fixed(height)
+        }
+    }
+
+    // the raw 16.16 values
+    pub fn from_fixed(x: int32_t, y: int32_t, width: int32_t, height: int32_t) -> SrcRect {
+        SrcRect {
+            x,
+            y,
+            width,
+            height
+        }
+    }
+
+    // the whole of a resource
+    pub fn from_size(width: uint32_t, height: uint32_t) -> SrcRect {
+        let whole = |value: uint32_t| value.min(i16::MAX as uint32_t) as int32_t;
+
+        SrcRect::new(0, 0, whole(width), whole(height))
+    }
+
+    pub fn to_f64(&self) -> (f64, f64, f64, f64) {
+        (self.x as f64 / 65536.0, self.y as f64 / 65536.0, self.width as f64 / 65536.0,
+         self.height as f64 / 65536.0)
+    }
+
+    // whole pixels covering the rect
+    pub fn to_rect(&self) -> Rect {
+        let (x, y) = (self.x >> 16, self.y >> 16);
+
+        // the far edge can pass i32::MAX before it is shifted back to whole pixels
+        let right  = (self.x as i64 + self.width as i64 + 0xffff) >> 16;
+        let bottom = (self.y as i64 + self.height as i64 + 0xffff) >> 16;
+
+        Rect::new(x, y, (right - x as i64) as int32_t, (bottom - y as i64) as int32_t)
+    }
+}
+
+impl From<Rect> for SrcRect {
+    fn from(rect: Rect) -> SrcRect {
+        SrcRect::new(rect.x, rect.y, rect.width, rect.height)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// distance from start to end, kept within int32_t when the edges are far apart
+fn span(start: int32_t, end: int32_t) -> int32_t {
+    (end as i64 - start as i64).clamp(i32::MIN as i64, i32::MAX as i64) as int32_t
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+    use std::mem;
+
+    use super::{ Image,
+                 ImageInfo,
+                 ImageTransform,
+                 ImageType,
+                 ImageTypeError,
+                 ImageView,
+                 ImageViewError,
+                 Plane,
+                 Rect,
+                 SrcRect,
+                 IMAGE_TYPES };
+
+    // offsets from vc_image_structs.h, VC_IMAGE_T is padded to 64 bytes on the 32 bit videocore
+    #[test]
+    pub fn image_layout_pass() {
+        assert_eq!(mem::size_of::<ImageInfo>(), 2);
+        assert_eq!(mem::offset_of!(Image, info), 2);
+        assert_eq!(mem::offset_of!(Image, width), 4);
+        assert_eq!(mem::offset_of!(Image, pitch), 8);
+        assert_eq!(mem::offset_of!(Image, image_data), 16);
+
+        if cfg!(target_pointer_width = "32") {
+            assert_eq!(mem::size_of::<Image>(), 64);
+            assert_eq!(mem::align_of::<Image>(), 4);
+            assert_eq!(mem::offset_of!(Image, extra), 20);
+            assert_eq!(mem::offset_of!(Image, metadata), 32);
+            assert_eq!(mem::offset_of!(Image, mem_handle), 40);
+            assert_eq!(mem::offset_of!(Image, num_channels), 56);
+        } else {
+            assert_eq!(mem::size_of::<Image>(), 80);
+            assert_eq!(mem::align_of::<Image>(), 8);
+            assert_eq!(mem::offset_of!(Image, extra), 24);
+            assert_eq!(mem::offset_of!(Image, metadata), 40);
+            assert_eq!(mem::offset_of!(Image, mem_handle), 56);
+            assert_eq!(mem::offset_of!(Image, num_channels), 72);
+        }
+    }
+
+    #[test]
+    pub fn image_transform_pass() {
+        // the top left corner of a rotation by 90 lands top right
+        assert_eq!(ImageTransform::ROT90.map(0, 0), (1, 0));
+        assert_eq!(ImageTransform::ROT90.then(ImageTransform::ROT90), ImageTransform::ROT180);
+        assert_eq!(ImageTransform::ROT90.then(ImageTransform::MIRROR_ROT0),
+                   ImageTransform::MIRROR_ROT90);
+        assert_eq!(ImageTransform::MIRROR_ROT0.then(ImageTransform::ROT90),
+                   ImageTransform::MIRROR_ROT270);
+        assert_eq!(ImageTransform::ROT90.inverse(), ImageTransform::ROT270);
+
+        for transform in (0..8).map(ImageTransform) {
+            assert_eq!(transform.then(transform.inverse()), ImageTransform::ROT0);
+            assert_eq!(transform.inverse().then(transform), ImageTransform::ROT0);
+        }
+    }
+
+    #[test]
+    pub fn image_type_buffer_size_pass() {
+        assert_eq!(ImageType::RGB565.required_pitch(100), Some(224));
+        assert_eq!(ImageType::RGB565.buffer_size(100, 10), Some(2240));
+        assert_eq!(ImageType::RGBA32.required_pitch(8), Some(32));
+
+        // chroma planes are half the luma pitch and rounded up in height
+        assert_eq!(ImageType::YUV420.bits_per_pixel(), Some(12));
+        assert_eq!(ImageType::YUV420.buffer_size(64, 3), Some(64 * 3 + 2 * 32 * 2));
+        assert_eq!(ImageType::YUV420SP.buffer_size(64, 4), Some(64 * 4 + 64 * 2));
+
+        // resources pad the luma plane of YUV420 to 16 rows
+        assert_eq!(ImageType::YUV420.resource_rows(3), Some(16));
+        assert_eq!(ImageType::YUV420.resource_rows(32), Some(32));
+        assert_eq!(ImageType::YUV420.resource_rows(u32::MAX), None);
+        assert_eq!(ImageType::RGB888.resource_rows(3), Some(3));
+
+        // t-format pads to whole tiles
+        assert_eq!(ImageType::TF_RGBA32.required_pitch(10), Some(128));
+        assert_eq!(ImageType::TF_RGBA32.buffer_size(10, 10), Some(128 * 32));
+
+        assert_eq!(ImageType::BAYER.bits_per_pixel(), None);
+        assert_eq!(ImageType::CODEC.buffer_size(16, 16), None);
+    }
+
+    #[test]
+    pub fn image_type_flags_pass() {
+        assert!(ImageType::ARGB8888.has_alpha());
+        assert!(!ImageType::XRGB8888.has_alpha());
+        assert!(ImageType::TF_PAL4.is_palettised());
+        assert!(ImageType::TF_PAL4.is_tformat());
+        assert!(!ImageType::RGB888.is_tformat());
+        assert!(ImageType::YUV422UYVY.is_yuv());
+        assert!(!ImageType::RGBA16.is_yuv());
+
+        assert_eq!(ImageType::YUV422PLANAR.planes()[1],
+                   Plane {
+                       bits_per_pixel: 8,
+                       h_subsampling:  2,
+                       v_subsampling:  1
+                   });
+    }
+
+    #[test]
+    pub fn image_type_try_from_pass() {
+        for (i, type_) in IMAGE_TYPES.iter().enumerate() {
+            assert_eq!(*type_ as u32, i as u32 + 1);
+            assert_eq!(ImageType::try_from(i as u32 + 1), Ok(*type_));
+        }
+
+        assert_eq!(ImageType::try_from(0), Err(ImageTypeError(0)));
+        assert_eq!(ImageType::try_from(ImageType::MAX as u32),
+                   Err(ImageTypeError(ImageType::MAX as u32)));
+    }
+
+    #[test]
+    pub fn image_view_pass() {
+        let data    = [0; 64 * 4 + 2 * 32 * 2];
+        let palette = [0xf800u16; 16];
+
+        let view  = ImageView::new(&data, ImageType::YUV420, 64, 4, 64).unwrap();
+        let view  = view.info(ImageInfo::CSC_ITUR_BT709);
+        let image = view.image();
+
+        assert_eq!(image.image_type(), Ok(ImageType::YUV420));
+        assert_eq!((image.width, image.height, image.pitch, image.size), (64, 4, 64, 384));
+        assert_eq!(image.image_data as *const u8, data.as_ptr());
+        assert!(image.info.contains(ImageInfo::CSC_ITUR_BT709));
+        assert_eq!(view.as_ptr(), image as *const Image);
+
+        let view = ImageView::new(&data, ImageType::_4BPP, 3, 2, 2).unwrap().palette(&palette);
+
+        assert_eq!(unsafe { view.image().extra.palette.palette } as *const u16, palette.as_ptr());
+
+        assert_eq!(ImageView::new(&data, ImageType::YUV420, 64, 5, 64).err(),
+                   Some(ImageViewError::BufferTooSmall));
+        assert_eq!(ImageView::new(&data, ImageType::RGB888, 4, 1, 11).err(),
+                   Some(ImageViewError::InvalidPitch));
+        assert_eq!(ImageView::new(&data, ImageType::RGB888, 70000, 1, 1).err(),
+                   Some(ImageViewError::InvalidDimensions));
+        assert_eq!(ImageView::new(&data, ImageType::BAYER, 4, 1, 4).err(),
+                   Some(ImageViewError::Unsupported(ImageType::BAYER)));
+    }
+
+    #[test]
+    pub fn rect_pass() {
+        let rect = Rect::new(10, 20, 30, 40);
+
+        assert_eq!((rect.right(), rect.bottom()), (40, 60));
+        assert!(rect.contains(10, 20) && rect.contains(39, 59));
+        assert!(!rect.contains(40, 20) && !rect.contains(9, 20));
+
+        assert_eq!(rect.intersect(&Rect::new(30, 50, 100, 100)), Some(Rect::new(30, 50, 10, 10)));
+        assert_eq!(rect.intersect(&Rect::new(40, 20, 10, 10)), None);
+        assert_eq!(rect.union(&Rect::new(0, 0, 5, 5)), Rect::new(0, 0, 40, 60));
+        assert_eq!(rect.union(&Rect::new(100, 100, 0, 5)), rect);
+        assert_eq!(Rect::new(0, 0, 0, 0).union(&rect), rect);
+
+        assert_eq!(rect.translate(-15, 5), Rect::new(-5, 25, 30, 40));
+        assert_eq!(rect.scale(0.5, 1.5), Rect::new(5, 30, 15, 60));
+        assert_eq!(rect.translate(-15, 5).clip_to(20, 50), Some(Rect::new(0, 25, 20, 25)));
+        assert_eq!(rect.clip_to(10, 10), None);
+
+        assert!(Rect::new(0, 0, 0, 10).is_empty() && Rect::new(0, 0, 10, -1).is_empty());
+        assert_eq!(Rect::from_size(1920, 1080), Rect::new(0, 0, 1920, 1080));
+
+        // edges at the far ends of the range
+        let (low, high) = (Rect::new(i32::MIN, 0, 1, 1), Rect::new(i32::MAX - 1, 0, 1, 1));
+
+        assert_eq!(low.intersect(&high), None);
+        assert_eq!(low.union(&high), Rect::new(i32::MIN, 0, i32::MAX, 1));
+        assert_eq!(Rect::new(i32::MAX - 1, 0, 10, 1).intersect(&Rect::new(0, 0, i32::MAX, 1)),
+                   Some(Rect::new(i32::MAX - 1, 0, 1, 1)));
+    }
+
+    #[test]
+    pub fn src_rect_pass() {
+        // a whole 1 pixel resource is 65536 wide, not 1
+        assert_eq!(SrcRect::from_size(1, 1), SrcRect::from_fixed(0, 0, 1 << 16, 1 << 16));
+        assert_eq!(SrcRect::from(Rect::new(2, 3, 4, 5)), SrcRect::new(2, 3, 4, 5));
+        assert_eq!(SrcRect::from_f64(0.5, 1.25, 2.0, 0.75),
+                   SrcRect::from_fixed(0x8000, 0x14000, 0x20000, 0xc000));
+        assert_eq!(SrcRect::from_f64(0.5, 1.25, 2.0, 0.75).to_f64(), (0.5, 1.25, 2.0, 0.75));
+
+        // partial pixels are covered by whole ones
+        assert_eq!(SrcRect::from_f64(0.5, 1.25, 2.0, 0.75).to_rect(), Rect::new(0, 1, 3, 1));
+        assert_eq!(SrcRect::new(7, 8, 9, 10).to_rect(), Rect::new(7, 8, 9, 10));
+
+        // whole pixels outside 16.16 are clamped rather than wrapped
+        assert_eq!(SrcRect::new(40000, -40000, 32767, 0),
+                   SrcRect::from_fixed(i16::MAX as i32 * 65536, i32::MIN, i16::MAX as i32 * 65536,
+                                       0));
+        assert_eq!(SrcRect::from(Rect::from_size(u32::MAX, 1)), SrcRect::from_size(32767, 1));
+        assert_eq!(SrcRect::from_fixed(i32::MAX, i32::MAX, i32::MAX, i32::MAX).to_rect(),
+                   Rect::new(32767, 32767, 32769, 32769));
+    }
+}
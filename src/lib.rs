@@ -28,20 +28,93 @@ extern crate libc;
 // LINKING
 // -------------------------------------------------------------------------------------------------
 
+// the firmware libraries only exist on the pi, elsewhere the software backend is used instead
+#[cfg(not(videocore_software))]
 #[link(name = "bcm_host")]
 #[link(name = "vcos")]
 extern {}
 
-#[cfg(target_arch = "arm")]
+#[cfg(all(not(videocore_software), target_arch = "arm"))]
 #[link(name = "vchiq_arm")]
 extern {}
 
+// -------------------------------------------------------------------------------------------------
+// MACROS
+// -------------------------------------------------------------------------------------------------
+
+// operators for the #[repr(transparent)] newtypes standing in for the OR-ed flag enums of the C
+// API, each type provides its own is_valid()
+macro_rules! flags {
+    ($name:ident) => {
+        impl $name {
+            pub fn bits(&self) -> u32 {
+                self.0
+            }
+
+            pub fn contains(&self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            pub fn from_bits(bits: u32) -> Option<$name> {
+                let flags = $name(bits);
+
+                if flags.is_valid() {
+                    Some(flags)
+                } else {
+                    None
+                }
+            }
+        }
+
+        impl ::std::ops::BitAnd for $name {
+            type Output = $name;
+
+            fn bitand(self, other: $name) -> $name {
+                $name(self.0 & other.0)
+            }
+        }
+
+        impl ::std::ops::BitAndAssign for $name {
+            fn bitand_assign(&mut self, other: $name) {
+                self.0 &= other.0;
+            }
+        }
+
+        impl ::std::ops::BitOr for $name {
+            type Output = $name;
+
+            fn bitor(self, other: $name) -> $name {
+                $name(self.0 | other.0)
+            }
+        }
+
+        impl ::std::ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, other: $name) {
+                self.0 |= other.0;
+            }
+        }
+    }
+}
+
 // -------------------------------------------------------------------------------------------------
 // MODULES
 // -------------------------------------------------------------------------------------------------
 
+pub mod backend;
+pub mod bayer;
 pub mod bcm_host;
+pub mod capture;
+pub mod convert;
+pub mod decode;
+pub mod demosaic;
 pub mod display;
+pub mod dither;
 pub mod dispmanx;
+pub mod etc1;
 pub mod image;
+pub mod palette;
+pub mod record;
+pub mod rotate;
+pub mod tformat;
 pub mod vchi;
+pub mod yuv;
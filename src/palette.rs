@@ -0,0 +1,456 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use libc::uint32_t;
+
+use std::collections::HashMap;
+
+// local
+use convert::{ self,
+               Error,
+               Layout };
+
+use image::ImageType;
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// colours in the RGB565 form vc_dispmanx_resource_set_palette expects
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Palette {
+    entries: Vec<u16>
+}
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl Palette {
+    pub fn new() -> Palette {
+        Palette::default()
+    }
+
+    pub fn from_rgb(colours: &[[u8; 3]]) -> Palette {
+        let mut palette = Palette::new();
+
+        for &colour in colours {
+            palette.push(colour);
+        }
+
+        palette
+    }
+
+    pub fn from_rgb565(entries: Vec<u16>) -> Palette {
+        Palette {
+            entries
+        }
+    }
+
+    pub fn entries(&self) -> &[u16] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // index of the entry closest to a colour, 0 when the palette is empty
+    pub fn nearest(&self, colour: [u8; 3]) -> u8 {
+        (0..self.entries.len()).min_by_key(|&i| distance(self.rgb(i as u8), colour))
+                               .unwrap_or(0) as u8
+    }
+
+    pub fn push(&mut self, colour: [u8; 3]) {
+        self.entries.push(rgb565(colour));
+    }
+
+    // the colour the display shows for an entry
+    pub fn rgb(&self, index: u8) -> [u8; 3] {
+        let entry = self.entries[index as usize].to_le_bytes();
+        let pixel = convert::read_pixel(ImageType::RGB565, &entry, 0);
+
+        [pixel[0], pixel[1], pixel[2]]
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+fn diffuse(pixels: &[[u8; 3]], width: usize, palette: &Palette) -> Vec<u8> {
+    // errors in sixteenths for this row and the next, with a pixel of margin either side
+    let mut errors = [vec![[0i32; 3]; width + 2], vec![[0i32; 3]; width + 2]];
+    let mut out    = Vec::with_capacity(pixels.len());
+
+    for row in pixels.chunks(width) {
+        for (x, &pixel) in row.iter().enumerate() {
+            let mut value = [0; 3];
+
+            for (c, channel) in value.iter_mut().enumerate() {
+                *channel = (pixel[c] as i32 + errors[0][x + 1][c] / 16).clamp(0, 255) as u8;
+            }
+
+            let index = palette.nearest(value);
+            let shown = palette.rgb(index);
+
+            for c in 0..3 {
+                let error = value[c] as i32 - shown[c] as i32;
+
+                errors[0][x + 2][c] += error * 7;
+                errors[1][x][c]     += error * 3;
+                errors[1][x + 1][c] += error * 5;
+                errors[1][x + 2][c] += error;
+            }
+
+            out.push(index);
+        }
+
+        errors.swap(0, 1);
+        errors[1].iter_mut().for_each(|error| *error = [0; 3]);
+    }
+
+    out
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter().zip(b.iter()).map(|(&a, &b)| (a as i32 - b as i32).pow(2) as u32).sum()
+}
+
+// splits the colours of an image into boxes by the median of their widest channel until there
+// are as many boxes as colours wanted, each entry is the average of its box
+fn median_cut(histogram: Vec<([u32; 3], u32)>, colours: usize) -> Palette {
+    let mut boxes = vec![histogram];
+
+    while boxes.len() < colours {
+        let widest = boxes.iter()
+                          .enumerate()
+                          .filter(|&(_, b)| b.len() > 1)
+                          .map(|(i, b)| (i, widest_channel(b)))
+                          .max_by_key(|&(_, (_, range))| range);
+
+        let (i, channel) = match widest {
+            Some((i, (channel, _))) => (i, channel),
+            None                    => break
+        };
+
+        let mut colours = boxes.swap_remove(i);
+
+        colours.sort_by_key(|&(colour, _)| colour[channel]);
+
+        // split where half the pixels fall on each side, keeping both sides non-empty
+        let total    = colours.iter().map(|&(_, count)| count as u64).sum::<u64>();
+        let mut seen = 0;
+
+        let split = colours.iter()
+                           .position(|&(_, count)| {
+                               seen += count as u64;
+                               seen * 2 >= total
+                           })
+                           .map_or(1, |i| i + 1)
+                           .clamp(1, colours.len() - 1);
+
+        let upper = colours.split_off(split);
+
+        boxes.push(colours);
+        boxes.push(upper);
+    }
+
+    let averages: Vec<[u8; 3]> = boxes.iter().map(|b| {
+        let total = b.iter().map(|&(_, count)| count as u64).sum::<u64>().max(1);
+        let mut sum = [0u64; 3];
+
+        for &(colour, count) in b {
+            for (s, &c) in sum.iter_mut().zip(colour.iter()) {
+                *s += c as u64 * count as u64;
+            }
+        }
+
+        [(sum[0] / total) as u8, (sum[1] / total) as u8, (sum[2] / total) as u8]
+    }).collect();
+
+    Palette::from_rgb(&averages)
+}
+
+// packs one index per byte into the rows of a _4BPP or _8BPP image, even pixels of _4BPP rows take
+// the low nibble
+pub fn pack(indices: &[u8], dst: &mut [u8], dst_layout: Layout, width: uint32_t,
+            height: uint32_t) -> Result<(), Error> {
+    if !matches!(dst_layout.type_, ImageType::_4BPP | ImageType::_8BPP) {
+        return Err(Error::Unsupported(ImageType::_8BPP, dst_layout.type_));
+    }
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let row = dst_layout.check(dst.len(), width, height)?;
+
+    if indices.len() < width as usize * height as usize {
+        return Err(Error::BufferTooSmall);
+    }
+
+    for (src, dst) in indices.chunks(width as usize).zip(dst.chunks_mut(dst_layout.pitch)) {
+        if dst_layout.type_ == ImageType::_8BPP {
+            dst[..row].copy_from_slice(src);
+            continue;
+        }
+
+        for (pair, byte) in src.chunks(2).zip(dst[..row].iter_mut()) {
+            *byte = pair[0] & 0x0f | pair.get(1).map_or(0, |&index| index << 4);
+        }
+    }
+
+    Ok(())
+}
+
+// reduces an image to at most `colours` entries by median cut and maps every pixel to one, with
+// floyd-steinberg error diffusion when dithering, alpha is ignored
+pub fn quantize(src: &[u8], src_layout: Layout, width: uint32_t, height: uint32_t, colours: usize,
+                dither: bool) -> Result<(Palette, Vec<u8>), Error> {
+    if !convert::is_supported(src_layout.type_) {
+        return Err(Error::Unsupported(src_layout.type_, ImageType::_8BPP));
+    }
+
+    if width == 0 || height == 0 {
+        return Ok((Palette::new(), Vec::new()));
+    }
+
+    src_layout.check(src.len(), width, height)?;
+
+    let (width, height) = (width as usize, height as usize);
+
+    let mut pixels = Vec::with_capacity(width * height);
+
+    for y in 0..height {
+        let row = &src[y * src_layout.pitch..];
+
+        pixels.extend((0..width).map(|x| {
+            let pixel = convert::read_pixel(src_layout.type_, row, x);
+
+            [pixel[0], pixel[1], pixel[2]]
+        }));
+    }
+
+    // colours that look the same on the display share a bin
+    let mut bins: HashMap<u16, ([u32; 3], u32)> = HashMap::new();
+
+    for &pixel in &pixels {
+        let bin = bins.entry(rgb565(pixel)).or_insert(([0; 3], 0));
+
+        for (sum, &channel) in bin.0.iter_mut().zip(pixel.iter()) {
+            *sum += channel as u32;
+        }
+
+        bin.1 += 1;
+    }
+
+    let histogram = bins.values()
+                        .map(|&(sum, count)| {
+                            ([sum[0] / count, sum[1] / count, sum[2] / count], count)
+                        })
+                        .collect();
+
+    let palette = median_cut(histogram, colours.clamp(1, 256));
+
+    let indices = if dither {
+        diffuse(&pixels, width, &palette)
+    } else {
+        let mut cache = HashMap::new();
+
+        pixels.iter()
+              .map(|&pixel| *cache.entry(pixel).or_insert_with(|| palette.nearest(pixel)))
+              .collect()
+    };
+
+    Ok((palette, indices))
+}
+
+fn rgb565(colour: [u8; 3]) -> u16 {
+    let mut entry = [0; 2];
+
+    convert::write_pixel(ImageType::RGB565, &mut entry, 0, [colour[0], colour[1], colour[2], 255]);
+
+    u16::from_le_bytes(entry)
+}
+
+// unpacks the rows of a _4BPP or _8BPP image into one index per byte
+pub fn unpack(src: &[u8], src_layout: Layout, indices: &mut [u8], width: uint32_t,
+              height: uint32_t) -> Result<(), Error> {
+    if !matches!(src_layout.type_, ImageType::_4BPP | ImageType::_8BPP) {
+        return Err(Error::Unsupported(src_layout.type_, ImageType::_8BPP));
+    }
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let row = src_layout.check(src.len(), width, height)?;
+
+    if indices.len() < width as usize * height as usize {
+        return Err(Error::BufferTooSmall);
+    }
+
+    for (dst, src) in indices.chunks_mut(width as usize).zip(src.chunks(src_layout.pitch)) {
+        if src_layout.type_ == ImageType::_8BPP {
+            dst.copy_from_slice(&src[..row]);
+            continue;
+        }
+
+        for (pair, &byte) in dst.chunks_mut(2).zip(src[..row].iter()) {
+            pair[0] = byte & 0x0f;
+
+            if let Some(index) = pair.get_mut(1) {
+                *index = byte >> 4;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn widest_channel(colours: &[([u32; 3], u32)]) -> (usize, u32) {
+    (0..3).map(|c| {
+              let values = colours.iter().map(|&(colour, _)| colour[c]);
+
+              (c, values.clone().max().unwrap_or(0) - values.min().unwrap_or(0))
+          })
+          .max_by_key(|&(_, range)| range)
+          .unwrap()
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use convert::{ Error,
+                   Layout };
+
+    use image::ImageType;
+
+    use super::{ diffuse,
+                 pack,
+                 quantize,
+                 unpack,
+                 Palette };
+
+    #[test]
+    pub fn palette_dither_pass() {
+        // a grey between black and white comes out as a mix of both only when dithering
+        let src     = [128; 8 * 8 * 3];
+        let layout  = Layout::new(ImageType::RGB888, 24);
+        let palette = Palette::from_rgb(&[[0, 0, 0], [255, 255, 255]]);
+
+        let (quantized, flat) = quantize(&src, layout, 8, 8, 2, false).unwrap();
+
+        assert_eq!(quantized.len(), 1);
+        assert!(flat.iter().all(|&index| index == 0));
+
+        let pixels = vec![[128; 3]; 64];
+        let white  = diffuse(&pixels, 8, &palette).iter().filter(|&&i| i == 1).count();
+
+        assert!((28..=36).contains(&white), "{}", white);
+    }
+
+    #[test]
+    pub fn palette_entries_pass() {
+        let mut palette = Palette::from_rgb(&[[255, 0, 0], [0, 255, 0]]);
+
+        palette.push([0, 0, 255]);
+
+        assert_eq!(palette.entries(), [0xf800, 0x07e0, 0x001f]);
+        assert_eq!(palette.rgb(1), [0, 255, 0]);
+        assert_eq!(palette.nearest([200, 30, 40]), 0);
+        assert_eq!(Palette::new().nearest([1, 2, 3]), 0);
+    }
+
+    #[test]
+    pub fn palette_errors_pass() {
+        let mut out = [0; 16];
+
+        assert_eq!(pack(&[0; 4], &mut out, Layout::new(ImageType::RGB565, 4), 2, 2),
+                   Err(Error::Unsupported(ImageType::_8BPP, ImageType::RGB565)));
+        assert_eq!(pack(&[0; 3], &mut out, Layout::new(ImageType::_8BPP, 2), 2, 2),
+                   Err(Error::BufferTooSmall));
+        assert_eq!(quantize(&out, Layout::new(ImageType::YUV420, 4), 2, 2, 4, false),
+                   Err(Error::Unsupported(ImageType::YUV420, ImageType::_8BPP)));
+    }
+
+    #[test]
+    pub fn palette_pack_pass() {
+        let indices = [1, 2, 3, 4, 5, 6];
+        let mut out = [0; 4];
+
+        pack(&indices, &mut out, Layout::new(ImageType::_4BPP, 2), 3, 2).unwrap();
+
+        assert_eq!(out, [0x21, 0x03, 0x54, 0x06]);
+
+        let mut back = [0; 6];
+
+        unpack(&out, Layout::new(ImageType::_4BPP, 2), &mut back, 3, 2).unwrap();
+
+        assert_eq!(back, indices);
+
+        pack(&indices, &mut out, Layout::new(ImageType::_8BPP, 3), 1, 2).unwrap();
+        unpack(&out, Layout::new(ImageType::_8BPP, 3), &mut back, 1, 2).unwrap();
+
+        assert_eq!(out[..4], [1, 0x03, 0x54, 2]);
+        assert_eq!(back[..2], [1, 2]);
+    }
+
+    #[test]
+    pub fn palette_quantize_pass() {
+        // an image with fewer colours than entries survives exactly
+        let colours = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255], [0, 0, 0]];
+        let src: Vec<u8> = (0..40).flat_map(|i| colours[i * 7 % 5].to_vec()).collect();
+
+        let (palette, indices) = quantize(&src, Layout::new(ImageType::RGB888, 24), 8, 5, 16,
+                                          false).unwrap();
+
+        assert_eq!(palette.len(), 5);
+
+        for (i, &index) in indices.iter().enumerate() {
+            assert_eq!(palette.rgb(index), colours[i * 7 % 5]);
+        }
+
+        // a gradient reduced to 16 colours keeps its average with dithering
+        let src: Vec<u8> = (0..64 * 4).flat_map(|i| [(i % 64 * 4) as u8; 3].to_vec()).collect();
+
+        let (palette, indices) = quantize(&src, Layout::new(ImageType::RGB888, 192), 64, 4, 16,
+                                          true).unwrap();
+
+        assert_eq!(palette.len(), 16);
+
+        let mean = |values: &mut dyn Iterator<Item = u32>| values.sum::<u32>() as f64 / 256.0;
+
+        let before = mean(&mut src.iter().step_by(3).map(|&v| v as u32));
+        let after  = mean(&mut indices.iter().map(|&i| palette.rgb(i)[0] as u32));
+
+        assert!((before - after).abs() < 2.0, "{} {}", before, after);
+    }
+}
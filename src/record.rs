@@ -0,0 +1,671 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use std::{ error,
+           fmt,
+           io,
+           thread };
+
+use std::io::Write;
+use std::time::{ Duration,
+                 Instant };
+
+use libc::uint32_t;
+
+// local
+use capture;
+
+use dispmanx::{ self,
+                Display,
+                ErrorKind,
+                Resource,
+                Transform,
+                VsyncStream };
+
+use image::ImageType;
+
+use yuv::{ ChromaSiting,
+           Colorspace,
+           Range };
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+// when frames are due, counting the ones that went by while the last was being taken
+enum Clock {
+    Fixed {
+        start: Instant,
+        rate:  (uint32_t, uint32_t),
+        ticks: u64
+    },
+
+    Vsync(VsyncStream)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Dispmanx(dispmanx::Error),
+    Io(io::Error),
+
+    // the display stopped sending vsyncs while its rate was being measured
+    VsyncTimeout
+}
+
+// Fixed is frames per second as numerator and denominator, Vsync takes a frame on every vsync
+// and measures the rate of the display from its first vsyncs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rate {
+    Fixed(uint32_t, uint32_t),
+    Vsync
+}
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+// stream parameters of a 4:2:0 progressive YUV4MPEG2 file, aspect is that of a pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Header {
+    pub width:  uint32_t,
+    pub height: uint32_t,
+    pub rate:   (uint32_t, uint32_t),
+    pub aspect: (uint32_t, uint32_t),
+    pub siting: ChromaSiting,
+    pub range:  Range
+}
+
+// frames are snapshotted at width x height, the display size when unset
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Options {
+    pub width:    Option<uint32_t>,
+    pub height:   Option<uint32_t>,
+    pub rate:     Rate,
+    pub snapshot: capture::Options
+}
+
+// snapshots a display into a YUV420 resource and appends the frames to a Y4M stream, the clock
+// is declared first so the vsync callback is unregistered before the display is closed
+pub struct Recorder<W: Write> {
+    clock:     Clock,
+    display:   Display,
+    resource:  Resource,
+    writer:    Y4mWriter<W>,
+    transform: Transform,
+    buffer:    Vec<u8>,
+    pitch:     usize,
+    dropped:   u64
+}
+
+// writes YUV4MPEG2 frames from YUV420 buffers laid out like a resource, the last frame is kept
+// so it can be repeated in place of dropped ones
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    header: Header,
+    frame:  Vec<u8>,
+    frames: u64
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+// measured rates this close to a broadcast rate are written as that rate
+const RATE_TOLERANCE: f64 = 0.005;
+
+const STANDARD_RATES: [(uint32_t, uint32_t); 8] = [(24000, 1001), (24, 1), (25, 1), (30000, 1001),
+                                                   (30, 1), (50, 1), (60000, 1001), (60, 1)];
+
+// intervals timed before the header is written, about 130ms at 60Hz
+const VSYNC_SAMPLES: u32 = 8;
+
+const VSYNC_TIMEOUT: Duration = Duration::from_secs(1);
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl Clock {
+    // blocks until the next frame is due and returns how many were missed since the last
+    fn wait(&mut self) -> u64 {
+        match *self {
+            Clock::Fixed { start, rate, ref mut ticks } => {
+                let now = Instant::now();
+                let due = start + tick_time(rate, *ticks);
+
+                if now < due {
+                    thread::sleep(due - now);
+
+                    *ticks += 1;
+
+                    return 0;
+                }
+
+                let elapsed = now.duration_since(start).as_nanos();
+                let current = (elapsed * rate.0 as u128
+                               / (rate.1 as u128 * 1_000_000_000)) as u64;
+
+                let missed = current.saturating_sub(*ticks);
+
+                *ticks = current.max(*ticks) + 1;
+
+                missed
+            },
+
+            Clock::Vsync(ref mut stream) => stream.wait().missed
+        }
+    }
+}
+
+impl error::Error for Error {
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Dispmanx(ref error) => write!(f, "{}", error),
+            Error::Io(ref error)       => write!(f, "{}", error),
+            Error::VsyncTimeout        => write!(f, "no vsync while measuring the display rate")
+        }
+    }
+}
+
+impl From<dispmanx::Error> for Error {
+    fn from(error: dispmanx::Error) -> Error {
+        Error::Dispmanx(error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl Header {
+    // square pixels with the chroma siting and range of the default colorspace
+    pub fn new(width: uint32_t, height: uint32_t, rate: (uint32_t, uint32_t)) -> Header {
+        let colorspace = Colorspace::default();
+
+        Header {
+            width,
+            height,
+            rate,
+            aspect: (1, 1),
+            siting: colorspace.siting,
+            range:  colorspace.range
+        }
+    }
+
+    fn line(&self) -> String {
+        let chroma = match self.siting {
+            ChromaSiting::Center  => "420jpeg",
+            ChromaSiting::Left    => "420mpeg2",
+            ChromaSiting::TopLeft => "420paldv"
+        };
+
+        let range = match self.range {
+            Range::Full    => "FULL",
+            Range::Limited => "LIMITED"
+        };
+
+        format!("YUV4MPEG2 W{} H{} F{}:{} Ip A{}:{} C{} XCOLORRANGE={}\n", self.width, self.height,
+                self.rate.0, self.rate.1, self.aspect.0, self.aspect.1, chroma, range)
+    }
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            width:    None,
+            height:   None,
+            rate:     Rate::Vsync,
+            snapshot: capture::Options::new()
+        }
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    // writes the header straight away, once the first vsyncs have been timed for Rate::Vsync, odd
+    // sizes are rounded down as chroma covers 2 x 2 pixels
+    pub fn new(device: uint32_t, writer: W, options: &Options) -> Result<Recorder<W>, Error> {
+        let display = Display::open(device)?;
+        let info    = display.get_info()?;
+
+        let (display_width, display_height) = (info.width.max(2) as uint32_t,
+                                               info.height.max(2) as uint32_t);
+
+        let width  = options.width.unwrap_or(display_width).max(2) & !1;
+        let height = options.height.unwrap_or(display_height).max(2) & !1;
+
+        let resource = Resource::create(ImageType::YUV420, width, height)?;
+
+        let size = ImageType::YUV420.resource_rows(height)
+                                    .and_then(|rows| ImageType::YUV420.buffer_size(width, rows));
+
+        let (pitch, size) = match (ImageType::YUV420.required_pitch(width), size) {
+            (Some(pitch), Some(size)) => (pitch as usize, size),
+            _                         => {
+                return Err(dispmanx::Error::new("vc_dispmanx_resource_create",
+                                                ErrorKind::UnsupportedFormat).into())
+            }
+        };
+
+        let (rate, clock) = match options.rate {
+            Rate::Fixed(numerator, denominator) => {
+                let rate = (numerator, denominator);
+
+                (rate, Clock::Fixed {
+                           start: Instant::now(),
+                           rate,
+                           ticks: 0
+                       })
+            },
+
+            Rate::Vsync => {
+                let mut stream = display.vsync_stream()?;
+
+                (measure_vsync(&mut stream)?, Clock::Vsync(stream))
+            }
+        };
+
+        let header = Header {
+            aspect: pixel_aspect(display_width, display_height, width, height),
+            ..Header::new(width, height, rate)
+        };
+
+        Ok(Recorder {
+               clock,
+               display,
+               resource,
+               writer: Y4mWriter::new(writer, header)?,
+               transform: options.snapshot.transform(),
+               buffer: vec![0; size],
+               pitch,
+               dropped: 0
+           })
+    }
+
+    // frames filled in with copies of the one before them
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn finish(self) -> Result<W, Error> {
+        let mut writer = self.writer.into_inner();
+
+        writer.flush()?;
+
+        Ok(writer)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.writer.frames()
+    }
+
+    pub fn header(&self) -> &Header {
+        self.writer.header()
+    }
+
+    // records until duration has passed, then leaves the recorder ready for more
+    pub fn record_for(&mut self, duration: Duration) -> Result<(), Error> {
+        let start = Instant::now();
+
+        while start.elapsed() < duration {
+            self.record_frame()?;
+        }
+
+        Ok(())
+    }
+
+    // waits for the next vsync or tick, then appends a snapshot, frames missed while waiting
+    // are repeats of the previous one so the stream keeps time
+    pub fn record_frame(&mut self) -> Result<(), Error> {
+        let missed = self.clock.wait();
+
+        if missed > 0 && self.writer.frames() > 0 {
+            self.writer.repeat(missed)?;
+            self.dropped += missed;
+        }
+
+        dispmanx::snapshot(self.display.as_raw(), self.resource.as_raw(), self.transform)?;
+
+        self.resource.read_planes(&mut self.buffer)?;
+        self.writer.write_frame(&self.buffer, self.pitch)?;
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut writer: W, header: Header) -> io::Result<Y4mWriter<W>> {
+        if header.width == 0 || header.height == 0 || header.rate.0 == 0 || header.rate.1 == 0
+           || header.aspect.0 == 0 || header.aspect.1 == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid y4m header"));
+        }
+
+        writer.write_all(header.line().as_bytes())?;
+
+        Ok(Y4mWriter {
+               writer,
+               header,
+               frame: Vec::new(),
+               frames: 0
+           })
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    // writes the last frame again count times, nothing is written before the first frame
+    pub fn repeat(&mut self, count: u64) -> io::Result<()> {
+        if self.frame.is_empty() {
+            return Ok(());
+        }
+
+        for _ in 0..count {
+            self.writer.write_all(b"FRAME\n")?;
+            self.writer.write_all(&self.frame)?;
+            self.frames += 1;
+        }
+
+        Ok(())
+    }
+
+    // laid out like a YUV420 resource, the chroma planes follow the luma rows padded to 16 and
+    // are at half the pitch
+    pub fn write_frame(&mut self, data: &[u8], pitch: usize) -> io::Result<()> {
+        let (width, height) = (self.header.width as usize, self.header.height as usize);
+
+        let rows = ImageType::YUV420.resource_rows(self.header.height);
+        let size = rows.and_then(|rows| {
+                           ImageType::YUV420.buffer_size_at_pitch(rows, pitch as uint32_t)
+                       });
+
+        if pitch < width || size.is_none_or(|size| data.len() < size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid yuv420 frame"));
+        }
+
+        let chroma_pitch = ImageType::YUV420.plane_pitch(1, pitch as uint32_t).unwrap() as usize;
+
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+        let rows = rows.unwrap() as usize;
+
+        let u = pitch * rows;
+        let v = u + chroma_pitch * rows / 2;
+
+        self.frame.clear();
+
+        for (offset, pitch, width, height) in [(0, pitch, width, height),
+                                               (u, chroma_pitch, chroma_width, chroma_height),
+                                               (v, chroma_pitch, chroma_width, chroma_height)] {
+            for row in 0..height {
+                let start = offset + row * pitch;
+
+                self.frame.extend_from_slice(&data[start..start + width]);
+            }
+        }
+
+        self.repeat(1)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// times the first vsyncs of a stream, the ones read here are not recorded
+fn measure_vsync(stream: &mut VsyncStream) -> Result<(uint32_t, uint32_t), Error> {
+    let mut next = || stream.wait_timeout(VSYNC_TIMEOUT).ok_or(Error::VsyncTimeout);
+
+    let first = next()?;
+
+    let mut last = first;
+
+    for _ in 0..VSYNC_SAMPLES {
+        last = next()?;
+    }
+
+    Ok(vsync_rate(last.frame - first.frame, last.timestamp.duration_since(first.timestamp)))
+}
+
+// aspect of a pixel once a display has been scaled to width x height
+fn pixel_aspect(display_width: uint32_t, display_height: uint32_t, width: uint32_t,
+                height: uint32_t) -> (uint32_t, uint32_t) {
+    let x = display_width as u64 * height as u64;
+    let y = display_height as u64 * width as u64;
+
+    let divisor = gcd(x, y).max(1);
+
+    ((x / divisor) as uint32_t, (y / divisor) as uint32_t)
+}
+
+// time of a tick since the clock started
+fn tick_time((numerator, denominator): (uint32_t, uint32_t), tick: u64) -> Duration {
+    let nanos = tick as u128 * denominator as u128 * 1_000_000_000 / numerator as u128;
+
+    Duration::from_nanos(nanos as u64)
+}
+
+// frames over elapsed as the closest broadcast rate, or to a thousandth of a frame per second
+// when none is near
+fn vsync_rate(frames: u64, elapsed: Duration) -> (uint32_t, uint32_t) {
+    let measured = frames as f64 / elapsed.as_secs_f64().max(1e-9);
+
+    let error = |&(numerator, denominator): &(uint32_t, uint32_t)| {
+        (numerator as f64 / denominator as f64 / measured - 1.0).abs()
+    };
+
+    let closest = STANDARD_RATES.iter()
+                                .min_by(|a, b| error(a).total_cmp(&error(b)))
+                                .unwrap();
+
+    if error(closest) < RATE_TOLERANCE {
+        return *closest;
+    }
+
+    let millis  = (measured * 1000.0).round().clamp(1.0, uint32_t::MAX as f64) as u64;
+    let divisor = gcd(millis, 1000);
+
+    ((millis / divisor) as uint32_t, (1000 / divisor) as uint32_t)
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use std::time::{ Duration,
+                     Instant };
+
+    use yuv::{ ChromaSiting,
+               Range };
+
+    use super::{ pixel_aspect,
+                 tick_time,
+                 vsync_rate,
+                 Clock,
+                 Header,
+                 Y4mWriter };
+
+    #[test]
+    pub fn clock_pass() {
+        assert_eq!(tick_time((30000, 1001), 30), Duration::from_nanos(1_001_000_000));
+
+        let mut clock = Clock::Fixed {
+            start: Instant::now() - Duration::from_millis(2500),
+            rate:  (1, 1),
+            ticks: 0
+        };
+
+        // ticks 0 and 1 went by while the third was due
+        assert_eq!(clock.wait(), 2);
+
+        match clock {
+            Clock::Fixed { ticks, .. } => assert_eq!(ticks, 3),
+            _                          => unreachable!()
+        }
+    }
+
+    #[test]
+    pub fn header_pass() {
+        let header = Header::new(640, 480, (30000, 1001));
+
+        assert_eq!(header.line(), "YUV4MPEG2 W640 H480 F30000:1001 Ip A1:1 C420mpeg2 \
+                                   XCOLORRANGE=LIMITED\n");
+
+        let header = Header {
+            aspect: pixel_aspect(1920, 1080, 640, 480),
+            siting: ChromaSiting::Center,
+            range:  Range::Full,
+            ..header
+        };
+
+        assert_eq!(header.aspect, (4, 3));
+        assert_eq!(header.line(), "YUV4MPEG2 W640 H480 F30000:1001 Ip A4:3 C420jpeg \
+                                   XCOLORRANGE=FULL\n");
+
+        assert!(Y4mWriter::new(Vec::new(), Header::new(640, 480, (0, 1))).is_err());
+        assert!(Y4mWriter::new(Vec::new(), Header::new(0, 480, (25, 1))).is_err());
+    }
+
+    #[test]
+    pub fn vsync_rate_pass() {
+        assert_eq!(vsync_rate(60, Duration::from_secs(1)), (60, 1));
+        assert_eq!(vsync_rate(8, Duration::from_micros(133_467)), (60000, 1001));
+        assert_eq!(vsync_rate(8, Duration::from_millis(320)), (25, 1));
+
+        // nothing standard within half a percent
+        assert_eq!(vsync_rate(8, Duration::from_millis(128)), (125, 2));
+        assert_eq!(vsync_rate(8, Duration::from_millis(200)), (40, 1));
+        // vsyncs stamped at the same instant still give a rate the header accepts
+        assert!(vsync_rate(8, Duration::ZERO).0 > 0);
+    }
+
+    #[test]
+    pub fn write_frame_pass() {
+        let mut writer = Y4mWriter::new(Vec::new(), Header::new(3, 3, (25, 1))).unwrap();
+
+        // a 4 byte luma pitch over 16 padded rows and 2 byte chroma pitch over 8
+        let data: Vec<u8> = (0..96).collect();
+
+        writer.repeat(2).unwrap();
+        writer.write_frame(&data, 4).unwrap();
+        writer.repeat(1).unwrap();
+
+        assert!(writer.write_frame(&data[..95], 4).is_err());
+        assert!(writer.write_frame(&data, 2).is_err());
+        assert_eq!(writer.frames(), 2);
+
+        let out   = writer.into_inner();
+        let start = out.iter().position(|&byte| byte == b'\n').unwrap() + 1;
+
+        let frame = [b"FRAME\n".to_vec(), vec![0, 1, 2, 4, 5, 6, 8, 9, 10, 64, 65, 66, 67, 80,
+                                               81, 82, 83]].concat();
+
+        assert_eq!(&out[start..], &[frame.clone(), frame].concat()[..]);
+    }
+
+    #[cfg(videocore_software)]
+    mod software {
+        use backend::software::SoftwareBackend;
+
+        use dispmanx::{ update_start,
+                        Display,
+                        ElementBuilder,
+                        Resource };
+
+        use image::{ ImageType,
+                     Rect };
+
+        use record::{ Options,
+                      Rate,
+                      Recorder };
+
+        #[test]
+        pub fn record_pass() {
+            let backend = SoftwareBackend::instance();
+
+            backend.set_display_size(107, 4, 2);
+
+            let display  = Display::open(107).unwrap();
+            let resource = Resource::create(ImageType::RGB888, 1, 1).unwrap();
+
+            resource.write(&[255, 255, 255], 3, &Rect::new(0, 0, 1, 1)).unwrap();
+
+            let mut update = update_start(0).unwrap();
+
+            update.element_add(&display,
+                               ElementBuilder::new().dest(Rect::new(0, 0, 4, 2)).source(&resource))
+                  .unwrap();
+
+            update.submit_sync().unwrap();
+
+            let options = Options {
+                width: Some(2),
+                rate:  Rate::Fixed(1000, 1),
+                ..Options::new()
+            };
+
+            let mut recorder = Recorder::new(107, Vec::new(), &options).unwrap();
+
+            assert_eq!(recorder.header().aspect, (2, 1));
+
+            recorder.record_frame().unwrap();
+            recorder.record_frame().unwrap();
+
+            let frames = recorder.frames();
+
+            assert_eq!(frames, 2 + recorder.dropped());
+
+            let out = recorder.finish().unwrap();
+
+            let header = b"YUV4MPEG2 W2 H2 F1000:1 Ip A2:1 C420mpeg2 XCOLORRANGE=LIMITED\n";
+            let frame  = b"FRAME\n\xeb\xeb\xeb\xeb\x80\x80";
+
+            assert_eq!(&out[..header.len()], &header[..]);
+            assert_eq!(&out[header.len()..header.len() + frame.len()], &frame[..]);
+            assert_eq!(out.len(), header.len() + frame.len() * frames as usize);
+        }
+    }
+}
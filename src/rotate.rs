@@ -0,0 +1,162 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use libc::uint32_t;
+
+// local
+use convert::{ Error,
+               Layout };
+
+use image::ImageTransform;
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// width and height of an image once oriented
+pub fn dimensions(transform: ImageTransform, width: uint32_t,
+                  height: uint32_t) -> (uint32_t, uint32_t) {
+    if transform.transpose() { (height, width) } else { (width, height) }
+}
+
+// copies width x height pixels of a single plane raster image into dst with the orientation
+// applied, pixels under 8 bits are packed from the low bits of each byte
+pub fn rotate(src: &[u8], src_layout: Layout, dst: &mut [u8], dst_layout: Layout, width: uint32_t,
+              height: uint32_t, transform: ImageTransform) -> Result<(), Error> {
+    let type_ = src_layout.type_;
+
+    let bits = match type_.planes() {
+        [plane] if type_ == dst_layout.type_ && !type_.is_tformat() => {
+            plane.bits_per_pixel as usize
+        },
+        _ => return Err(Error::Unsupported(type_, dst_layout.type_))
+    };
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let (dst_width, dst_height) = dimensions(transform, width, height);
+
+    src_layout.check(src.len(), width, height)?;
+    dst_layout.check(dst.len(), dst_width, dst_height)?;
+
+    let (width, height) = (width as usize, height as usize);
+
+    for v in 0..dst_height as usize {
+        let row = &mut dst[v * dst_layout.pitch..];
+
+        for u in 0..dst_width as usize {
+            let (x, y) = if transform.transpose() { (v, u) } else { (u, v) };
+            let x      = if transform.flip_horizontal() { width - 1 - x } else { x };
+            let y      = if transform.flip_vertical() { height - 1 - y } else { y };
+
+            let src = &src[y * src_layout.pitch..];
+
+            if bits >= 8 {
+                let bytes = bits / 8;
+
+                row[u * bytes..(u + 1) * bytes].copy_from_slice(&src[x * bytes..(x + 1) * bytes]);
+            } else {
+                let mask  = (1 << bits) - 1;
+                let value = src[x * bits / 8] >> (x * bits % 8) & mask;
+                let shift = u * bits % 8;
+
+                row[u * bits / 8] = row[u * bits / 8] & !(mask << shift) | value << shift;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use convert::{ Error,
+                   Layout };
+
+    use image::{ ImageTransform,
+                 ImageType };
+
+    use super::{ dimensions,
+                 rotate };
+
+    #[test]
+    pub fn rotate_errors_pass() {
+        let mut out = [0; 64];
+
+        assert_eq!(rotate(&[0; 64], Layout::new(ImageType::RGB565, 4), &mut out,
+                          Layout::new(ImageType::RGB888, 6), 2, 2, ImageTransform::ROT90),
+                   Err(Error::Unsupported(ImageType::RGB565, ImageType::RGB888)));
+        assert_eq!(rotate(&[0; 64], Layout::new(ImageType::YUV420, 4), &mut out,
+                          Layout::new(ImageType::YUV420, 4), 2, 2, ImageTransform::ROT90),
+                   Err(Error::Unsupported(ImageType::YUV420, ImageType::YUV420)));
+        assert_eq!(rotate(&[0; 6], Layout::new(ImageType::_8BPP, 3), &mut out[..5],
+                          Layout::new(ImageType::_8BPP, 2), 3, 2, ImageTransform::ROT90),
+                   Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    pub fn rotate_nibbles_pass() {
+        // 3x2 at 4 bits, mirrored left to right
+        let src     = [0x21, 0x03, 0x54, 0x06];
+        let mut out = [0xff; 4];
+
+        rotate(&src, Layout::new(ImageType::_4BPP, 2), &mut out, Layout::new(ImageType::_4BPP, 2),
+               3, 2, ImageTransform::MIRROR_ROT0).unwrap();
+
+        assert_eq!(out, [0x23, 0xf1, 0x56, 0xf4]);
+    }
+
+    #[test]
+    pub fn rotate_pass() {
+        // 3x2 at 16 bits, pixels numbered in raster order
+        let src: Vec<u8> = (1..=6).flat_map(|i| [i, 0]).collect();
+
+        let expected: [(ImageTransform, [u8; 6]); 4] = [
+            (ImageTransform::ROT90, [4, 1, 5, 2, 6, 3]),
+            (ImageTransform::ROT180, [6, 5, 4, 3, 2, 1]),
+            (ImageTransform::ROT270, [3, 6, 2, 5, 1, 4]),
+            (ImageTransform::MIRROR_ROT90, [1, 4, 2, 5, 3, 6])
+        ];
+
+        for &(transform, pixels) in &expected {
+            let (width, height) = dimensions(transform, 3, 2);
+            let pitch           = width as usize * 2 + 2;
+            let mut out         = vec![0; pitch * height as usize];
+
+            rotate(&src, Layout::new(ImageType::RGB565, 6), &mut out,
+                   Layout::new(ImageType::RGB565, pitch), 3, 2, transform).unwrap();
+
+            let got: Vec<u8> = out.chunks(pitch)
+                                  .flat_map(|row| row[..width as usize * 2].to_vec())
+                                  .step_by(2)
+                                  .collect();
+
+            assert_eq!(got, pixels, "{:?}", transform);
+        }
+    }
+}
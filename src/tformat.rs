@@ -0,0 +1,299 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use libc::uint32_t;
+
+// local
+use convert::Error;
+
+use image::ImageType;
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+// TFormat groups 64 byte micro-tiles into 1k sub-tiles and 4k tiles, reversing the tile order on
+// odd tile rows, LinearTile lays the micro-tiles out in raster order and is used for images too
+// small to fill tiles
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tiling {
+    LinearTile,
+    TFormat
+}
+
+// -------------------------------------------------------------------------------------------------
+// CONSTANTS
+// -------------------------------------------------------------------------------------------------
+
+const UTILE_SIZE: usize = 64;
+const TILE_SIZE:  usize = 4096;
+
+// micro-tiles along each side of a tile and of a sub-tile
+const TILE_UTILES:    usize = 8;
+const SUBTILE_UTILES: usize = 4;
+
+// order of the four sub-tiles in a tile, indexed by 2 * row + column
+const EVEN_SUBTILES: [usize; 4] = [0, 3, 1, 2];
+const ODD_SUBTILES:  [usize; 4] = [2, 1, 3, 0];
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+// byte offset of a pixel in a tiled image of the given width
+fn address(x: usize, y: usize, width: usize, cpp: usize, tiling: Tiling) -> usize {
+    let (utile_width, utile_height) = utile(cpp);
+
+    let utile_x = x / utile_width;
+    let utile_y = y / utile_height;
+
+    let within = ((y % utile_height) * utile_width + x % utile_width) * cpp;
+
+    if tiling == Tiling::LinearTile {
+        let stride = width.div_ceil(utile_width);
+
+        return (utile_y * stride + utile_x) * UTILE_SIZE + within;
+    }
+
+    let stride = width.div_ceil(utile_width * TILE_UTILES);
+
+    let tile_x = utile_x / TILE_UTILES;
+    let tile_y = utile_y / TILE_UTILES;
+
+    let subtile = 2 * (utile_y / SUBTILE_UTILES % 2) + utile_x / SUBTILE_UTILES % 2;
+
+    let (tile, subtile) = if tile_y.is_multiple_of(2) {
+        (tile_y * stride + tile_x, EVEN_SUBTILES[subtile])
+    } else {
+        (tile_y * stride + stride - tile_x - 1, ODD_SUBTILES[subtile])
+    };
+
+    let utile = (utile_y % SUBTILE_UTILES) * SUBTILE_UTILES + utile_x % SUBTILE_UTILES;
+
+    tile * TILE_SIZE + subtile * (TILE_SIZE / 4) + utile * UTILE_SIZE + within
+}
+
+fn check(type_: ImageType, linear: usize, pitch: usize, tiled: usize, width: uint32_t,
+         height: uint32_t, tiling: Tiling) -> Result<(usize, usize), Error> {
+    let (cpp, columns) = columns(type_, width).ok_or(Error::Unsupported(type_, type_))?;
+
+    if pitch < columns * cpp {
+        return Err(Error::InvalidPitch);
+    }
+
+    if linear < pitch * (height as usize - 1) + columns * cpp
+       || tiled < size(type_, width, height, tiling).unwrap() {
+        return Err(Error::BufferTooSmall);
+    }
+
+    Ok((cpp, columns))
+}
+
+// bytes per tiled column and columns across a row. a 4 bit micro-tile of 16x8 pixels is laid out
+// like an 8 bit one of 8x8 bytes, so TF_PAL4 is tiled a byte, or two pixels, at a time
+fn columns(type_: ImageType, width: uint32_t) -> Option<(usize, usize)> {
+    if !type_.is_tformat() {
+        return None;
+    }
+
+    match (type_, type_.bits_per_pixel()?) {
+        (ImageType::TF_PAL4, _)   => Some((1, (width as usize).div_ceil(2))),
+        (_, bits @ (8 | 16 | 32)) => Some((bits as usize / 8, width as usize)),
+        _                         => None
+    }
+}
+
+// converts a tiled image into linear rows of the same format, TF_PAL4 rows are packed two pixels
+// to a byte
+pub fn decode(src: &[u8], dst: &mut [u8], dst_pitch: usize, type_: ImageType, width: uint32_t,
+              height: uint32_t, tiling: Tiling) -> Result<(), Error> {
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let (cpp, columns) = check(type_, dst.len(), dst_pitch, src.len(), width, height, tiling)?;
+
+    for y in 0..height as usize {
+        for x in 0..columns {
+            let from = address(x, y, columns, cpp, tiling);
+            let to   = y * dst_pitch + x * cpp;
+
+            dst[to..to + cpp].copy_from_slice(&src[from..from + cpp]);
+        }
+    }
+
+    Ok(())
+}
+
+// converts linear rows into a tiled image of the same format, padding is left as it was
+pub fn encode(src: &[u8], src_pitch: usize, dst: &mut [u8], type_: ImageType, width: uint32_t,
+              height: uint32_t, tiling: Tiling) -> Result<(), Error> {
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    let (cpp, columns) = check(type_, src.len(), src_pitch, dst.len(), width, height, tiling)?;
+
+    for y in 0..height as usize {
+        for x in 0..columns {
+            let from = y * src_pitch + x * cpp;
+            let to   = address(x, y, columns, cpp, tiling);
+
+            dst[to..to + cpp].copy_from_slice(&src[from..from + cpp]);
+        }
+    }
+
+    Ok(())
+}
+
+// bytes taken by a tiled image, whole tiles for TFormat and whole micro-tiles for LinearTile
+pub fn size(type_: ImageType, width: uint32_t, height: uint32_t, tiling: Tiling) -> Option<usize> {
+    let (cpp, width) = columns(type_, width)?;
+
+    let (utile_width, utile_height) = utile(cpp);
+
+    let height = height as usize;
+
+    match tiling {
+        Tiling::LinearTile => {
+            Some(width.div_ceil(utile_width) * height.div_ceil(utile_height) * UTILE_SIZE)
+        },
+        Tiling::TFormat    => {
+            Some(width.div_ceil(utile_width * TILE_UTILES)
+                 * height.div_ceil(utile_height * TILE_UTILES) * TILE_SIZE)
+        }
+    }
+}
+
+// the layout the gpu expects for an image, images no more than 4 micro-tiles wide or high are
+// linear tiled
+pub fn tiling(type_: ImageType, width: uint32_t, height: uint32_t) -> Option<Tiling> {
+    let (cpp, width) = columns(type_, width)?;
+
+    let (utile_width, utile_height) = utile(cpp);
+
+    if width <= SUBTILE_UTILES * utile_width
+       || height as usize <= SUBTILE_UTILES * utile_height {
+        Some(Tiling::LinearTile)
+    } else {
+        Some(Tiling::TFormat)
+    }
+}
+
+// pixels across and down a 64 byte micro-tile
+fn utile(cpp: usize) -> (usize, usize) {
+    match cpp {
+        1 => (8, 8),
+        2 => (8, 4),
+        _ => (4, 4)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use image::ImageType;
+
+    use super::{ decode,
+                 encode,
+                 size,
+                 tiling,
+                 Tiling };
+
+    // tiled offset of a single marked pixel
+    fn offset(x: usize, y: usize, width: u32, height: u32) -> usize {
+        let mut src = vec![0u8; width as usize * height as usize * 4];
+        let mut dst = vec![0u8; size(ImageType::TF_RGBA32, width, height,
+                                     Tiling::TFormat).unwrap()];
+
+        src[(y * width as usize + x) * 4] = 1;
+
+        encode(&src, width as usize * 4, &mut dst, ImageType::TF_RGBA32, width, height,
+               Tiling::TFormat).unwrap();
+
+        dst.iter().position(|&byte| byte == 1).unwrap()
+    }
+
+    #[test]
+    pub fn tformat_address_pass() {
+        // 4x4 pixel micro-tiles, sub-tiles of 16x16 and tiles of 32x32
+        assert_eq!(offset(1, 1, 64, 64), 20);
+        assert_eq!(offset(4, 0, 64, 64), 64);
+        assert_eq!(offset(0, 4, 64, 64), 256);
+        assert_eq!(offset(16, 0, 64, 64), 3 * 1024);
+        assert_eq!(offset(0, 16, 64, 64), 1024);
+        assert_eq!(offset(16, 16, 64, 64), 2 * 1024);
+        assert_eq!(offset(32, 0, 64, 64), 4096);
+
+        // odd tile rows run right to left with their own sub-tile order
+        assert_eq!(offset(32, 32, 64, 64), 2 * 4096 + 2 * 1024);
+        assert_eq!(offset(0, 32, 64, 64), 3 * 4096 + 2 * 1024);
+        assert_eq!(offset(16, 32, 64, 64), 3 * 4096 + 1024);
+    }
+
+    #[test]
+    pub fn tformat_round_trip_pass() {
+        for &(type_, bits) in &[(ImageType::TF_RGBA32, 32), (ImageType::TF_RGB565, 16),
+                                (ImageType::TF_Y8, 8), (ImageType::TF_PAL4, 4)] {
+            for &(width, height) in &[(70u32, 75u32), (5, 3), (129, 1)] {
+                for &tiling in &[Tiling::TFormat, Tiling::LinearTile] {
+                    let row   = (width as usize * bits).div_ceil(8);
+                    let pitch = row + 3;
+
+                    let src: Vec<u8> = (0..pitch * height as usize).map(|i| (i * 7 % 251) as u8)
+                                                                   .collect();
+
+                    let mut tiled = vec![0; size(type_, width, height, tiling).unwrap()];
+                    let mut back  = vec![0; src.len()];
+
+                    encode(&src, pitch, &mut tiled, type_, width, height, tiling).unwrap();
+                    decode(&tiled, &mut back, pitch, type_, width, height, tiling).unwrap();
+
+                    for y in 0..height as usize {
+                        let row = y * pitch..y * pitch + row;
+
+                        assert_eq!(back[row.clone()], src[row]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn tformat_size_pass() {
+        assert_eq!(size(ImageType::TF_RGBA32, 33, 32, Tiling::TFormat), Some(2 * 4096));
+        assert_eq!(size(ImageType::TF_RGB565, 64, 33, Tiling::TFormat), Some(2 * 4096));
+        assert_eq!(size(ImageType::TF_BYTE, 65, 64, Tiling::TFormat), Some(2 * 4096));
+        assert_eq!(size(ImageType::TF_PAL4, 129, 64, Tiling::TFormat), Some(2 * 4096));
+        assert_eq!(size(ImageType::TF_PAL4, 17, 8, Tiling::LinearTile), Some(2 * 64));
+        assert_eq!(size(ImageType::TF_RGBA32, 5, 4, Tiling::LinearTile), Some(2 * 64));
+        assert_eq!(size(ImageType::RGBA32, 5, 4, Tiling::LinearTile), None);
+        assert_eq!(size(ImageType::TF_ETC1, 8, 8, Tiling::TFormat), None);
+
+        assert_eq!(tiling(ImageType::TF_RGBA32, 16, 64), Some(Tiling::LinearTile));
+        assert_eq!(tiling(ImageType::TF_RGBA32, 17, 17), Some(Tiling::TFormat));
+    }
+}
@@ -0,0 +1,663 @@
+// +-----------------------------------------------------------------------------------------------+
+// | Copyright 2015 Sean Kerr                                                                      |
+// |                                                                                               |
+// | Licensed under the Apache License, Version 2.0 (the "License");                               |
+// | you may not use this file except in compliance with the License.                              |
+// | You may obtain a copy of the License Author                                                   |
+// |                                                                                               |
+// |  http://www.apache.org/licenses/LICENSE-2.0                                                   |
+// |                                                                                               |
+// | Unless required by applicable law or agreed to in writing, software                           |
+// | distributed under the License is distributed on an "AS IS" BASIS,                             |
+// | WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.                      |
+// | See the License for the specific language governing permissions and                           |
+// | limitations under the License.                                                                |
+// +-----------------------------------------------------------------------------------------------+
+// | Author: Sean Kerr <sean@metatomic.io>                                                         |
+// +-----------------------------------------------------------------------------------------------+
+
+// -------------------------------------------------------------------------------------------------
+// DEPENDENCIES
+// -------------------------------------------------------------------------------------------------
+
+// system
+use libc::uint32_t;
+
+// local
+use convert::{ self,
+               Error,
+               Layout };
+
+use image::ImageType;
+
+// -------------------------------------------------------------------------------------------------
+// ENUMS
+// -------------------------------------------------------------------------------------------------
+
+// where subsampled chroma sits relative to the luma samples it covers, Left is horizontally
+// co-sited and vertically centred as in mpeg-2 and h.264, Center is centred both ways as in jpeg
+// and TopLeft is co-sited both ways
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChromaSiting {
+    Center,
+    Left,
+    TopLeft
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Matrix {
+    Bt601,
+    Bt709
+}
+
+// Full uses 0-255 for every channel, Limited uses 16-235 for luma and 16-240 for chroma
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Range {
+    Full,
+    Limited
+}
+
+// -------------------------------------------------------------------------------------------------
+// STRUCTS
+// -------------------------------------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Colorspace {
+    pub matrix: Matrix,
+    pub range:  Range,
+    pub siting: ChromaSiting
+}
+
+// unpacked samples, chroma at its subsampled size
+struct Planes {
+    y:             Vec<f32>,
+    u:             Vec<f32>,
+    v:             Vec<f32>,
+    chroma_width:  usize,
+    chroma_height: usize
+}
+
+// -------------------------------------------------------------------------------------------------
+// IMPLEMENTATIONS
+// -------------------------------------------------------------------------------------------------
+
+impl Colorspace {
+    pub fn new(matrix: Matrix, range: Range, siting: ChromaSiting) -> Colorspace {
+        Colorspace {
+            matrix,
+            range,
+            siting
+        }
+    }
+
+    // red and blue weights of the matrix
+    fn coefficients(&self) -> (f32, f32) {
+        match self.matrix {
+            Matrix::Bt601 => (0.299, 0.114),
+            Matrix::Bt709 => (0.2126, 0.0722)
+        }
+    }
+
+    // luma and chroma positions of the first chroma sample in luma samples, for a subsampling
+    // factor of 2
+    fn offsets(&self) -> (f32, f32) {
+        match self.siting {
+            ChromaSiting::Center  => (0.5, 0.5),
+            ChromaSiting::Left    => (0.0, 0.5),
+            ChromaSiting::TopLeft => (0.0, 0.0)
+        }
+    }
+
+    fn rgb(&self, y: f32, u: f32, v: f32) -> [u8; 4] {
+        let (kr, kb)               = self.coefficients();
+        let (offset, luma, chroma) = self.scale();
+
+        let y = (y - offset) / luma;
+        let u = (u - 128.0) / chroma;
+        let v = (v - 128.0) / chroma;
+
+        let r = y + 2.0 * (1.0 - kr) * v;
+        let b = y + 2.0 * (1.0 - kb) * u;
+        let g = (y - kr * r - kb * b) / (1.0 - kr - kb);
+
+        [to_u8(r * 255.0), to_u8(g * 255.0), to_u8(b * 255.0), 255]
+    }
+
+    // luma offset, luma scale and chroma scale in 8 bit codes
+    fn scale(&self) -> (f32, f32, f32) {
+        match self.range {
+            Range::Full    => (0.0, 255.0, 255.0),
+            Range::Limited => (16.0, 219.0, 224.0)
+        }
+    }
+
+    fn yuv(&self, pixel: [u8; 4]) -> (f32, f32, f32) {
+        let (kr, kb)               = self.coefficients();
+        let (offset, luma, chroma) = self.scale();
+
+        let r = pixel[0] as f32 / 255.0;
+        let g = pixel[1] as f32 / 255.0;
+        let b = pixel[2] as f32 / 255.0;
+
+        let y = kr * r + (1.0 - kr - kb) * g + kb * b;
+        let u = (b - y) / (2.0 * (1.0 - kb));
+        let v = (r - y) / (2.0 * (1.0 - kr));
+
+        (offset + luma * y, 128.0 + chroma * u, 128.0 + chroma * v)
+    }
+}
+
+impl Default for Colorspace {
+    fn default() -> Colorspace {
+        Colorspace::new(Matrix::Bt601, Range::Limited, ChromaSiting::Left)
+    }
+}
+
+// -------------------------------------------------------------------------------------------------
+// FUNCTIONS
+// -------------------------------------------------------------------------------------------------
+
+fn check_size(len: usize, pitch: usize, row: usize, rows: usize) -> Result<(), Error> {
+    if pitch < row {
+        return Err(Error::InvalidPitch);
+    }
+
+    if len < pitch * (rows - 1) + row {
+        return Err(Error::BufferTooSmall);
+    }
+
+    Ok(())
+}
+
+// filters full resolution chroma down to the subsampled grid with a tent centred on each chroma
+// sample, weights falling outside the image are dropped so odd edges average what is there
+fn downsample(plane: &[f32], width: usize, height: usize, h_subsampling: usize,
+              v_subsampling: usize, h_offset: f32, v_offset: f32) -> Vec<f32> {
+    let chroma_width  = width.div_ceil(h_subsampling);
+    let chroma_height = height.div_ceil(v_subsampling);
+
+    let h_taps = taps(width, chroma_width, h_subsampling, h_offset);
+    let v_taps = taps(height, chroma_height, v_subsampling, v_offset);
+
+    let mut rows = vec![0.0; chroma_width * height];
+
+    for y in 0..height {
+        for (cx, taps) in h_taps.iter().enumerate() {
+            rows[y * chroma_width + cx] = taps.iter().map(|&(x, w)| plane[y * width + x] * w).sum();
+        }
+    }
+
+    let mut out = vec![0.0; chroma_width * chroma_height];
+
+    for (cy, taps) in v_taps.iter().enumerate() {
+        for cx in 0..chroma_width {
+            out[cy * chroma_width + cx] = taps.iter().map(|&(y, w)| rows[y * chroma_width + cx] * w)
+                                              .sum();
+        }
+    }
+
+    out
+}
+
+// converts width x height pixels of an rgb format supported by convert into one of the yuv
+// layouts, chroma is filtered down from every pixel it covers
+pub fn from_rgb(src: &[u8], src_layout: Layout, dst: &mut [u8], dst_layout: Layout,
+                width: uint32_t, height: uint32_t, colorspace: Colorspace) -> Result<(), Error> {
+    if !convert::is_supported(src_layout.type_) || !is_supported(dst_layout.type_) {
+        return Err(Error::Unsupported(src_layout.type_, dst_layout.type_));
+    }
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    src_layout.check(src.len(), width, height)?;
+
+    let (width, height) = (width as usize, height as usize);
+
+    let mut y = Vec::with_capacity(width * height);
+    let mut u = Vec::with_capacity(width * height);
+    let mut v = Vec::with_capacity(width * height);
+
+    for row in 0..height {
+        let row = &src[row * src_layout.pitch..];
+
+        for x in 0..width {
+            let (luma, cb, cr) = colorspace.yuv(convert::read_pixel(src_layout.type_, row, x));
+
+            y.push(luma);
+            u.push(cb);
+            v.push(cr);
+        }
+    }
+
+    let (h_subsampling, v_subsampling) = subsampling(dst_layout.type_);
+    let (h_offset, v_offset)           = colorspace.offsets();
+
+    let u = downsample(&u, width, height, h_subsampling, v_subsampling, h_offset, v_offset);
+    let v = downsample(&v, width, height, h_subsampling, v_subsampling, h_offset, v_offset);
+
+    let planes = Planes {
+        y,
+        u,
+        v,
+        chroma_width:  width.div_ceil(h_subsampling),
+        chroma_height: height.div_ceil(v_subsampling)
+    };
+
+    write_planes(dst, dst_layout, width, height, &planes)
+}
+
+fn interpolate(plane: &[f32], width: usize, (x, x_weight): (usize, f32),
+               (y, y_weight): (usize, f32)) -> f32 {
+    let height = plane.len() / width;
+
+    let x1 = (x + 1).min(width - 1);
+    let y1 = (y + 1).min(height - 1);
+
+    let top    = plane[y * width + x] * (1.0 - x_weight) + plane[y * width + x1] * x_weight;
+    let bottom = plane[y1 * width + x] * (1.0 - x_weight) + plane[y1 * width + x1] * x_weight;
+
+    top * (1.0 - y_weight) + bottom * y_weight
+}
+
+pub fn is_supported(type_: ImageType) -> bool {
+    matches!(type_, ImageType::YUV420 | ImageType::YUV420SP | ImageType::YUV422PLANAR
+                    | ImageType::YUV422YUYV | ImageType::YUV422YVYU | ImageType::YUV422UYVY
+                    | ImageType::YUV422VYUY | ImageType::YUV444PLANAR)
+}
+
+// byte offsets of Y0, U, Y1 and V within a packed 4:2:2 macropixel
+fn macropixel(type_: ImageType) -> Option<[usize; 4]> {
+    match type_ {
+        ImageType::YUV422YUYV => Some([0, 1, 2, 3]),
+        ImageType::YUV422YVYU => Some([0, 3, 2, 1]),
+        ImageType::YUV422UYVY => Some([1, 0, 3, 2]),
+        ImageType::YUV422VYUY => Some([1, 2, 3, 0]),
+        _                     => None
+    }
+}
+
+// offsets of the U plane and, unless U and V are interleaved, the V plane, planes follow one
+// another at the pitches ImageType::plane_pitch gives
+fn plane_offsets(len: usize, layout: Layout, width: usize,
+                 height: usize) -> Result<(usize, Option<usize>), Error> {
+    let (h_subsampling, v_subsampling) = subsampling(layout.type_);
+
+    let chroma_width  = width.div_ceil(h_subsampling);
+    let chroma_height = height.div_ceil(v_subsampling);
+
+    let pitch = layout.type_.plane_pitch(1, layout.pitch as uint32_t).unwrap() as usize;
+
+    check_size(len, layout.pitch, width, height)?;
+
+    let u = layout.pitch * height;
+
+    if layout.type_ == ImageType::YUV420SP {
+        check_size(len.saturating_sub(u), pitch, chroma_width * 2, chroma_height)?;
+
+        return Ok((u, None));
+    }
+
+    let v = u + pitch * chroma_height;
+
+    check_size(len.saturating_sub(v), pitch, chroma_width, chroma_height)?;
+
+    Ok((u, Some(v)))
+}
+
+// chroma sample at or before a luma sample and the weight of the one after it
+fn position(luma: usize, subsampling: usize, offset: f32, size: usize) -> (usize, f32) {
+    let offset = if subsampling == 1 { 0.0 } else { offset };
+
+    let position = ((luma as f32 - offset) / subsampling as f32).clamp(0.0, (size - 1) as f32);
+
+    (position as usize, position.fract())
+}
+
+fn read_planes(src: &[u8], layout: Layout, width: usize,
+               height: usize) -> Result<Planes, Error> {
+    let (h_subsampling, v_subsampling) = subsampling(layout.type_);
+
+    let chroma_width  = width.div_ceil(h_subsampling);
+    let chroma_height = height.div_ceil(v_subsampling);
+
+    let mut planes = Planes {
+        y: vec![0.0; width * height],
+        u: vec![0.0; chroma_width * chroma_height],
+        v: vec![0.0; chroma_width * chroma_height],
+        chroma_width,
+        chroma_height
+    };
+
+    if let Some([y0, u, y1, v]) = macropixel(layout.type_) {
+        check_size(src.len(), layout.pitch, chroma_width * 4, height)?;
+
+        for y in 0..height {
+            let row = &src[y * layout.pitch..];
+
+            for cx in 0..chroma_width {
+                let p = &row[cx * 4..cx * 4 + 4];
+
+                planes.y[y * width + cx * 2] = p[y0] as f32;
+
+                if cx * 2 + 1 < width {
+                    planes.y[y * width + cx * 2 + 1] = p[y1] as f32;
+                }
+
+                planes.u[y * chroma_width + cx] = p[u] as f32;
+                planes.v[y * chroma_width + cx] = p[v] as f32;
+            }
+        }
+
+        return Ok(planes);
+    }
+
+    let offsets = plane_offsets(src.len(), layout, width, height)?;
+
+    for y in 0..height {
+        let row = &src[y * layout.pitch..];
+
+        for (luma, &sample) in planes.y[y * width..(y + 1) * width].iter_mut().zip(row) {
+            *luma = sample as f32;
+        }
+    }
+
+    let pitch = layout.type_.plane_pitch(1, layout.pitch as uint32_t).unwrap() as usize;
+
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let (u, v) = match offsets {
+                (u, None)    => (src[u + cy * pitch + cx * 2], src[u + cy * pitch + cx * 2 + 1]),
+                (u, Some(v)) => (src[u + cy * pitch + cx], src[v + cy * pitch + cx])
+            };
+
+            planes.u[cy * chroma_width + cx] = u as f32;
+            planes.v[cy * chroma_width + cx] = v as f32;
+        }
+    }
+
+    Ok(planes)
+}
+
+fn subsampling(type_: ImageType) -> (usize, usize) {
+    match type_ {
+        ImageType::YUV420 | ImageType::YUV420SP => (2, 2),
+        ImageType::YUV444PLANAR                 => (1, 1),
+        _                                       => (2, 1)
+    }
+}
+
+// source pixels and weights contributing to each of size chroma samples
+fn taps(luma: usize, size: usize, subsampling: usize, offset: f32) -> Vec<Vec<(usize, f32)>> {
+    let offset = if subsampling == 1 { 0.0 } else { offset };
+
+    (0..size).map(|c| {
+                 let centre = (c * subsampling) as f32 + offset;
+
+                 let mut taps: Vec<(usize, f32)> =
+                     (0..luma).map(|x| (x, 1.0 - (x as f32 - centre).abs() / subsampling as f32))
+                              .filter(|&(_, w)| w > 0.0)
+                              .collect();
+
+                 let total: f32 = taps.iter().map(|&(_, w)| w).sum();
+
+                 for tap in &mut taps {
+                     tap.1 /= total;
+                 }
+
+                 taps
+             })
+             .collect()
+}
+
+// converts width x height pixels of one of the yuv layouts into an rgb format supported by
+// convert, chroma is interpolated at each pixel according to its siting
+pub fn to_rgb(src: &[u8], src_layout: Layout, dst: &mut [u8], dst_layout: Layout,
+              width: uint32_t, height: uint32_t, colorspace: Colorspace) -> Result<(), Error> {
+    if !is_supported(src_layout.type_) || !convert::is_supported(dst_layout.type_) {
+        return Err(Error::Unsupported(src_layout.type_, dst_layout.type_));
+    }
+
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+
+    dst_layout.check(dst.len(), width, height)?;
+
+    let (width, height) = (width as usize, height as usize);
+
+    let planes = read_planes(src, src_layout, width, height)?;
+
+    let (h_subsampling, v_subsampling) = subsampling(src_layout.type_);
+    let (h_offset, v_offset)           = colorspace.offsets();
+
+    for y in 0..height {
+        let row = &mut dst[y * dst_layout.pitch..];
+
+        // fractional chroma row and column each pixel falls on
+        let cy = position(y, v_subsampling, v_offset, planes.chroma_height);
+
+        for x in 0..width {
+            let cx = position(x, h_subsampling, h_offset, planes.chroma_width);
+
+            let u = interpolate(&planes.u, planes.chroma_width, cx, cy);
+            let v = interpolate(&planes.v, planes.chroma_width, cx, cy);
+
+            convert::write_pixel(dst_layout.type_, row, x,
+                                 colorspace.rgb(planes.y[y * width + x], u, v));
+        }
+    }
+
+    Ok(())
+}
+
+fn to_u8(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+fn write_planes(dst: &mut [u8], layout: Layout, width: usize, height: usize,
+                planes: &Planes) -> Result<(), Error> {
+    let chroma_width  = planes.chroma_width;
+    let chroma_height = planes.chroma_height;
+
+    if let Some([y0, u, y1, v]) = macropixel(layout.type_) {
+        check_size(dst.len(), layout.pitch, chroma_width * 4, height)?;
+
+        for y in 0..height {
+            let row = &mut dst[y * layout.pitch..];
+
+            for cx in 0..chroma_width {
+                let p = &mut row[cx * 4..cx * 4 + 4];
+
+                // an odd last pixel repeats its luma into the padding
+                let x1 = (cx * 2 + 1).min(width - 1);
+
+                p[y0] = to_u8(planes.y[y * width + cx * 2]);
+                p[y1] = to_u8(planes.y[y * width + x1]);
+                p[u]  = to_u8(planes.u[y * chroma_width + cx]);
+                p[v]  = to_u8(planes.v[y * chroma_width + cx]);
+            }
+        }
+
+        return Ok(());
+    }
+
+    let offsets = plane_offsets(dst.len(), layout, width, height)?;
+
+    for y in 0..height {
+        let row = &mut dst[y * layout.pitch..];
+
+        for (sample, &luma) in row.iter_mut().zip(&planes.y[y * width..(y + 1) * width]) {
+            *sample = to_u8(luma);
+        }
+    }
+
+    let pitch = layout.type_.plane_pitch(1, layout.pitch as uint32_t).unwrap() as usize;
+
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let u = to_u8(planes.u[cy * chroma_width + cx]);
+            let v = to_u8(planes.v[cy * chroma_width + cx]);
+
+            match offsets {
+                (offset, None) => {
+                    dst[offset + cy * pitch + cx * 2]     = u;
+                    dst[offset + cy * pitch + cx * 2 + 1] = v;
+                },
+                (u_offset, Some(v_offset)) => {
+                    dst[u_offset + cy * pitch + cx] = u;
+                    dst[v_offset + cy * pitch + cx] = v;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// -------------------------------------------------------------------------------------------------
+// TESTS
+// -------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+    use convert::{ Error,
+                   Layout };
+
+    use image::ImageType;
+
+    use super::{ from_rgb,
+                 to_rgb,
+                 ChromaSiting,
+                 Colorspace,
+                 Matrix,
+                 Range };
+
+    const LAYOUTS: [ImageType; 8] = [ImageType::YUV420, ImageType::YUV420SP,
+                                     ImageType::YUV422PLANAR, ImageType::YUV422YUYV,
+                                     ImageType::YUV422YVYU, ImageType::YUV422UYVY,
+                                     ImageType::YUV422VYUY, ImageType::YUV444PLANAR];
+
+    fn yuv(pixel: [u8; 3], colorspace: Colorspace) -> [u8; 3] {
+        let mut out = [0; 3];
+
+        from_rgb(&pixel, Layout::new(ImageType::RGB888, 3), &mut out,
+                 Layout::new(ImageType::YUV444PLANAR, 1), 1, 1, colorspace).unwrap();
+
+        out
+    }
+
+    #[test]
+    pub fn yuv_error_pass() {
+        let mut dst = [0; 64];
+
+        assert_eq!(to_rgb(&[0; 64], Layout::new(ImageType::RGB888, 12), &mut dst,
+                          Layout::new(ImageType::RGBA32, 16), 4, 4, Colorspace::default()),
+                   Err(Error::Unsupported(ImageType::RGB888, ImageType::RGBA32)));
+
+        // 4x4 YUV420 needs 16 bytes of luma and two 4 byte chroma planes
+        assert_eq!(to_rgb(&[0; 23], Layout::new(ImageType::YUV420, 4), &mut dst,
+                          Layout::new(ImageType::RGBA32, 16), 4, 4, Colorspace::default()),
+                   Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    pub fn yuv_macropixel_pass() {
+        let mut yuyv = [0; 4];
+        let mut uyvy = [0; 4];
+
+        let src = [255, 0, 0, 255, 0, 0];
+
+        from_rgb(&src, Layout::new(ImageType::RGB888, 6), &mut yuyv,
+                 Layout::new(ImageType::YUV422YUYV, 4), 2, 1, Colorspace::default()).unwrap();
+        from_rgb(&src, Layout::new(ImageType::RGB888, 6), &mut uyvy,
+                 Layout::new(ImageType::YUV422UYVY, 4), 2, 1, Colorspace::default()).unwrap();
+
+        assert_eq!(yuyv, [81, 90, 81, 240]);
+        assert_eq!(uyvy, [90, 81, 240, 81]);
+    }
+
+    #[test]
+    pub fn yuv_reference_pass() {
+        let bt601 = Colorspace::new(Matrix::Bt601, Range::Limited, ChromaSiting::Left);
+        let bt709 = Colorspace::new(Matrix::Bt709, Range::Limited, ChromaSiting::Left);
+        let jpeg  = Colorspace::new(Matrix::Bt601, Range::Full, ChromaSiting::Center);
+
+        assert_eq!(yuv([255, 255, 255], bt601), [235, 128, 128]);
+        assert_eq!(yuv([0, 0, 0], bt601), [16, 128, 128]);
+        assert_eq!(yuv([255, 0, 0], bt601), [81, 90, 240]);
+        assert_eq!(yuv([0, 255, 0], bt601), [145, 54, 34]);
+        assert_eq!(yuv([0, 0, 255], bt601), [41, 240, 110]);
+        assert_eq!(yuv([255, 0, 0], bt709), [63, 102, 240]);
+        assert_eq!(yuv([0, 0, 255], bt709), [32, 240, 118]);
+        assert_eq!(yuv([255, 0, 0], jpeg), [76, 85, 255]);
+        assert_eq!(yuv([255, 255, 255], jpeg), [255, 128, 128]);
+
+        let mut rgb = [0; 9];
+
+        to_rgb(&[235, 16, 81, 128, 128, 90, 128, 128, 240], Layout::new(ImageType::YUV444PLANAR, 3),
+               &mut rgb, Layout::new(ImageType::RGB888, 9), 3, 1, bt601).unwrap();
+
+        // the red codes are rounded so only come back within a step
+        assert_eq!(rgb, [255, 255, 255, 0, 0, 0, 254, 0, 0]);
+    }
+
+    #[test]
+    pub fn yuv_round_trip_pass() {
+        // odd sizes leave partial chroma samples on the right and bottom edges
+        let (width, height) = (5, 3);
+
+        let src: Vec<u8> = (0..width * height).flat_map(|_| vec![40, 160, 220]).collect();
+
+        for &type_ in &LAYOUTS {
+            for &matrix in &[Matrix::Bt601, Matrix::Bt709] {
+                for &range in &[Range::Full, Range::Limited] {
+                    let colorspace = Colorspace::new(matrix, range, ChromaSiting::Center);
+
+                    let pitch = type_.required_pitch(width as u32).unwrap() as usize;
+                    let size  = type_.buffer_size(width as u32, height as u32).unwrap();
+
+                    let mut yuv = vec![0; size];
+                    let mut rgb = vec![0; src.len()];
+
+                    from_rgb(&src, Layout::new(ImageType::RGB888, width * 3), &mut yuv,
+                             Layout::new(type_, pitch), width as u32, height as u32, colorspace)
+                        .unwrap();
+
+                    to_rgb(&yuv, Layout::new(type_, pitch), &mut rgb,
+                           Layout::new(ImageType::RGB888, width * 3), width as u32,
+                           height as u32, colorspace).unwrap();
+
+                    for (a, b) in src.iter().zip(rgb.iter()) {
+                        assert!((*a as i32 - *b as i32).abs() <= 2, "{:?} {:?}", type_, rgb);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn yuv_siting_pass() {
+        let red  = yuv([255, 0, 0], Colorspace::default());
+        let blue = yuv([0, 0, 255], Colorspace::default());
+
+        let src = [255, 0, 0, 0, 0, 255];
+
+        let chroma = |siting| {
+            let mut out = [0; 4];
+
+            from_rgb(&src, Layout::new(ImageType::RGB888, 6), &mut out,
+                     Layout::new(ImageType::YUV422YUYV, 4), 2, 1,
+                     Colorspace::new(Matrix::Bt601, Range::Limited, siting)).unwrap();
+
+            out[1]
+        };
+
+        // centred chroma averages both pixels, co-sited chroma weights the left one 2:1
+        assert_eq!(chroma(ChromaSiting::Center),
+                   ((red[1] as f32 + blue[1] as f32) / 2.0).round() as u8);
+        assert_eq!(chroma(ChromaSiting::Left),
+                   ((red[1] as f32 * 2.0 + blue[1] as f32) / 3.0).round() as u8);
+    }
+}